@@ -0,0 +1,42 @@
+#!/usr/bin/env bash
+# Runs the PocketIC tests in src/integration-tests. Builds the backend wasm, then downloads the
+# PocketIC server and the ICRC-1 ledger wasm into target/integration-tests unless POCKET_IC_BIN
+# and ICRC1_LEDGER_WASM already point at local copies. Extra arguments go to `cargo test`.
+set -euo pipefail
+
+ROOT="$(cd "$(dirname "$0")/.." && pwd)"
+# The server must match the major version of the pocket-ic crate in src/integration-tests
+POCKET_IC_VERSION="${POCKET_IC_VERSION:-6.0.0}"
+# IC release the ledger wasm is taken from
+IC_VERSION="${IC_VERSION:-d87954601e4b22972899e9957e800406a0a6b929}"
+CACHE="$ROOT/target/integration-tests"
+mkdir -p "$CACHE"
+
+case "$(uname -s)" in
+    Linux) platform=linux ;;
+    Darwin) platform=darwin ;;
+    *) echo "PocketIC has no server build for $(uname -s)" >&2; exit 1 ;;
+esac
+
+if [ -z "${POCKET_IC_BIN:-}" ]; then
+    POCKET_IC_BIN="$CACHE/pocket-ic-$POCKET_IC_VERSION"
+    if [ ! -x "$POCKET_IC_BIN" ]; then
+        curl -fsSL "https://github.com/dfinity/pocketic/releases/download/$POCKET_IC_VERSION/pocket-ic-x86_64-$platform.gz" | gunzip > "$POCKET_IC_BIN.part"
+        chmod +x "$POCKET_IC_BIN.part"
+        mv "$POCKET_IC_BIN.part" "$POCKET_IC_BIN"
+    fi
+fi
+
+if [ -z "${ICRC1_LEDGER_WASM:-}" ]; then
+    ICRC1_LEDGER_WASM="$CACHE/ic-icrc1-ledger-$IC_VERSION.wasm"
+    if [ ! -f "$ICRC1_LEDGER_WASM" ]; then
+        curl -fsSL "https://download.dfinity.systems/ic/$IC_VERSION/canisters/ic-icrc1-ledger.wasm.gz" | gunzip > "$ICRC1_LEDGER_WASM.part"
+        mv "$ICRC1_LEDGER_WASM.part" "$ICRC1_LEDGER_WASM"
+    fi
+fi
+
+cd "$ROOT"
+cargo build --target wasm32-unknown-unknown --release -p icp-music-platform-backend
+
+cd "$ROOT/src/integration-tests"
+POCKET_IC_BIN="$POCKET_IC_BIN" ICRC1_LEDGER_WASM="$ICRC1_LEDGER_WASM" cargo test "$@"
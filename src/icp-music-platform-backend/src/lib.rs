@@ -1,10 +1,24 @@
-use ic_cdk::storage;
 use candid::{CandidType, Deserialize};
+use candid::types::{Label, TypeEnv};
+use candid::types::value::{IDLArgs, IDLField, IDLValue, VariantValue};
 use std::cell::RefCell;
 use ic_cdk::api::caller;
-use candid::Principal;
+use candid::{Nat, Principal};
 use ic_stable_structures::{StableBTreeMap, DefaultMemoryImpl, storable::BoundedStorable, storable::Storable};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{reader::Reader, writer::Writer, Memory as _};
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use serde::Serialize;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Each stable structure gets its own virtual memory region.
+const UPGRADES_MEMORY_ID: MemoryId = MemoryId::new(0);
+const ASSET_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const ASSET_CHUNK_DATA_MEMORY_ID: MemoryId = MemoryId::new(3);
+const FREE_CHUNK_SLOTS_MEMORY_ID: MemoryId = MemoryId::new(4);
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Artist {
@@ -12,7 +26,6 @@ pub struct Artist {
     pub name: String,
     pub bio: String,
     pub social: Option<String>,
-    pub royalty_balance: u64,
     pub profile_image_url: Option<String>,
     pub links: Option<Vec<String>>,
     pub user_principal: Principal,
@@ -24,7 +37,7 @@ pub struct Comment {
     pub text: String,
 }
 
-#[derive(Clone, Debug, CandidType, Deserialize)]
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
 pub struct Split {
     pub id: u64, // artist id
     pub pct: u8, // percentage
@@ -35,6 +48,7 @@ pub enum TrackVisibility {
     Public,
     Private,
     InviteOnly,
+    SubscribersOnly, // members of any tier of the track's owning artists
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
@@ -44,6 +58,52 @@ pub enum TrackRole {
     Viewer,
 }
 
+impl TrackRole {
+    fn rank(&self) -> u8 {
+        match self {
+            TrackRole::Owner => 3,
+            TrackRole::Collaborator => 2,
+            TrackRole::Viewer => 1,
+        }
+    }
+
+    /// True if this role grants everything `required` does.
+    fn satisfies(&self, required: &TrackRole) -> bool {
+        self.rank() >= required.rank()
+    }
+}
+
+// --- API Errors ---
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum ApiError {
+    NotFound { resource: String },
+    Unauthorized { reason: String },
+    Suspended { reason: String },
+    RateLimited { retry_after_secs: u64 },
+    InvalidInput { field: String, reason: String },
+    Conflict { reason: String },
+    InsufficientFunds { available: u64 },
+    Ledger { reason: String },
+}
+
+impl ApiError {
+    fn not_found(resource: &str) -> Self {
+        ApiError::NotFound { resource: resource.to_string() }
+    }
+
+    fn unauthorized(reason: &str) -> Self {
+        ApiError::Unauthorized { reason: reason.to_string() }
+    }
+
+    fn invalid_input(field: &str, reason: &str) -> Self {
+        ApiError::InvalidInput { field: field.to_string(), reason: reason.to_string() }
+    }
+
+    fn conflict(reason: &str) -> Self {
+        ApiError::Conflict { reason: reason.to_string() }
+    }
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Track {
     pub id: u64,
@@ -60,8 +120,11 @@ pub struct Track {
     pub ratings: Vec<(u64, u8)>, // user_id, rating (1-5)
     pub tags: Vec<String>,
     pub genre: Option<String>,
-    pub play_count: u64, // new field for analytics
+    pub play_count: u64, // validated plays, see count_play
+    pub raw_play_count: u64, // every reported play, including rejected ones
+    pub gateway_play_count: u64, // anonymous plays started over the HTTP gateway, never validated
     pub downloadable: bool,
+    pub pricing: TrackPricing,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -77,9 +140,12 @@ pub struct TrackVersion {
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Payment {
-    pub payer: u64, // artist id or user id
+    pub payer: u64, // artist id or user id, 0 if the paying principal has no artist
     pub amount: u64, // in smallest unit (e.g., tokens)
     pub timestamp: u64,
+    pub from: Option<Principal>, // ledger account owner the tokens were pulled from
+    pub block_index: Option<u64>, // ledger block of the transfer; None for payments made before ledger integration
+    pub split_sheet: Option<u32>, // version of the split sheet the payment was divided by
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
@@ -142,6 +208,7 @@ pub enum UserRole {
     User,
     Admin,
     Moderator,
+    Banned,
 }
 
 // 1. Notifications System
@@ -219,11 +286,19 @@ pub struct TrackLicense {
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct RateLimitEntry {
     pub principal: Principal,
+    pub endpoint: String,
     pub last_call: u64,
     pub call_count: u32,
     pub window_start: u64,
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct RateLimitPolicy {
+    pub endpoint: String,
+    pub max_calls: u32, // per principal per window
+    pub window_secs: u64,
+}
+
 // --- Audit Log & Admin Actions History ---
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct AuditLogEntry {
@@ -314,41 +389,172 @@ pub struct SuspensionAppeal {
     pub notes: Option<String>,
 }
 
+/// A ban is a User suspension with extra consequences: the user's role is set to Banned, every
+/// call they make is refused and, optionally, their artists' tracks are hidden. It lasts as long
+/// as the suspension, so appeals, lifts and expiry end both.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Ban {
+    pub suspension_id: u64,
+    pub principal: Principal,
+    pub reason: String,
+    pub banned_by: Principal,
+    pub banned_at: u64,
+    pub expires_at: Option<u64>,
+    pub hide_tracks: bool,
+    /// Role restored when the ban ends; None when the principal had no profile.
+    pub previous_role: Option<UserRole>,
+    pub ended_at: Option<u64>,
+}
+
+// --- Permissions ---
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    ReviewReports,
+    ReviewModerationQueue,
+    /// Moderators may only impose time-bound suspensions up to the configured cap
+    SuspendTargets,
+    LiftSuspensions,
+    ReviewAppeals,
+    /// Admin-only
+    ManageKeywords,
+    /// Admin-only: the `delete_*_by_admin` endpoints
+    DeleteContent,
+    /// Admin-only
+    ViewAuditLog,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct RolePermissions {
+    pub role: UserRole,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct PermissionSettings {
+    pub roles: Vec<RolePermissions>,
+    pub moderator_suspension_cap_secs: u64,
+}
+
+// --- Staff Listings ---
+/// Which slice of a listing to return. Listings are newest first and `limit` is capped at
+/// `MAX_PAGE_SIZE`.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PageRequest {
+    pub offset: u64,
+    pub limit: u32,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Matching entries across all pages
+    pub total: u64,
+    pub next_offset: Option<u64>,
+}
+
+/// Timestamps are in ms; `from` is inclusive and `to` exclusive. Unset fields match everything.
+#[derive(Clone, Debug, CandidType, Deserialize, Default)]
+pub struct ReportFilter {
+    pub status: Option<ReportStatus>,
+    pub target_type: Option<ReportTargetType>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Default)]
+pub struct ModerationFilter {
+    pub status: Option<ModerationStatus>,
+    pub target_type: Option<ModerationTargetType>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Default)]
+pub struct SuspensionFilter {
+    pub status: Option<SuspensionStatus>,
+    pub target_type: Option<SuspensionTargetType>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+/// `target_type` matches the type of the appealed suspension's target.
+#[derive(Clone, Debug, CandidType, Deserialize, Default)]
+pub struct AppealFilter {
+    pub status: Option<AppealStatus>,
+    pub target_type: Option<SuspensionTargetType>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Default)]
+pub struct AuditLogFilter {
+    pub admin: Option<Principal>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Default)]
+pub struct UserFilter {
+    pub role: Option<UserRole>,
+}
+
+/// A report as its reporter sees it, without the reviewer's identity or notes.
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct MyReport {
+    pub id: u64,
+    pub target_type: ReportTargetType,
+    pub target_id: String,
+    pub reason: String,
+    pub status: ReportStatus,
+    pub created_at: u64,
+    pub reviewed_at: Option<u64>,
+}
+
 // Storage for workflow management
 thread_local! {
-    static ARTISTS: RefCell<Vec<Artist>> = RefCell::new(Vec::new());
-    static TRACKS: RefCell<Vec<Track>> = RefCell::new(Vec::new());
-    static ARTIST_ID: RefCell<u64> = RefCell::new(1);
-    static TRACK_ID: RefCell<u64> = RefCell::new(1);
-    static TRACK_VERSIONS: RefCell<Vec<(u64, Vec<TrackVersion>)>> = RefCell::new(Vec::new()); // track_id -> versions
-    static ACTIVITY_LOG: RefCell<Vec<Activity>> = RefCell::new(Vec::new());
-    static COLLAB_REQUESTS: RefCell<Vec<CollabRequest>> = RefCell::new(Vec::new());
-    static COLLAB_REQUEST_ID: RefCell<u64> = RefCell::new(1);
-    static TASKS: RefCell<Vec<Task>> = RefCell::new(Vec::new());
-    static TASK_ID: RefCell<u64> = RefCell::new(1);
-    static USERS: RefCell<Vec<User>> = RefCell::new(Vec::new());
-    static USER_ACTIVITY_LOG: RefCell<Vec<UserActivity>> = RefCell::new(Vec::new());
-    static NOTIFICATIONS: RefCell<Vec<Notification>> = RefCell::new(Vec::new());
-    static NOTIFICATION_ID: RefCell<u64> = RefCell::new(1);
-    static FOLLOWED_ARTISTS: RefCell<Vec<(Principal, Vec<Principal>)>> = RefCell::new(Vec::new());
-    static FOLLOWED_TRACKS: RefCell<Vec<(Principal, Vec<u64>)>> = RefCell::new(Vec::new());
-    static PLAYLISTS: RefCell<Vec<Playlist>> = RefCell::new(Vec::new());
-    static PLAYLIST_ID: RefCell<u64> = RefCell::new(1);
-    static PLAY_COUNTS: RefCell<Vec<PlayDownloadCount>> = RefCell::new(Vec::new());
-    static MESSAGES: RefCell<Vec<Message>> = RefCell::new(Vec::new());
-    static MESSAGE_ID: RefCell<u64> = RefCell::new(1);
-    static REPORTS: RefCell<Vec<Report>> = RefCell::new(Vec::new());
-    static REPORT_ID: RefCell<u64> = RefCell::new(1);
-    static TRACK_LICENSES: RefCell<Vec<TrackLicense>> = RefCell::new(Vec::new());
-    static RATE_LIMITS: RefCell<Vec<RateLimitEntry>> = RefCell::new(Vec::new());
-    static AUDIT_LOG: RefCell<Vec<AuditLogEntry>> = RefCell::new(Vec::new());
-    static AUDIT_LOG_ID: RefCell<u64> = RefCell::new(1);
-    static MODERATION_QUEUE: RefCell<Vec<ModerationQueueItem>> = RefCell::new(Vec::new());
-    static MODERATION_QUEUE_ID: RefCell<u64> = RefCell::new(1);
-    static SUSPENSIONS: RefCell<Vec<Suspension>> = RefCell::new(Vec::new());
-    static SUSPENSION_ID: RefCell<u64> = RefCell::new(1);
-    static SUSPENSION_APPEALS: RefCell<Vec<SuspensionAppeal>> = RefCell::new(Vec::new());
-    static SUSPENSION_APPEAL_ID: RefCell<u64> = RefCell::new(1);
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static ARTISTS: RefCell<Vec<Artist>> = const { RefCell::new(Vec::new()) };
+    static TRACKS: RefCell<Vec<Track>> = const { RefCell::new(Vec::new()) };
+    static ARTIST_ID: RefCell<u64> = const { RefCell::new(1) };
+    static TRACK_ID: RefCell<u64> = const { RefCell::new(1) };
+    static TRACK_VERSIONS: RefCell<Vec<(u64, Vec<TrackVersion>)>> = const { RefCell::new(Vec::new()) }; // track_id -> versions
+    static ACTIVITY_LOG: RefCell<Vec<Activity>> = const { RefCell::new(Vec::new()) };
+    static COLLAB_REQUESTS: RefCell<Vec<CollabRequest>> = const { RefCell::new(Vec::new()) };
+    static COLLAB_REQUEST_ID: RefCell<u64> = const { RefCell::new(1) };
+    static TASKS: RefCell<Vec<Task>> = const { RefCell::new(Vec::new()) };
+    static TASK_ID: RefCell<u64> = const { RefCell::new(1) };
+    static USERS: RefCell<BTreeMap<Principal, User>> = const { RefCell::new(BTreeMap::new()) };
+    static ARTISTS_BY_PRINCIPAL: RefCell<BTreeMap<Principal, Vec<u64>>> = const { RefCell::new(BTreeMap::new()) }; // derived, see rebuild_identity_index
+    static USER_ACTIVITY_LOG: RefCell<Vec<UserActivity>> = const { RefCell::new(Vec::new()) };
+    static NOTIFICATIONS: RefCell<Vec<Notification>> = const { RefCell::new(Vec::new()) };
+    static NOTIFICATION_ID: RefCell<u64> = const { RefCell::new(1) };
+    static FOLLOWED_ARTISTS: RefCell<Vec<(Principal, Vec<Principal>)>> = const { RefCell::new(Vec::new()) };
+    static FOLLOWED_TRACKS: RefCell<Vec<(Principal, Vec<u64>)>> = const { RefCell::new(Vec::new()) };
+    static PLAYLISTS: RefCell<Vec<Playlist>> = const { RefCell::new(Vec::new()) };
+    static PLAYLIST_ID: RefCell<u64> = const { RefCell::new(1) };
+    static PLAY_COUNTS: RefCell<Vec<PlayDownloadCount>> = const { RefCell::new(Vec::new()) };
+    static MESSAGES: RefCell<Vec<Message>> = const { RefCell::new(Vec::new()) };
+    static MESSAGE_ID: RefCell<u64> = const { RefCell::new(1) };
+    static REPORTS: RefCell<Vec<Report>> = const { RefCell::new(Vec::new()) };
+    static REPORT_ID: RefCell<u64> = const { RefCell::new(1) };
+    static TRACK_LICENSES: RefCell<Vec<TrackLicense>> = const { RefCell::new(Vec::new()) };
+    static RATE_LIMITS: RefCell<Vec<RateLimitEntry>> = const { RefCell::new(Vec::new()) };
+    static RATE_LIMIT_POLICIES: RefCell<Vec<RateLimitPolicy>> = RefCell::new(default_rate_limit_policies());
+    static AUDIT_LOG: RefCell<Vec<AuditLogEntry>> = const { RefCell::new(Vec::new()) };
+    static AUDIT_LOG_ID: RefCell<u64> = const { RefCell::new(1) };
+    static MODERATION_QUEUE: RefCell<Vec<ModerationQueueItem>> = const { RefCell::new(Vec::new()) };
+    static MODERATION_QUEUE_ID: RefCell<u64> = const { RefCell::new(1) };
+    static SUSPENSIONS: RefCell<Vec<Suspension>> = const { RefCell::new(Vec::new()) };
+    static SUSPENSION_ID: RefCell<u64> = const { RefCell::new(1) };
+    static SUSPENSION_APPEALS: RefCell<Vec<SuspensionAppeal>> = const { RefCell::new(Vec::new()) };
+    static SUSPENSION_APPEAL_ID: RefCell<u64> = const { RefCell::new(1) };
+    static BANS: RefCell<Vec<Ban>> = const { RefCell::new(Vec::new()) };
+    static ROLE_PERMISSIONS: RefCell<Vec<RolePermissions>> = RefCell::new(default_role_permissions());
+    static MODERATOR_SUSPENSION_CAP_SECS: RefCell<u64> = const { RefCell::new(DEFAULT_MODERATOR_SUSPENSION_CAP_SECS) };
     static BANNED_KEYWORDS: RefCell<Vec<String>> = RefCell::new(vec![
         "spam".to_string(),
         "scam".to_string(),
@@ -358,18 +564,50 @@ thread_local! {
     ]);
     
     // Workflow management storage
-    static WORKFLOW_STEPS: RefCell<Vec<WorkflowStep>> = RefCell::new(Vec::new());
-    static COLLABORATION_SESSIONS: RefCell<Vec<CollaborationSession>> = RefCell::new(Vec::new());
-    static WORKFLOW_TEMPLATES: RefCell<Vec<WorkflowTemplate>> = RefCell::new(Vec::new());
-    static WORKFLOW_STEP_ID: RefCell<u64> = RefCell::new(1);
-    static SESSION_ID: RefCell<u64> = RefCell::new(1);
-    static TEMPLATE_ID: RefCell<u64> = RefCell::new(1);
-    static TRACK_FILES: RefCell<StableBTreeMap<u64, TrackFile, DefaultMemoryImpl>> = RefCell::new(StableBTreeMap::new(DefaultMemoryImpl::default()));
+    static WORKFLOW_STEPS: RefCell<Vec<WorkflowStep>> = const { RefCell::new(Vec::new()) };
+    static COLLABORATION_SESSIONS: RefCell<Vec<CollaborationSession>> = const { RefCell::new(Vec::new()) };
+    static WORKFLOW_TEMPLATES: RefCell<Vec<WorkflowTemplate>> = const { RefCell::new(Vec::new()) };
+    static WORKFLOW_STEP_ID: RefCell<u64> = const { RefCell::new(1) };
+    static SESSION_ID: RefCell<u64> = const { RefCell::new(1) };
+    static TEMPLATE_ID: RefCell<u64> = const { RefCell::new(1) };
+    static MIGRATION_HISTORY: RefCell<Vec<MigrationRecord>> = const { RefCell::new(Vec::new()) };
+    static TRACK_ASSETS: RefCell<Vec<TrackAsset>> = const { RefCell::new(Vec::new()) };
+    static ASSET_ID: RefCell<u64> = const { RefCell::new(1) };
+    static UPLOAD_SESSIONS: RefCell<Vec<UploadSession>> = const { RefCell::new(Vec::new()) };
+    static UPLOAD_SESSION_ID: RefCell<u64> = const { RefCell::new(1) };
+    static LEDGER_CANISTER: RefCell<Option<Principal>> = const { RefCell::new(None) };
+    static JOURNAL_ENTRIES: RefCell<Vec<JournalEntry>> = const { RefCell::new(Vec::new()) }; // append-only
+    static JOURNAL_ENTRY_ID: RefCell<u64> = const { RefCell::new(1) };
+    static JOURNAL_TRANSACTION_ID: RefCell<u64> = const { RefCell::new(1) };
+    static SPLIT_PROPOSALS: RefCell<Vec<SplitProposal>> = const { RefCell::new(Vec::new()) };
+    static SPLIT_PROPOSAL_ID: RefCell<u64> = const { RefCell::new(1) };
+    static SPLIT_SHEETS: RefCell<Vec<SplitSheet>> = const { RefCell::new(Vec::new()) };
+    static FEE_SCHEDULES: RefCell<Vec<FeeSchedule>> = const { RefCell::new(Vec::new()) };
+    static ADVANCES: RefCell<Vec<Advance>> = const { RefCell::new(Vec::new()) };
+    static ADVANCE_ID: RefCell<u64> = const { RefCell::new(1) };
+    static MONTHLY_STATEMENTS: RefCell<Vec<MonthlyStatement>> = const { RefCell::new(Vec::new()) };
+    static MONTHLY_STATEMENT_ID: RefCell<u64> = const { RefCell::new(1) };
+    static STATEMENTS_GENERATED_THROUGH: RefCell<u64> = const { RefCell::new(0) }; // end of the last generated month, ms
+    static PENDING_PURCHASES: RefCell<Vec<(Principal, u64)>> = const { RefCell::new(Vec::new()) }; // not persisted
+    static ENTITLEMENTS: RefCell<Vec<Entitlement>> = const { RefCell::new(Vec::new()) };
+    static MEMBERSHIP_TIERS: RefCell<Vec<MembershipTier>> = const { RefCell::new(Vec::new()) };
+    static MEMBERSHIP_TIER_ID: RefCell<u64> = const { RefCell::new(1) };
+    static SUBSCRIPTIONS: RefCell<Vec<Subscription>> = const { RefCell::new(Vec::new()) };
+    static SUBSCRIPTION_ID: RefCell<u64> = const { RefCell::new(1) };
+    static GATEWAY_PLAY_WINDOW: RefCell<(u64, u64)> = const { RefCell::new((0, 0)) }; // window start, plays; not persisted
+    static RENEWALS_IN_FLIGHT: RefCell<bool> = const { RefCell::new(false) }; // not persisted; an interrupted run just runs again
+    static RENEWAL_FAILURES: RefCell<BTreeMap<u64, u32>> = const { RefCell::new(BTreeMap::new()) }; // not persisted
+    static CAMPAIGNS: RefCell<Vec<Campaign>> = const { RefCell::new(Vec::new()) };
+    static CAMPAIGN_ID: RefCell<u64> = const { RefCell::new(1) };
+    static CAMPAIGN_JOBS_IN_FLIGHT: RefCell<bool> = const { RefCell::new(false) }; // not persisted
+    static ASSET_CHUNKS: RefCell<StableBTreeMap<(u64, u32), ChunkSlot, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(ASSET_CHUNKS_MEMORY_ID)))
+    );
+    static FREE_CHUNK_SLOTS: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(FREE_CHUNK_SLOTS_MEMORY_ID)))
+    );
 }
 
-/// Max file size: 10MB
-const MAX_FILE_SIZE: usize = 10 * 1024 * 1024;
-
 #[ic_cdk::query]
 fn greet(name: String) -> String {
     format!("Hello, {}!", name)
@@ -377,11 +615,9 @@ fn greet(name: String) -> String {
 
 // Artist CRUD
 #[ic_cdk::update]
-fn register_artist(name: String, bio: String, social: Option<String>, profile_image_url: Option<String>, links: Option<Vec<String>>) -> Option<Artist> {
-    if name.trim().is_empty() {
-        return None;
-    }
-    let principal = caller();
+fn register_artist(name: String, bio: String, social: Option<String>, profile_image_url: Option<String>, links: Option<Vec<String>>) -> Result<Artist, ApiError> {
+    require_non_empty("name", &name)?;
+    let principal = active_caller()?;
     ARTISTS.with(|artists| {
         ARTIST_ID.with(|id| {
             let mut id_mut = id.borrow_mut();
@@ -390,28 +626,30 @@ fn register_artist(name: String, bio: String, social: Option<String>, profile_im
                 name: name.clone(),
                 bio: bio.clone(),
                 social: social.clone(),
-                royalty_balance: 0,
                 profile_image_url: profile_image_url.clone(),
                 links: links.clone(),
                 user_principal: principal,
             };
             artists.borrow_mut().push(artist.clone());
+            index_artist(principal, artist.id);
             *id_mut += 1;
             let now = ic_cdk::api::time() / 1_000_000;
             log_user_activity(principal, "register_artist", now, &format!("Registered artist: {}", name));
-            Some(artist)
+            Ok(artist)
         })
     })
 }
 
 #[ic_cdk::query]
-fn get_artist(id: u64) -> Option<Artist> {
+fn get_artist(id: u64) -> Result<Artist, ApiError> {
     ARTISTS.with(|artists| artists.borrow().iter().find(|a| a.id == id).cloned())
+        .ok_or_else(|| ApiError::not_found("artist"))
 }
 
 #[ic_cdk::update]
-fn update_artist(id: u64, name: String, bio: String, social: Option<String>, profile_image_url: Option<String>, links: Option<Vec<String>>) -> Option<Artist> {
+fn update_artist(id: u64, name: String, bio: String, social: Option<String>, profile_image_url: Option<String>, links: Option<Vec<String>>) -> Result<Artist, ApiError> {
     let principal = caller();
+    require_artist_owner(principal, id)?;
     ARTISTS.with(|artists| {
         let mut artists = artists.borrow_mut();
         if let Some(artist) = artists.iter_mut().find(|a| a.id == id) {
@@ -422,9 +660,9 @@ fn update_artist(id: u64, name: String, bio: String, social: Option<String>, pro
             artist.links = links.clone();
             let now = ic_cdk::api::time() / 1_000_000;
             log_user_activity(principal, "update_artist", now, &format!("Updated artist: {}", name));
-            return Some(artist.clone());
+            return Ok(artist.clone());
         }
-        None
+        Err(ApiError::not_found("artist"))
     })
 }
 
@@ -433,11 +671,28 @@ fn list_artists() -> Vec<Artist> {
     ARTISTS.with(|artists| artists.borrow().clone())
 }
 
+// Artists owned by the caller
+#[ic_cdk::query]
+fn list_my_artists() -> Vec<Artist> {
+    let ids = artist_ids_of(caller());
+    ARTISTS.with(|artists| artists.borrow().iter().filter(|a| ids.contains(&a.id)).cloned().collect())
+}
+
 // Track CRUD
 #[ic_cdk::update]
-fn create_track(title: String, description: String, contributors: Vec<u64>) -> Option<Track> {
-    if title.trim().is_empty() || description.trim().is_empty() || contributors.is_empty() {
-        return None;
+fn create_track(title: String, description: String, contributors: Vec<u64>) -> Result<Track, ApiError> {
+    require_non_empty("title", &title)?;
+    require_non_empty("description", &description)?;
+    if contributors.is_empty() {
+        return Err(ApiError::invalid_input("contributors", "at least one contributor is required"));
+    }
+    // The creator must be one of the contributing artists
+    let creator = active_caller()?;
+    if !artist_ids_of(creator).iter().any(|id| contributors.contains(id)) {
+        return Err(ApiError::unauthorized("caller must own one of the contributing artists"));
+    }
+    for id in &contributors {
+        require_not_suspended(SuspensionTargetType::Artist, &id.to_string())?;
     }
     let now = ic_cdk::api::time() / 1_000_000;
     let contributors_for_log = contributors.clone();
@@ -452,7 +707,6 @@ fn create_track(title: String, description: String, contributors: Vec<u64>) -> O
     let content_to_check = format!("{} {}", title, description);
     auto_flag_content_if_needed(ModerationTargetType::Track, track_id.to_string(), &content_to_check);
     
-    let creator = caller();
     TRACKS.with(|tracks| {
         let mut roles = vec![];
         for &cid in &contributors {
@@ -474,7 +728,10 @@ fn create_track(title: String, description: String, contributors: Vec<u64>) -> O
             tags: vec![],
             genre: None,
             play_count: 0,
+            raw_play_count: 0,
+            gateway_play_count: 0,
             downloadable: true,
+            pricing: TrackPricing::default(),
         };
         tracks.borrow_mut().push(track.clone());
         // Store initial version
@@ -495,89 +752,83 @@ fn create_track(title: String, description: String, contributors: Vec<u64>) -> O
         for &cid in &contributors_for_log {
             log_activity(cid, "create_track", now, &format!("Track {} created", track.id));
         }
-        Some(track)
+        Ok(track)
     })
 }
 
 #[ic_cdk::query]
-fn get_track(id: u64) -> Option<Track> {
-    TRACKS.with(|tracks| tracks.borrow().iter().find(|t| t.id == id).cloned())
+fn get_track(id: u64) -> Result<Track, ApiError> {
+    visible_track(id)
 }
 
+// Collaborators may edit metadata; changing contributors requires an owner
 #[ic_cdk::update]
-fn update_track(id: u64, title: String, description: String, contributors: Vec<u64>, version: u32) -> Option<Track> {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == id) {
-            track.title = title;
-            track.description = description;
-            track.contributors = contributors;
-            track.version = version;
-            return Some(track.clone());
-        }
-        None
+fn update_track(id: u64, title: String, description: String, contributors: Vec<u64>, version: u32) -> Result<Track, ApiError> {
+    let required = match find_track(id) {
+        Some(track) if track.contributors != contributors => TrackRole::Owner,
+        Some(_) => TrackRole::Collaborator,
+        None => return Err(ApiError::not_found("track")),
+    };
+    with_track_as(id, required, |track| {
+        track.title = title;
+        track.description = description;
+        track.contributors = contributors;
+        track.version = version;
+        track.clone()
     })
 }
 
 #[ic_cdk::query]
 fn list_tracks() -> Vec<Track> {
-    TRACKS.with(|tracks| tracks.borrow().clone())
-}
-
-// Add/Update splits for a track
-#[ic_cdk::update]
-fn set_track_splits(track_id: u64, splits: Vec<Split>) -> Option<Track> {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            track.splits = Some(splits);
-            return Some(track.clone());
-        }
-        None
-    })
+    visible_tracks(|_| true)
 }
 
 #[ic_cdk::query]
-fn get_track_splits(track_id: u64) -> Option<Vec<Split>> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id).and_then(|t| t.splits.clone())
-    })
+fn get_track_splits(track_id: u64) -> Result<Option<Vec<Split>>, ApiError> {
+    visible_track(track_id).map(|t| t.splits)
 }
 
 // Add a comment to a track
 #[ic_cdk::update]
-fn add_comment(track_id: u64, commenter: u64, text: String) -> Option<Track> {
+fn add_comment(track_id: u64, commenter: u64, text: String) -> Result<Track, ApiError> {
+    let principal = caller();
+    require_artist_owner(principal, commenter)?;
+    require_non_empty("text", &text)?;
+    find_track(track_id).filter(|t| can_view_track(principal, t)).ok_or_else(|| ApiError::not_found("track"))?;
     let now = ic_cdk::api::time() / 1_000_000;
+    check_rate_limit(principal, "add_comment", now)?;
     
     // Check comment for banned words
     auto_flag_content_if_needed(ModerationTargetType::Comment, format!("track_{}_comment_{}", track_id, now), &text);
     
     TRACKS.with(|tracks| {
         let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
+        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id && can_view_track(principal, t)) {
             track.comments.push(Comment { commenter, text: text.clone() });
             log_activity(commenter, "add_comment", now, &format!("Commented on track {}: {}", track_id, text));
-            return Some(track.clone());
+            return Ok(track.clone());
         }
-        None
+        Err(ApiError::not_found("track"))
     })
 }
 
 #[ic_cdk::query]
-fn list_comments(track_id: u64) -> Vec<Comment> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id).map(|t| t.comments.clone()).unwrap_or_default()
-    })
+fn list_comments(track_id: u64) -> Result<Vec<Comment>, ApiError> {
+    visible_track(track_id).map(|t| t.comments)
 }
 
 // Add a new version to a track
 #[ic_cdk::update]
-fn add_track_version(track_id: u64, title: String, description: String, contributors: Vec<u64>, change_description: Option<String>) -> Option<TrackVersion> {
+fn add_track_version(track_id: u64, title: String, description: String, contributors: Vec<u64>, change_description: Option<String>) -> Result<TrackVersion, ApiError> {
     let now = ic_cdk::api::time() / 1_000_000;
     let changer = caller();
+    // Collaborators may edit metadata; changing contributors requires an owner
+    let track = find_track(track_id).ok_or_else(|| ApiError::not_found("track"))?;
+    let required = if track.contributors != contributors { TrackRole::Owner } else { TrackRole::Collaborator };
+    require_track_role(changer, &track, required)?;
     TRACK_VERSIONS.with(|tv| {
         let mut tv = tv.borrow_mut();
-        if let Some((id, versions)) = tv.iter_mut().find(|(tid, _)| *tid == track_id) {
+        if let Some((_, versions)) = tv.iter_mut().find(|(tid, _)| *tid == track_id) {
             let new_version_num = versions.len() as u32 + 1;
             let version = TrackVersion {
                 version: new_version_num,
@@ -598,168 +849,234 @@ fn add_track_version(track_id: u64, title: String, description: String, contribu
                     track.version = new_version_num;
                 }
             });
-            Some(version)
+            Ok(version)
         } else {
-            None
+            Err(ApiError::not_found("track versions"))
         }
     })
 }
 
 #[ic_cdk::query]
-fn get_track_versions(track_id: u64) -> Vec<TrackVersion> {
-    TRACK_VERSIONS.with(|tv| {
+fn get_track_versions(track_id: u64) -> Result<Vec<TrackVersion>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(TRACK_VERSIONS.with(|tv| {
         tv.borrow().iter().find(|(id, _)| *id == track_id).map(|(_, versions)| versions.clone()).unwrap_or_default()
-    })
+    }))
 }
 
 // Search tracks by title (case-insensitive substring)
 #[ic_cdk::query]
 fn search_tracks_by_title(query: String) -> Vec<Track> {
     let q = query.to_lowercase();
-    TRACKS.with(|tracks| {
-        if q.is_empty() {
-            tracks.borrow().clone()
-        } else {
-            tracks.borrow().iter().filter(|t| t.title.to_lowercase().contains(&q)).cloned().collect()
-        }
-    })
+    visible_tracks(|t| q.is_empty() || t.title.to_lowercase().contains(&q))
 }
 
 // Search tracks by contributor (artist id)
 #[ic_cdk::query]
 fn search_tracks_by_contributor(artist_id: u64) -> Vec<Track> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().filter(|t| t.contributors.contains(&artist_id)).cloned().collect()
-    })
+    visible_tracks(|t| t.contributors.contains(&artist_id))
 }
 
 // Delete a track by id
 #[ic_cdk::update]
-fn delete_track(track_id: u64) -> bool {
-    let mut deleted = false;
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        let len_before = tracks.len();
-        tracks.retain(|t| t.id != track_id);
-        deleted = tracks.len() < len_before;
-    });
+fn delete_track(track_id: u64) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Owner, |_| ())?;
+    require_no_active_campaign(track_id)?;
+    TRACKS.with(|tracks| tracks.borrow_mut().retain(|t| t.id != track_id));
     TRACK_VERSIONS.with(|tv| {
         let mut tv = tv.borrow_mut();
         tv.retain(|(id, _)| *id != track_id);
     });
-    deleted
+    delete_track_assets(track_id);
+    Ok(())
 }
 
-// Distribute payment for a track
+// Pay for a track: pulls `amount` from the caller's ledger account (which must have approved this
+// canister via icrc2_approve) and splits it between the track's artists. Returns the ledger block index.
 #[ic_cdk::update]
-fn distribute_payment(track_id: u64, payer: u64, amount: u64, timestamp: u64) -> bool {
-    let mut distributed = false;
+async fn distribute_payment(track_id: u64, amount: u64) -> Result<u64, ApiError> {
+    if amount == 0 {
+        return Err(ApiError::invalid_input("amount", "must be greater than zero"));
+    }
+    let payer = active_caller()?;
+    let track = visible_track(track_id)?;
+    collect_track_payment(payer, &track, amount, "distribute_payment").await
+}
+
+/// Pulls `amount` from `from` on the ledger and distributes it by the track's current split sheet.
+/// Returns the ledger block index.
+async fn collect_track_payment(from: Principal, track: &Track, amount: u64, action: &str) -> Result<u64, ApiError> {
+    let track_id = track.id;
+    let sheet = current_split_sheet(track_id).ok_or_else(|| ApiError::conflict("track has no agreed royalty splits"))?;
+    let fee_bps = effective_fee_bps(track);
+    let block_index = pull_payment(from, amount, track_id.to_be_bytes().to_vec()).await?;
+    let timestamp = ic_cdk::api::time() / 1_000_000;
+    // Sheet and fee are taken from before the transfer so what was shown to the payer is what gets paid out
+    record_payment_in(Some(track_id), &sheet.splits, fee_bps, amount, block_index, timestamp);
+    let payer = artist_ids_of(from).first().copied().unwrap_or(0);
     TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            if let Some(splits) = &track.splits {
-                for split in splits {
-                    let share = amount * (split.pct as u64) / 100;
-                    // Debug: log split info
-                    ic_cdk::println!("Distributing {} to artist {} ({}%)", share, split.id, split.pct);
-                    ARTISTS.with(|artists| {
-                        let mut artists = artists.borrow_mut();
-                        if let Some(artist) = artists.iter_mut().find(|a| a.id == split.id) {
-                            artist.royalty_balance += share;
-                            ic_cdk::println!("Updated artist {} balance: {}", artist.id, artist.royalty_balance);
-                        } else {
-                            ic_cdk::println!("Artist {} not found for royalty distribution", split.id);
-                        }
-                    });
-                }
-                track.payments.push(Payment { payer, amount, timestamp });
-                distributed = true;
-                log_activity(payer, "distribute_payment", timestamp, &format!("Paid {} for track {}", amount, track_id));
-            } else {
-                ic_cdk::println!("No splits set for track {}", track_id);
-            }
-        } else {
-            ic_cdk::println!("Track {} not found for payment distribution", track_id);
+        if let Some(track) = tracks.borrow_mut().iter_mut().find(|t| t.id == track_id) {
+            track.payments.push(Payment {
+                payer,
+                amount,
+                timestamp,
+                from: Some(from),
+                block_index: Some(block_index),
+                split_sheet: Some(sheet.version),
+            });
         }
     });
-    distributed
+    log_activity(payer, action, timestamp, &format!("Paid {} for track {} (block {})", amount, track_id, block_index));
+    Ok(block_index)
 }
 
 // View artist royalty balance
 #[ic_cdk::query]
-fn get_royalty_balance(artist_id: u64) -> u64 {
-    ARTISTS.with(|artists| {
-        artists.borrow().iter().find(|a| a.id == artist_id).map(|a| a.royalty_balance).unwrap_or(0)
-    })
+fn get_royalty_balance(artist_id: u64) -> Result<u64, ApiError> {
+    get_artist(artist_id)?;
+    Ok(account_balance(&RoyaltyAccount::Artist(artist_id)))
 }
 
 // View payment history for a track
 #[ic_cdk::query]
-fn get_payment_history(track_id: u64) -> Vec<Payment> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id).map(|t| t.payments.clone()).unwrap_or_default()
-    })
+fn get_payment_history(track_id: u64) -> Result<Vec<Payment>, ApiError> {
+    visible_track(track_id).map(|t| t.payments)
 }
 
 // Set track visibility
 #[ic_cdk::update]
-fn set_track_visibility(track_id: u64, visibility: TrackVisibility) -> bool {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            track.visibility = visibility;
-            return true;
-        }
-        false
-    })
+fn set_track_visibility(track_id: u64, visibility: TrackVisibility) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Owner, |track| track.visibility = visibility)
 }
 
 // Get track visibility
 #[ic_cdk::query]
-fn get_track_visibility(track_id: u64) -> Option<TrackVisibility> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id).map(|t| t.visibility.clone())
-    })
+fn get_track_visibility(track_id: u64) -> Result<TrackVisibility, ApiError> {
+    visible_track(track_id).map(|t| t.visibility)
 }
 
 // Invite user to track
 #[ic_cdk::update]
-fn invite_user(track_id: u64, user_id: u64) -> bool {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            if !track.invited.contains(&user_id) {
-                track.invited.push(user_id);
-            }
-            return true;
+fn invite_user(track_id: u64, user_id: u64) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Owner, |track| {
+        if !track.invited.contains(&user_id) {
+            track.invited.push(user_id);
         }
-        false
     })
 }
 
 // Assign role to user
 #[ic_cdk::update]
-fn assign_role(track_id: u64, user_id: u64, role: TrackRole) -> bool {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            if let Some(r) = track.roles.iter_mut().find(|(id, _)| *id == user_id) {
-                r.1 = role;
-            } else {
-                track.roles.push((user_id, role));
+fn assign_role(track_id: u64, user_id: u64, role: TrackRole) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Owner, |track| {
+        let owners = track.roles.iter().filter(|(_, r)| *r == TrackRole::Owner).count();
+        if let Some(r) = track.roles.iter_mut().find(|(id, _)| *id == user_id) {
+            if r.1 == TrackRole::Owner && role != TrackRole::Owner && owners == 1 {
+                return Err(ApiError::conflict("a track must keep at least one owner"));
             }
-            return true;
+            r.1 = role;
+        } else {
+            track.roles.push((user_id, role));
         }
-        false
-    })
+        Ok(())
+    })?
 }
 
 // Get user role for a track
 #[ic_cdk::query]
-fn get_user_role(track_id: u64, user_id: u64) -> Option<TrackRole> {
+fn get_user_role(track_id: u64, user_id: u64) -> Result<Option<TrackRole>, ApiError> {
+    visible_track(track_id)
+        .map(|track| track.roles.iter().find(|(id, _)| *id == user_id).map(|(_, role)| role.clone()))
+}
+
+// --- Track Authorization ---
+/// The strongest role any of the caller's artists holds on `track`.
+fn track_role_of(principal: Principal, track: &Track) -> Option<TrackRole> {
+    let artist_ids = artist_ids_of(principal);
+    track.roles.iter()
+        .filter(|(id, _)| artist_ids.contains(id))
+        .map(|(_, role)| role.clone())
+        .max_by_key(|role| role.rank())
+}
+
+fn has_track_role(principal: Principal, track: &Track, required: &TrackRole) -> bool {
+    track_role_of(principal, track).map(|role| role.satisfies(required)).unwrap_or(false)
+}
+
+/// Role check for changes to a track: suspended callers and suspended tracks are refused.
+fn require_track_role(principal: Principal, track: &Track, required: TrackRole) -> Result<(), ApiError> {
+    if !has_track_role(principal, track, &required) {
+        return Err(ApiError::unauthorized(&format!("requires the {:?} role on this track", required)));
+    }
+    require_active_user(principal)?;
+    require_not_suspended(SuspensionTargetType::Track, &track.id.to_string())
+}
+
+/// Public tracks are visible to everyone, private tracks to role holders and invite-only
+/// tracks to role holders and invitees. Admins can see every track.
+fn can_view_track(principal: Principal, track: &Track) -> bool {
+    if active_ban(principal).is_some() {
+        return false;
+    }
+    // Suspended tracks, and tracks of banned users who had them hidden, disappear for everyone
+    // but their role holders and admins
+    if is_suspended(&SuspensionTargetType::Track, &track.id.to_string()) || hidden_by_ban(track) {
+        return track_role_of(principal, track).is_some() || is_admin(principal);
+    }
+    match track.visibility {
+        TrackVisibility::Public => true,
+        TrackVisibility::Private => track_role_of(principal, track).is_some() || is_admin(principal),
+        TrackVisibility::InviteOnly => {
+            track_role_of(principal, track).is_some()
+                || artist_ids_of(principal).iter().any(|id| track.invited.contains(id))
+                || is_admin(principal)
+        }
+        TrackVisibility::SubscribersOnly => {
+            track_role_of(principal, track).is_some()
+                || track.roles.iter().any(|(id, role)| *role == TrackRole::Owner && is_subscribed(principal, *id))
+                || is_admin(principal)
+        }
+    }
+}
+
+/// Looks a track up without any visibility check. Only for internal use.
+fn find_track(track_id: u64) -> Option<Track> {
+    TRACKS.with(|tracks| tracks.borrow().iter().find(|t| t.id == track_id).cloned())
+}
+
+/// Hidden tracks are reported as missing so their existence doesn't leak.
+fn visible_track(track_id: u64) -> Result<Track, ApiError> {
+    find_track(track_id).filter(|t| can_view_track(caller(), t)).ok_or_else(|| ApiError::not_found("track"))
+}
+
+fn is_track_visible(track_id: u64) -> bool {
+    let principal = caller();
+    TRACKS.with(|tracks| tracks.borrow().iter().any(|t| t.id == track_id && can_view_track(principal, t)))
+}
+
+fn require_track_visible(track_id: u64) -> Result<(), ApiError> {
+    if is_track_visible(track_id) { Ok(()) } else { Err(ApiError::not_found("track")) }
+}
+
+/// Tracks matching `filter` that the caller is allowed to see.
+fn visible_tracks(filter: impl Fn(&Track) -> bool) -> Vec<Track> {
+    let principal = caller();
+    TRACKS.with(|tracks| {
+        tracks.borrow().iter().filter(|t| filter(t) && can_view_track(principal, t)).cloned().collect()
+    })
+}
+
+/// Runs `f` on the track if the caller holds at least `required` on it.
+fn with_track_as<R>(track_id: u64, required: TrackRole, f: impl FnOnce(&mut Track) -> R) -> Result<R, ApiError> {
+    with_track_for(caller(), track_id, required, f)
+}
+
+fn with_track_for<R>(principal: Principal, track_id: u64, required: TrackRole, f: impl FnOnce(&mut Track) -> R) -> Result<R, ApiError> {
     TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id)
-            .and_then(|track| track.roles.iter().find(|(id, _)| *id == user_id).map(|(_, role)| role.clone()))
+        let mut tracks = tracks.borrow_mut();
+        let track = tracks.iter_mut().find(|t| t.id == track_id).ok_or_else(|| ApiError::not_found("track"))?;
+        require_track_role(principal, track, required)?;
+        Ok(f(track))
     })
 }
 
@@ -789,128 +1106,102 @@ fn get_recent_activity(count: u32) -> Vec<Activity> {
     ACTIVITY_LOG.with(|log| {
         let log = log.borrow();
         let len = log.len();
-        let start = if len > count as usize { len - count as usize } else { 0 };
+        let start = len.saturating_sub(count as usize);
         log[start..].to_vec()
     })
 }
 
 // Rate a track
 #[ic_cdk::update]
-fn rate_track(track_id: u64, user_id: u64, rating: u8) -> bool {
-    if rating < 1 || rating > 5 {
-        return false;
+fn rate_track(track_id: u64, user_id: u64, rating: u8) -> Result<(), ApiError> {
+    if !(1..=5).contains(&rating) {
+        return Err(ApiError::invalid_input("rating", "must be between 1 and 5"));
     }
+    let principal = active_caller()?;
+    require_artist_owner(principal, user_id)?;
     TRACKS.with(|tracks| {
         let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
+        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id && can_view_track(principal, t)) {
             if let Some(r) = track.ratings.iter_mut().find(|(uid, _)| *uid == user_id) {
                 r.1 = rating;
             } else {
                 track.ratings.push((user_id, rating));
             }
-            return true;
+            return Ok(());
         }
-        false
+        Err(ApiError::not_found("track"))
     })
 }
 
 // Get average rating and count for a track
 #[ic_cdk::query]
-fn get_track_rating(track_id: u64) -> (u32, u8) {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id).map(|t| {
-            let count = t.ratings.len() as u32;
-            let sum: u32 = t.ratings.iter().map(|(_, r)| *r as u32).sum();
-            let avg = if count > 0 { (sum / count) as u8 } else { 0 };
-            (count, avg)
-        }).unwrap_or((0, 0))
+fn get_track_rating(track_id: u64) -> Result<(u32, u8), ApiError> {
+    visible_track(track_id).map(|t| {
+        let count = t.ratings.len() as u32;
+        let sum: u32 = t.ratings.iter().map(|(_, r)| *r as u32).sum();
+        let avg = sum.checked_div(count).unwrap_or(0) as u8;
+        (count, avg)
     })
 }
 
 // Get a user's rating for a track
 #[ic_cdk::query]
-fn get_user_track_rating(track_id: u64, user_id: u64) -> Option<u8> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id)
-            .and_then(|t| t.ratings.iter().find(|(uid, _)| *uid == user_id).map(|(_, r)| *r))
-    })
+fn get_user_track_rating(track_id: u64, user_id: u64) -> Result<Option<u8>, ApiError> {
+    visible_track(track_id)
+        .map(|t| t.ratings.iter().find(|(uid, _)| *uid == user_id).map(|(_, r)| *r))
 }
 
 // Add a tag to a track
 #[ic_cdk::update]
-fn add_tag(track_id: u64, tag: String) -> bool {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            if !track.tags.contains(&tag) {
-                track.tags.push(tag);
-            }
-            return true;
+fn add_tag(track_id: u64, tag: String) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Collaborator, |track| {
+        if !track.tags.contains(&tag) {
+            track.tags.push(tag);
         }
-        false
     })
 }
 
 // Remove a tag from a track
 #[ic_cdk::update]
-fn remove_tag(track_id: u64, tag: String) -> bool {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            track.tags.retain(|t| t != &tag);
-            return true;
-        }
-        false
-    })
+fn remove_tag(track_id: u64, tag: String) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Collaborator, |track| track.tags.retain(|t| t != &tag))
 }
 
 // Set genre for a track
 #[ic_cdk::update]
-fn set_genre(track_id: u64, genre: String) -> bool {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            track.genre = Some(genre);
-            return true;
-        }
-        false
-    })
+fn set_genre(track_id: u64, genre: String) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Collaborator, |track| track.genre = Some(genre))
 }
 
 // Get genre for a track
 #[ic_cdk::query]
-fn get_genre(track_id: u64) -> Option<String> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id).and_then(|t| t.genre.clone())
-    })
+fn get_genre(track_id: u64) -> Result<Option<String>, ApiError> {
+    visible_track(track_id).map(|t| t.genre)
 }
 
 // Search tracks by tag
 #[ic_cdk::query]
 fn search_tracks_by_tag(tag: String) -> Vec<Track> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().filter(|t| t.tags.contains(&tag)).cloned().collect()
-    })
+    visible_tracks(|t| t.tags.contains(&tag))
 }
 
 // Search tracks by genre
 #[ic_cdk::query]
 fn search_tracks_by_genre(genre: String) -> Vec<Track> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().filter(|t| t.genre.as_ref().map(|g| g == &genre).unwrap_or(false)).cloned().collect()
-    })
+    visible_tracks(|t| t.genre.as_ref().map(|g| g == &genre).unwrap_or(false))
 }
 
 // Collaboration Request Endpoints
 #[ic_cdk::update]
-fn send_collab_request(from: u64, to: u64, track_id: u64, message: Option<String>) -> Option<CollabRequest> {
+fn send_collab_request(from: u64, to: u64, track_id: u64, message: Option<String>) -> Result<CollabRequest, ApiError> {
+    require_artist_owner(caller(), from)?;
     let now = ic_cdk::api::time() / 1_000_000;
     COLLAB_REQUESTS.with(|requests| {
         COLLAB_REQUEST_ID.with(|id| {
             let mut id_mut = id.borrow_mut();
             // Prevent duplicate pending requests
             if requests.borrow().iter().any(|r| r.from == from && r.to == to && r.track_id == track_id && r.status == CollabRequestStatus::Pending) {
-                return None;
+                return Err(ApiError::conflict("a pending request already exists"));
             }
             let req = CollabRequest {
                 id: *id_mut,
@@ -923,20 +1214,23 @@ fn send_collab_request(from: u64, to: u64, track_id: u64, message: Option<String
             };
             requests.borrow_mut().push(req.clone());
             *id_mut += 1;
-            Some(req)
+            Ok(req)
         })
     })
 }
 
 #[ic_cdk::update]
-fn respond_collab_request(request_id: u64, accept: bool) -> Option<CollabRequest> {
+fn respond_collab_request(request_id: u64, accept: bool) -> Result<CollabRequest, ApiError> {
+    let principal = caller();
     COLLAB_REQUESTS.with(|requests| {
         let mut requests = requests.borrow_mut();
-        if let Some(req) = requests.iter_mut().find(|r| r.id == request_id && r.status == CollabRequestStatus::Pending) {
-            req.status = if accept { CollabRequestStatus::Accepted } else { CollabRequestStatus::Declined };
-            return Some(req.clone());
+        let req = requests.iter_mut().find(|r| r.id == request_id).ok_or_else(|| ApiError::not_found("collab request"))?;
+        require_artist_owner(principal, req.to)?;
+        if req.status != CollabRequestStatus::Pending {
+            return Err(ApiError::conflict("request has already been answered"));
         }
-        None
+        req.status = if accept { CollabRequestStatus::Accepted } else { CollabRequestStatus::Declined };
+        Ok(req.clone())
     })
 }
 
@@ -949,10 +1243,10 @@ fn list_collab_requests_for_user(user_id: u64) -> Vec<CollabRequest> {
 
 // Task Management Endpoints
 #[ic_cdk::update]
-fn create_task(track_id: u64, assigned_to: u64, description: String) -> Option<Task> {
-    if description.trim().is_empty() {
-        return None;
-    }
+fn create_task(track_id: u64, assigned_to: u64, description: String) -> Result<Task, ApiError> {
+    active_caller()?;
+    require_non_empty("description", &description)?;
+    find_track(track_id).ok_or_else(|| ApiError::not_found("track"))?;
     let now = ic_cdk::api::time() / 1_000_000;
     TASKS.with(|tasks| {
         TASK_ID.with(|id| {
@@ -968,76 +1262,72 @@ fn create_task(track_id: u64, assigned_to: u64, description: String) -> Option<T
             };
             tasks.borrow_mut().push(task.clone());
             *id_mut += 1;
-            Some(task)
+            Ok(task)
         })
     })
 }
 
 #[ic_cdk::update]
-fn update_task_status(task_id: u64, status: TaskStatus) -> Option<Task> {
+fn update_task_status(task_id: u64, status: TaskStatus) -> Result<Task, ApiError> {
     let now = ic_cdk::api::time() / 1_000_000;
+    let principal = caller();
     TASKS.with(|tasks| {
         let mut tasks = tasks.borrow_mut();
-        if let Some(task) = tasks.iter_mut().find(|t| t.id == task_id) {
-            task.status = status;
-            task.updated_at = now;
-            return Some(task.clone());
-        }
-        None
+        let task = tasks.iter_mut().find(|t| t.id == task_id).ok_or_else(|| ApiError::not_found("task"))?;
+        require_artist_owner(principal, task.assigned_to)?;
+        task.status = status;
+        task.updated_at = now;
+        Ok(task.clone())
     })
 }
 
 #[ic_cdk::query]
-fn list_tasks_for_track(track_id: u64) -> Vec<Task> {
-    TASKS.with(|tasks| {
+fn list_tasks_for_track(track_id: u64) -> Result<Vec<Task>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(TASKS.with(|tasks| {
         tasks.borrow().iter().filter(|t| t.track_id == track_id).cloned().collect()
-    })
+    }))
 }
 
 #[ic_cdk::query]
 fn list_tasks_for_user(user_id: u64) -> Vec<Task> {
     TASKS.with(|tasks| {
-        tasks.borrow().iter().filter(|t| t.assigned_to == user_id).cloned().collect()
+        tasks.borrow().iter().filter(|t| t.assigned_to == user_id && is_track_visible(t.track_id)).cloned().collect()
     })
 }
 
-// Royalty withdrawal endpoint
+// Royalty withdrawal endpoint. Sends `amount` minus the ledger fee to the caller and returns the
+// ledger block index; the balance is restored if the transfer fails.
 #[ic_cdk::update]
-fn withdraw_royalties(artist_id: u64, amount: u64) -> bool {
+async fn withdraw_royalties(artist_id: u64, amount: u64) -> Result<u64, ApiError> {
     if amount == 0 {
-        return false;
+        return Err(ApiError::invalid_input("amount", "must be greater than zero"));
     }
-    ARTISTS.with(|artists| {
-        let mut artists = artists.borrow_mut();
-        if let Some(artist) = artists.iter_mut().find(|a| a.id == artist_id) {
-            if artist.royalty_balance >= amount {
-                artist.royalty_balance -= amount;
-                let now = ic_cdk::api::time() / 1_000_000;
-                log_activity(artist_id, "withdraw_royalties", now, &format!("Withdrew {} tokens", amount));
-                // In production, integrate with ICP ledger here
-                return true;
-            }
-        }
-        false
-    })
+    let owner = caller();
+    require_artist_owner(owner, artist_id)?;
+    let block_index = pay_out(RoyaltyAccount::Artist(artist_id), owner, amount).await?;
+    let now = ic_cdk::api::time() / 1_000_000;
+    log_activity(artist_id, "withdraw_royalties", now, &format!("Withdrew {} tokens (block {})", amount, block_index));
+    Ok(block_index)
 }
 
-// Analytics: increment play count
+// Analytics: increment play count. Kept for older clients; without a listen duration the play
+// only counts towards the raw total. Use record_play.
 #[ic_cdk::update]
-fn increment_play_count(track_id: u64) -> bool {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            track.play_count += 1;
-            return true;
-        }
-        false
-    })
+fn increment_play_count(track_id: u64) -> Result<(), ApiError> {
+    let principal = require_identified_listener()?;
+    require_track_visible(track_id)?;
+    let now = ic_cdk::api::time() / 1_000_000;
+    check_rate_limit(principal, "record_play", now)?;
+    count_play(principal, track_id, None, now);
+    Ok(())
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct TrackAnalytics {
     pub play_count: u64,
+    pub raw_play_count: u64,
+    pub gateway_play_count: u64,
     pub revenue: u64,
     pub comments_count: u64,
     pub ratings_count: u64,
@@ -1045,37 +1335,35 @@ pub struct TrackAnalytics {
 }
 
 #[ic_cdk::query]
-fn get_track_analytics(track_id: u64) -> Option<TrackAnalytics> {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id).map(|t| {
-            let revenue: u64 = t.payments.iter().map(|p| p.amount).sum();
-            let comments_count = t.comments.len() as u64;
-            let ratings_count = t.ratings.len() as u64;
-            let avg_rating = if ratings_count > 0 {
-                (t.ratings.iter().map(|(_, r)| *r as u32).sum::<u32>() / ratings_count as u32) as u8
-            } else { 0 };
-            TrackAnalytics {
-                play_count: t.play_count,
-                revenue,
-                comments_count,
-                ratings_count,
-                avg_rating,
-            }
-        })
+fn get_track_analytics(track_id: u64) -> Result<TrackAnalytics, ApiError> {
+    visible_track(track_id).map(|t| {
+        let revenue: u64 = t.payments.iter().map(|p| p.amount).sum();
+        let comments_count = t.comments.len() as u64;
+        let ratings_count = t.ratings.len() as u64;
+        let avg_rating = if ratings_count > 0 {
+            (t.ratings.iter().map(|(_, r)| *r as u32).sum::<u32>() / ratings_count as u32) as u8
+        } else { 0 };
+        TrackAnalytics {
+            play_count: t.play_count,
+            raw_play_count: t.raw_play_count,
+            gateway_play_count: t.gateway_play_count,
+            revenue,
+            comments_count,
+            ratings_count,
+            avg_rating,
+        }
     })
 }
 
 // User CRUD
 #[ic_cdk::update]
-pub fn register_user(username: String, bio: Option<String>, avatar_url: Option<String>) -> Option<User> {
-    let principal = caller();
-    if username.trim().is_empty() {
-        return None;
-    }
+pub fn register_user(username: String, bio: Option<String>, avatar_url: Option<String>) -> Result<User, ApiError> {
+    let principal = active_caller()?;
+    require_non_empty("username", &username)?;
     USERS.with(|users| {
         let mut users = users.borrow_mut();
-        if users.iter().any(|u| u.principal == principal) {
-            return None; // Already registered
+        if users.contains_key(&principal) {
+            return Err(ApiError::conflict("caller is already registered"));
         }
         let user = User {
             principal,
@@ -1084,62 +1372,68 @@ pub fn register_user(username: String, bio: Option<String>, avatar_url: Option<S
             avatar_url: avatar_url.clone(),
             role: UserRole::User,
         };
-        users.push(user.clone());
+        users.insert(principal, user.clone());
         let now = ic_cdk::api::time() / 1_000_000;
         log_user_activity(principal, "register_user", now, &format!("Registered user: {}", username));
-        Some(user)
+        Ok(user)
     })
 }
 
 #[ic_cdk::query]
-pub fn get_user() -> Option<User> {
+pub fn get_user() -> Result<User, ApiError> {
     let principal = caller();
-    USERS.with(|users| users.borrow().iter().find(|u| u.principal == principal).cloned())
+    require_not_banned(principal)?;
+    find_user(principal).ok_or_else(|| ApiError::not_found("user"))
 }
 
 #[ic_cdk::update]
-fn update_user(username: String, bio: Option<String>, avatar_url: Option<String>) -> Option<User> {
-    let principal = caller();
+fn update_user(username: String, bio: Option<String>, avatar_url: Option<String>) -> Result<User, ApiError> {
+    let principal = active_caller()?;
+    require_non_empty("username", &username)?;
     USERS.with(|users| {
         let mut users = users.borrow_mut();
-        if let Some(user) = users.iter_mut().find(|u| u.principal == principal) {
+        if let Some(user) = users.get_mut(&principal) {
             user.username = username.clone();
             user.bio = bio.clone();
             user.avatar_url = avatar_url.clone();
             let now = ic_cdk::api::time() / 1_000_000;
             log_user_activity(principal, "update_user", now, &format!("Updated user: {}", username));
-            return Some(user.clone());
+            return Ok(user.clone());
         }
-        None
+        Err(ApiError::not_found("user"))
     })
 }
 
 #[ic_cdk::update]
-fn delete_user() -> bool {
-    let principal = caller();
-    USERS.with(|users| {
-        let mut users = users.borrow_mut();
-        let len_before = users.len();
-        users.retain(|u| u.principal != principal);
-        let deleted = users.len() < len_before;
-        if deleted {
-            let now = ic_cdk::api::time() / 1_000_000;
-            log_user_activity(principal, "delete_user", now, "Deleted user profile");
-        }
-        deleted
-    })
+fn delete_user() -> Result<(), ApiError> {
+    let principal = active_caller()?;
+    USERS.with(|users| users.borrow_mut().remove(&principal)).ok_or_else(|| ApiError::not_found("user"))?;
+    let now = ic_cdk::api::time() / 1_000_000;
+    log_user_activity(principal, "delete_user", now, "Deleted user profile");
+    Ok(())
 }
 
 // 1. List all users
 #[ic_cdk::query]
-fn list_users() -> Vec<User> {
-    USERS.with(|users| users.borrow().clone())
+fn list_users() -> Result<Vec<User>, ApiError> {
+    require_any_permission(caller(), &ALL_PERMISSIONS)?;
+    Ok(USERS.with(|users| users.borrow().values().cloned().collect()))
+}
+
+/// Users are kept by principal, so pages follow descending principal order rather than signup order.
+#[ic_cdk::query]
+fn query_users(filter: UserFilter, page: PageRequest) -> Result<Page<User>, ApiError> {
+    require_any_permission(caller(), &ALL_PERMISSIONS)?;
+    let users: Vec<User> = USERS.with(|users| {
+        users.borrow().values().filter(|u| matches_filter(&filter.role, &u.role)).cloned().collect()
+    });
+    Ok(paginate(users, &page))
 }
 
 // 2. Get user by principal
 #[ic_cdk::query]
-fn get_user_by_principal(principal: Principal) -> Option<User> {
-    USERS.with(|users| users.borrow().iter().find(|u| u.principal == principal).cloned())
+fn get_user_by_principal(principal: Principal) -> Result<User, ApiError> {
+    find_user(principal).ok_or_else(|| ApiError::not_found("user"))
 }
 
 // 3. Search users by username (case-insensitive substring)
@@ -1147,7 +1441,7 @@ fn get_user_by_principal(principal: Principal) -> Option<User> {
 fn search_users_by_username(query: String) -> Vec<User> {
     let q = query.to_lowercase();
     USERS.with(|users| {
-        users.borrow().iter().filter(|u| u.username.to_lowercase().contains(&q)).cloned().collect()
+        users.borrow().values().filter(|u| u.username.to_lowercase().contains(&q)).cloned().collect()
     })
 }
 
@@ -1187,129 +1481,300 @@ fn add_dummy_activity() {
 
 // 2. Audit/Admin Tools
 pub fn is_admin(principal: Principal) -> bool {
-    USERS.with(|users| users.borrow().iter().any(|u| u.principal == principal && u.role == UserRole::Admin))
+    find_user(principal).map(|u| u.role == UserRole::Admin).unwrap_or(false)
 }
 
-#[ic_cdk::update]
-pub fn ban_user(principal_to_ban: Principal) -> bool {
-    let principal = caller();
-    if !is_admin(principal) { return false; }
-    let mut success = false;
-    USERS.with(|users| {
-        let mut users = users.borrow_mut();
-        if let Some(user) = users.iter_mut().find(|u| u.principal == principal_to_ban) {
-            user.role = UserRole::User; // Or add a Banned role if desired
-            success = true;
-        }
+fn require_admin(principal: Principal) -> Result<(), ApiError> {
+    if is_admin(principal) { Ok(()) } else { Err(ApiError::unauthorized("admin only")) }
+}
+
+/// Changes a registered user's role and records the change in the audit log. Every role change
+/// goes through here. Returns the previous role, or None when the principal has no profile.
+fn set_role(target: Principal, role: UserRole, actor: Principal, now: u64) -> Option<UserRole> {
+    let previous = USERS.with(|users| {
+        users.borrow_mut().get_mut(&target).map(|user| std::mem::replace(&mut user.role, role.clone()))
+    })?;
+    if previous != role {
+        let details = format!("Role changed from {:?} to {:?}", previous, role);
+        push_audit_entry(actor, "set_user_role", "User", &target.to_text(), Some(details), now);
+    }
+    Some(previous)
+}
+
+/// Role changes made through `set_user_role`. Bans have their own endpoints so they can't be
+/// granted or cleared here.
+fn change_role(target: Principal, role: UserRole, actor: Principal, now: u64) -> Result<UserRole, ApiError> {
+    if role == UserRole::Banned {
+        return Err(ApiError::invalid_input("role", "use ban_user to ban a user"));
+    }
+    let user = find_user(target).ok_or_else(|| ApiError::not_found("user"))?;
+    if user.role == UserRole::Banned {
+        return Err(ApiError::conflict("user is banned; unban them first"));
+    }
+    Ok(set_role(target, role, actor, now).unwrap_or(user.role))
+}
+
+/// Controllers can always manage roles, so a canister that lost its last admin can be recovered.
+/// Other callers must be admins who aren't suspended.
+fn require_role_manager(principal: Principal) -> Result<(), ApiError> {
+    match is_admin(principal).then(|| require_active_user(principal)) {
+        Some(Ok(())) => Ok(()),
+        _ if ic_cdk::api::is_controller(&principal) => Ok(()),
+        Some(Err(err)) => Err(err),
+        None => Err(ApiError::unauthorized("admin or controller only")),
+    }
+}
+
+// --- Permission Matrix ---
+// Admins hold every permission. What other roles may do is configured at runtime with
+// `set_role_permissions`, except for the admin-only permissions, which can't be granted.
+
+/// Longest suspension a moderator may impose unless an admin changes it (one week)
+const DEFAULT_MODERATOR_SUSPENSION_CAP_SECS: u64 = 7 * 24 * 60 * 60;
+
+const ADMIN_ONLY_PERMISSIONS: [Permission; 3] = [Permission::ManageKeywords, Permission::DeleteContent, Permission::ViewAuditLog];
+
+const ALL_PERMISSIONS: [Permission; 8] = [
+    Permission::ReviewReports,
+    Permission::ReviewModerationQueue,
+    Permission::SuspendTargets,
+    Permission::LiftSuspensions,
+    Permission::ReviewAppeals,
+    Permission::ManageKeywords,
+    Permission::DeleteContent,
+    Permission::ViewAuditLog,
+];
+
+fn default_role_permissions() -> Vec<RolePermissions> {
+    vec![
+        RolePermissions {
+            role: UserRole::Moderator,
+            permissions: vec![Permission::ReviewReports, Permission::ReviewModerationQueue, Permission::SuspendTargets],
+        },
+        RolePermissions { role: UserRole::User, permissions: vec![] },
+    ]
+}
+
+fn permissions_of(principal: Principal) -> Vec<Permission> {
+    match find_user(principal).map(|u| u.role) {
+        Some(UserRole::Admin) => ALL_PERMISSIONS.to_vec(),
+        Some(UserRole::Banned) | None => vec![],
+        Some(role) => ROLE_PERMISSIONS.with(|roles| {
+            roles
+                .borrow()
+                .iter()
+                .find(|r| r.role == role)
+                .map(|r| r.permissions.iter().filter(|p| !ADMIN_ONLY_PERMISSIONS.contains(p)).cloned().collect())
+                .unwrap_or_default()
+        }),
+    }
+}
+
+fn has_permission(principal: Principal, permission: &Permission) -> bool {
+    permissions_of(principal).contains(permission)
+}
+
+/// Suspended staff keep their role but can't use it.
+fn require_permission(principal: Principal, permission: Permission) -> Result<(), ApiError> {
+    if !has_permission(principal, &permission) {
+        return Err(ApiError::unauthorized(&format!("requires the {:?} permission", permission)));
+    }
+    require_active_user(principal)
+}
+
+/// Non-admins may only suspend for a limited time, and never an admin.
+fn check_suspension_scope(
+    principal: Principal,
+    target_type: &SuspensionTargetType,
+    target_id: &str,
+    duration_secs: Option<u64>,
+) -> Result<(), ApiError> {
+    if is_admin(principal) {
+        return Ok(());
+    }
+    let cap = MODERATOR_SUSPENSION_CAP_SECS.with(|cap| *cap.borrow());
+    if duration_secs.is_none_or(|secs| secs > cap) {
+        return Err(ApiError::invalid_input("duration_secs", &format!("moderators can suspend for at most {}s", cap)));
+    }
+    let targets_admin = *target_type == SuspensionTargetType::User
+        && Principal::from_text(target_id).is_ok_and(is_admin);
+    if targets_admin {
+        return Err(ApiError::unauthorized("only admins can suspend an admin"));
+    }
+    Ok(())
+}
+
+fn update_role_permissions(role: UserRole, permissions: Vec<Permission>) -> Result<(), ApiError> {
+    if matches!(role, UserRole::Admin | UserRole::Banned) {
+        return Err(ApiError::invalid_input("role", "admins hold every permission and banned users none"));
+    }
+    if let Some(p) = permissions.iter().find(|p| ADMIN_ONLY_PERMISSIONS.contains(p)) {
+        return Err(ApiError::invalid_input("permissions", &format!("{:?} is admin-only", p)));
+    }
+    let mut permissions = permissions;
+    permissions.sort();
+    permissions.dedup();
+    ROLE_PERMISSIONS.with(|roles| {
+        let mut roles = roles.borrow_mut();
+        roles.retain(|r| r.role != role);
+        roles.push(RolePermissions { role, permissions });
     });
-    if success {
-        log_admin_action(
-            principal,
-            "ban_user",
-            "User",
-            &principal_to_ban.to_text(),
-            Some("User banned by admin".to_string()),
-        );
+    Ok(())
+}
+
+#[ic_cdk::update]
+pub fn set_role_permissions(role: UserRole, permissions: Vec<Permission>) -> Result<(), ApiError> {
+    let admin = caller();
+    require_admin(admin)?;
+    let details = format!("{:?}", permissions);
+    let target = format!("{:?}", role);
+    update_role_permissions(role, permissions)?;
+    log_admin_action(admin, "set_role_permissions", "Role", &target, Some(details));
+    Ok(())
+}
+
+#[ic_cdk::update]
+pub fn set_moderator_suspension_cap(cap_secs: u64) -> Result<(), ApiError> {
+    let admin = caller();
+    require_admin(admin)?;
+    if cap_secs == 0 {
+        return Err(ApiError::invalid_input("cap_secs", "must be greater than zero"));
     }
-    success
+    MODERATOR_SUSPENSION_CAP_SECS.with(|cap| *cap.borrow_mut() = cap_secs);
+    log_admin_action(admin, "set_moderator_suspension_cap", "PermissionSettings", "moderator_suspension_cap_secs", Some(format!("{}s", cap_secs)));
+    Ok(())
+}
+
+#[ic_cdk::query]
+pub fn get_permission_settings() -> Result<PermissionSettings, ApiError> {
+    require_admin(caller())?;
+    Ok(PermissionSettings {
+        roles: ROLE_PERMISSIONS.with(|roles| roles.borrow().clone()),
+        moderator_suspension_cap_secs: MODERATOR_SUSPENSION_CAP_SECS.with(|cap| *cap.borrow()),
+    })
 }
 
+#[ic_cdk::query]
+pub fn my_permissions() -> Vec<Permission> {
+    permissions_of(caller())
+}
+
+
 #[ic_cdk::update]
-pub fn delete_user_by_admin(principal_to_delete: Principal) -> bool {
+pub fn delete_user_by_admin(principal_to_delete: Principal) -> Result<(), ApiError> {
     let principal = caller();
-    if !is_admin(principal) { return false; }
-    let mut deleted = false;
-    USERS.with(|users| {
-        let mut users = users.borrow_mut();
-        let len_before = users.len();
-        users.retain(|u| u.principal != principal_to_delete);
-        deleted = users.len() < len_before;
-    });
-    if deleted {
-        log_admin_action(
-            principal,
-            "delete_user_by_admin",
-            "User",
-            &principal_to_delete.to_text(),
-            Some("User deleted by admin".to_string()),
-        );
-    }
-    deleted
+    require_permission(principal, Permission::DeleteContent)?;
+    USERS.with(|users| users.borrow_mut().remove(&principal_to_delete)).ok_or_else(|| ApiError::not_found("user"))?;
+    log_admin_action(
+        principal,
+        "delete_user_by_admin",
+        "User",
+        &principal_to_delete.to_text(),
+        Some("User deleted by admin".to_string()),
+    );
+    Ok(())
 }
 
 #[ic_cdk::update]
-pub fn delete_artist_by_admin(artist_id: u64) -> bool {
+pub fn delete_artist_by_admin(artist_id: u64) -> Result<(), ApiError> {
     let principal = caller();
-    if !is_admin(principal) { return false; }
-    let mut deleted = false;
+    require_permission(principal, Permission::DeleteContent)?;
     ARTISTS.with(|artists| {
         let mut artists = artists.borrow_mut();
-        let len_before = artists.len();
-        artists.retain(|a| a.id != artist_id);
-        deleted = artists.len() < len_before;
-    });
-    if deleted {
-        log_admin_action(
-            principal,
-            "delete_artist_by_admin",
-            "Artist",
-            &artist_id.to_string(),
-            Some("Artist deleted by admin".to_string()),
-        );
+        let pos = artists.iter().position(|a| a.id == artist_id).ok_or_else(|| ApiError::not_found("artist"))?;
+        let artist = artists.remove(pos);
+        unindex_artist(artist.user_principal, artist.id);
+        Ok(())
+    })?;
+    log_admin_action(
+        principal,
+        "delete_artist_by_admin",
+        "Artist",
+        &artist_id.to_string(),
+        Some("Artist deleted by admin".to_string()),
+    );
+    Ok(())
+}
+
+// Give an unclaimed artist, one carried over from before artists had owners, to its owner
+#[ic_cdk::update]
+pub fn assign_artist_owner(artist_id: u64, owner: Principal) -> Result<Artist, ApiError> {
+    let principal = caller();
+    require_admin(principal)?;
+    let artist = claim_artist(artist_id, owner)?;
+    log_admin_action(principal, "assign_artist_owner", "Artist", &artist_id.to_string(), Some(format!("Owner set to {}", owner)));
+    Ok(artist)
+}
+
+fn claim_artist(artist_id: u64, owner: Principal) -> Result<Artist, ApiError> {
+    if owner == Principal::anonymous() {
+        return Err(ApiError::invalid_input("owner", "must not be the anonymous principal"));
     }
-    deleted
+    let artist = ARTISTS.with(|artists| {
+        let mut artists = artists.borrow_mut();
+        let artist = artists.iter_mut().find(|a| a.id == artist_id).ok_or_else(|| ApiError::not_found("artist"))?;
+        if artist.user_principal != UNCLAIMED_ARTIST_OWNER {
+            return Err(ApiError::conflict("artist already has an owner"));
+        }
+        artist.user_principal = owner;
+        Ok(artist.clone())
+    })?;
+    index_artist(owner, artist_id);
+    Ok(artist)
 }
 
 #[ic_cdk::update]
-pub fn delete_track_by_admin(track_id: u64) -> bool {
+pub fn delete_track_by_admin(track_id: u64) -> Result<(), ApiError> {
     let principal = caller();
-    if !is_admin(principal) { return false; }
-    let mut deleted = false;
-    TRACKS.with(|tracks| {
+    require_permission(principal, Permission::DeleteContent)?;
+    require_no_active_campaign(track_id)?;
+    let deleted = TRACKS.with(|tracks| {
         let mut tracks = tracks.borrow_mut();
         let len_before = tracks.len();
         tracks.retain(|t| t.id != track_id);
-        deleted = tracks.len() < len_before;
+        tracks.len() < len_before
     });
-    if deleted {
-        log_admin_action(
-            principal,
-            "delete_track_by_admin",
-            "Track",
-            &track_id.to_string(),
-            Some("Track deleted by admin".to_string()),
-        );
+    if !deleted {
+        return Err(ApiError::not_found("track"));
     }
-    deleted
+    delete_track_assets(track_id);
+    log_admin_action(
+        principal,
+        "delete_track_by_admin",
+        "Track",
+        &track_id.to_string(),
+        Some("Track deleted by admin".to_string()),
+    );
+    Ok(())
 }
 
 // 3. Track/Artist Following
 #[ic_cdk::update]
-pub fn follow_artist(artist_principal: Principal) -> bool {
-    let principal = caller();
+pub fn follow_artist(artist_principal: Principal) -> Result<(), ApiError> {
+    let principal = active_caller()?;
     FOLLOWED_ARTISTS.with(|fa| {
         let mut fa = fa.borrow_mut();
-        if let Some((p, artists)) = fa.iter_mut().find(|(p, _)| *p == principal) {
+        if let Some((_, artists)) = fa.iter_mut().find(|(p, _)| *p == principal) {
             if !artists.contains(&artist_principal) {
                 artists.push(artist_principal);
             }
         } else {
             fa.push((principal, vec![artist_principal]));
         }
-        true
+        Ok(())
     })
 }
 
 #[ic_cdk::update]
-pub fn unfollow_artist(artist_principal: Principal) -> bool {
-    let principal = caller();
+pub fn unfollow_artist(artist_principal: Principal) -> Result<(), ApiError> {
+    let principal = active_caller()?;
     FOLLOWED_ARTISTS.with(|fa| {
         let mut fa = fa.borrow_mut();
-        if let Some((p, artists)) = fa.iter_mut().find(|(p, _)| *p == principal) {
+        if let Some((_, artists)) = fa.iter_mut().find(|(p, _)| *p == principal) {
             artists.retain(|a| a != &artist_principal);
-            return true;
+            return Ok(());
         }
-        false
+        Err(ApiError::not_found("followed artists"))
     })
 }
 
@@ -1322,31 +1787,32 @@ pub fn list_followed_artists() -> Vec<Principal> {
 }
 
 #[ic_cdk::update]
-pub fn follow_track(track_id: u64) -> bool {
-    let principal = caller();
+pub fn follow_track(track_id: u64) -> Result<(), ApiError> {
+    let principal = active_caller()?;
+    require_track_visible(track_id)?;
     FOLLOWED_TRACKS.with(|ft| {
         let mut ft = ft.borrow_mut();
-        if let Some((p, tracks)) = ft.iter_mut().find(|(p, _)| *p == principal) {
+        if let Some((_, tracks)) = ft.iter_mut().find(|(p, _)| *p == principal) {
             if !tracks.contains(&track_id) {
                 tracks.push(track_id);
             }
         } else {
             ft.push((principal, vec![track_id]));
         }
-        true
+        Ok(())
     })
 }
 
 #[ic_cdk::update]
-pub fn unfollow_track(track_id: u64) -> bool {
-    let principal = caller();
+pub fn unfollow_track(track_id: u64) -> Result<(), ApiError> {
+    let principal = active_caller()?;
     FOLLOWED_TRACKS.with(|ft| {
         let mut ft = ft.borrow_mut();
-        if let Some((p, tracks)) = ft.iter_mut().find(|(p, _)| *p == principal) {
+        if let Some((_, tracks)) = ft.iter_mut().find(|(p, _)| *p == principal) {
             tracks.retain(|t| t != &track_id);
-            return true;
+            return Ok(());
         }
-        false
+        Err(ApiError::not_found("followed tracks"))
     })
 }
 
@@ -1360,8 +1826,15 @@ pub fn list_followed_tracks() -> Vec<u64> {
 
 // Notifications System
 #[ic_cdk::update]
-pub fn send_notification(user_principal: Principal, message: String) -> Notification {
+pub fn send_notification(user_principal: Principal, message: String) -> Result<Notification, ApiError> {
+    let sender = active_caller()?;
+    require_non_empty("message", &message)?;
     let now = ic_cdk::api::time() / 1_000_000;
+    check_rate_limit(sender, "send_notification", now)?;
+    Ok(push_notification(user_principal, message, now))
+}
+
+fn push_notification(user_principal: Principal, message: String, now: u64) -> Notification {
     let id = NOTIFICATION_ID.with(|nid| {
         let mut nid = nid.borrow_mut();
         let id = *nid;
@@ -1386,38 +1859,31 @@ pub fn list_notifications() -> Vec<Notification> {
 }
 
 #[ic_cdk::update]
-pub fn mark_notification_read(notification_id: u64) -> bool {
+pub fn mark_notification_read(notification_id: u64) -> Result<(), ApiError> {
     let principal = caller();
     NOTIFICATIONS.with(|n| {
         let mut n = n.borrow_mut();
         if let Some(notif) = n.iter_mut().find(|notif| notif.id == notification_id && notif.user_principal == principal) {
             notif.read = true;
-            return true;
+            return Ok(());
         }
-        false
+        Err(ApiError::not_found("notification"))
     })
 }
 
 #[ic_cdk::update]
-fn promote_to_admin() -> bool {
+fn set_user_role(user_principal: Principal, role: UserRole) -> Result<(), ApiError> {
     let principal = caller();
-    USERS.with(|users| {
-        let mut users = users.borrow_mut();
-        if let Some(user) = users.iter_mut().find(|u| u.principal == principal) {
-            user.role = UserRole::Admin;
-            return true;
-        }
-        false
-    })
+    require_role_manager(principal)?;
+    change_role(user_principal, role, principal, ic_cdk::api::time() / 1_000_000)?;
+    Ok(())
 }
 
 // 4. Playlist Management
 #[ic_cdk::update]
-pub fn create_playlist(name: String, description: Option<String>, track_ids: Vec<u64>) -> Option<Playlist> {
-    let owner = caller();
-    if name.trim().is_empty() {
-        return None;
-    }
+pub fn create_playlist(name: String, description: Option<String>, track_ids: Vec<u64>) -> Result<Playlist, ApiError> {
+    let owner = active_caller()?;
+    require_non_empty("name", &name)?;
     let now = ic_cdk::api::time() / 1_000_000;
     let id = PLAYLIST_ID.with(|pid| {
         let mut pid = pid.borrow_mut();
@@ -1435,12 +1901,13 @@ pub fn create_playlist(name: String, description: Option<String>, track_ids: Vec
         updated_at: now,
     };
     PLAYLISTS.with(|p| p.borrow_mut().push(playlist.clone()));
-    Some(playlist)
+    Ok(playlist)
 }
 
 #[ic_cdk::update]
-pub fn update_playlist(playlist_id: u64, name: String, description: Option<String>, track_ids: Vec<u64>) -> Option<Playlist> {
-    let owner = caller();
+pub fn update_playlist(playlist_id: u64, name: String, description: Option<String>, track_ids: Vec<u64>) -> Result<Playlist, ApiError> {
+    let owner = active_caller()?;
+    require_non_empty("name", &name)?;
     PLAYLISTS.with(|p| {
         let mut p = p.borrow_mut();
         if let Some(playlist) = p.iter_mut().find(|pl| pl.id == playlist_id && pl.owner == owner) {
@@ -1448,32 +1915,41 @@ pub fn update_playlist(playlist_id: u64, name: String, description: Option<Strin
             playlist.description = description;
             playlist.track_ids = track_ids;
             playlist.updated_at = ic_cdk::api::time() / 1_000_000;
-            return Some(playlist.clone());
+            return Ok(playlist.clone());
         }
-        None
+        Err(ApiError::not_found("playlist"))
     })
 }
 
 #[ic_cdk::update]
-pub fn delete_playlist(playlist_id: u64) -> bool {
-    let owner = caller();
+pub fn delete_playlist(playlist_id: u64) -> Result<(), ApiError> {
+    let owner = active_caller()?;
     PLAYLISTS.with(|p| {
         let mut p = p.borrow_mut();
         let len_before = p.len();
         p.retain(|pl| !(pl.id == playlist_id && pl.owner == owner));
-        p.len() < len_before
+        if p.len() < len_before { Ok(()) } else { Err(ApiError::not_found("playlist")) }
     })
 }
 
 #[ic_cdk::query]
 pub fn list_playlists() -> Vec<Playlist> {
     let owner = caller();
-    PLAYLISTS.with(|p| p.borrow().iter().filter(|pl| pl.owner == owner).cloned().collect())
+    PLAYLISTS.with(|p| p.borrow().iter().filter(|pl| pl.owner == owner).cloned().map(without_hidden_tracks).collect())
 }
 
 #[ic_cdk::query]
-pub fn get_playlist(playlist_id: u64) -> Option<Playlist> {
+pub fn get_playlist(playlist_id: u64) -> Result<Playlist, ApiError> {
+    require_not_banned(caller())?;
     PLAYLISTS.with(|p| p.borrow().iter().find(|pl| pl.id == playlist_id).cloned())
+        .map(without_hidden_tracks)
+        .ok_or_else(|| ApiError::not_found("playlist"))
+}
+
+// Playlists may reference tracks that have since become private; drop those the caller can't see
+fn without_hidden_tracks(mut playlist: Playlist) -> Playlist {
+    playlist.track_ids.retain(|id| is_track_visible(*id));
+    playlist
 }
 
 // 5. Track Download/Streaming Controls
@@ -1481,8 +1957,88 @@ pub fn get_playlist(playlist_id: u64) -> Option<Playlist> {
 pub struct PlayDownloadCount {
     pub principal: Principal,
     pub track_id: u64,
-    pub play_count: u64,
+    pub play_count: u64, // raw
     pub download_count: u64,
+    pub validated_play_count: u64,
+    pub last_validated_at: Option<u64>,
+    pub burst_window_start: u64,
+    pub burst_plays: u32, // raw plays since burst_window_start
+}
+
+// Play counting. Every reported play is counted raw; it only counts as validated (feeding
+// `Track::play_count`, analytics and rankings) if the client reports a long enough listen and
+// the principal's last validated play of the track is older than the dedup window. A principal
+// reporting too many plays of one track within the burst window has further plays rejected and
+// is flagged for moderation once per window.
+const MIN_LISTEN_SECS: u32 = 30;
+const PLAY_DEDUP_WINDOW_MS: u64 = 30 * 60 * 1000;
+const PLAY_BURST_WINDOW_MS: u64 = 60 * 60 * 1000;
+const PLAY_BURST_THRESHOLD: u32 = 20;
+
+fn require_identified_listener() -> Result<Principal, ApiError> {
+    let principal = active_caller()?;
+    if principal == Principal::anonymous() {
+        return Err(ApiError::unauthorized("anonymous plays are not counted; sign in first"));
+    }
+    Ok(principal)
+}
+
+/// Counts a play of `track_id` by `principal`; `listened_secs` is None when the client didn't
+/// report a listen (e.g. a stream being opened). Returns whether the play was validated.
+fn count_play(principal: Principal, track_id: u64, listened_secs: Option<u32>, now: u64) -> bool {
+    let (validated, burst) = PLAY_COUNTS.with(|pc| {
+        let mut pc = pc.borrow_mut();
+        let index = match pc.iter().position(|e| e.principal == principal && e.track_id == track_id) {
+            Some(index) => index,
+            None => {
+                pc.push(PlayDownloadCount {
+                    principal,
+                    track_id,
+                    play_count: 0,
+                    download_count: 0,
+                    validated_play_count: 0,
+                    last_validated_at: None,
+                    burst_window_start: now,
+                    burst_plays: 0,
+                });
+                pc.len() - 1
+            }
+        };
+        let entry = &mut pc[index];
+        entry.play_count += 1;
+        if now >= entry.burst_window_start + PLAY_BURST_WINDOW_MS {
+            entry.burst_window_start = now;
+            entry.burst_plays = 0;
+        }
+        entry.burst_plays += 1;
+        let within_burst_limit = entry.burst_plays <= PLAY_BURST_THRESHOLD;
+        let validated = within_burst_limit
+            && listened_secs.is_some_and(|secs| secs >= MIN_LISTEN_SECS)
+            && entry.last_validated_at.is_none_or(|last| now >= last + PLAY_DEDUP_WINDOW_MS);
+        if validated {
+            entry.validated_play_count += 1;
+            entry.last_validated_at = Some(now);
+        }
+        (validated, (entry.burst_plays == PLAY_BURST_THRESHOLD + 1).then_some(entry.burst_plays))
+    });
+    TRACKS.with(|tracks| {
+        if let Some(track) = tracks.borrow_mut().iter_mut().find(|t| t.id == track_id) {
+            track.raw_play_count += 1;
+            if validated {
+                track.play_count += 1;
+            }
+        }
+    });
+    if let Some(plays) = burst {
+        push_moderation_item(
+            ModerationTargetType::Track,
+            track_id.to_string(),
+            format!("Play burst: {} reported {} plays within an hour", principal, plays),
+            "Auto-flagged by play counting",
+            now,
+        );
+    }
+    validated
 }
 
 // Extend Track with downloadable
@@ -1490,49 +2046,46 @@ pub struct PlayDownloadCount {
 // Update Track struct definition and all usages accordingly
 
 #[ic_cdk::update]
-pub fn set_track_downloadable(track_id: u64, downloadable: bool) -> bool {
-    TRACKS.with(|tracks| {
-        let mut tracks = tracks.borrow_mut();
-        if let Some(track) = tracks.iter_mut().find(|t| t.id == track_id) {
-            track.downloadable = downloadable;
-            return true;
-        }
-        false
-    })
+pub fn set_track_downloadable(track_id: u64, downloadable: bool) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Owner, |track| track.downloadable = downloadable)
 }
 
 #[ic_cdk::query]
-pub fn can_download_track(track_id: u64) -> bool {
-    TRACKS.with(|tracks| {
-        tracks.borrow().iter().find(|t| t.id == track_id).map(|t| t.downloadable).unwrap_or(false)
-    })
+pub fn can_download_track(track_id: u64) -> Result<bool, ApiError> {
+    visible_track(track_id).map(|t| has_track_access(caller(), &t, TrackAccess::Download))
 }
 
+// Report a finished listen of `listened_secs`. Returns whether it counted as a validated play.
 #[ic_cdk::update]
-pub fn record_play(track_id: u64) -> bool {
-    let principal = caller();
-    PLAY_COUNTS.with(|pc| {
-        let mut pc = pc.borrow_mut();
-        if let Some(entry) = pc.iter_mut().find(|e| e.principal == principal && e.track_id == track_id) {
-            entry.play_count += 1;
-        } else {
-            pc.push(PlayDownloadCount { principal, track_id, play_count: 1, download_count: 0 });
-        }
-        true
-    })
+pub fn record_play(track_id: u64, listened_secs: u32) -> Result<bool, ApiError> {
+    let principal = require_identified_listener()?;
+    require_track_visible(track_id)?;
+    let now = ic_cdk::api::time() / 1_000_000;
+    check_rate_limit(principal, "record_play", now)?;
+    Ok(count_play(principal, track_id, Some(listened_secs), now))
 }
 
 #[ic_cdk::update]
-pub fn record_download(track_id: u64) -> bool {
-    let principal = caller();
+pub fn record_download(track_id: u64) -> Result<(), ApiError> {
+    let principal = active_caller()?;
+    require_track_access(principal, &visible_track(track_id)?, TrackAccess::Download)?;
     PLAY_COUNTS.with(|pc| {
         let mut pc = pc.borrow_mut();
         if let Some(entry) = pc.iter_mut().find(|e| e.principal == principal && e.track_id == track_id) {
             entry.download_count += 1;
         } else {
-            pc.push(PlayDownloadCount { principal, track_id, play_count: 0, download_count: 1 });
+            pc.push(PlayDownloadCount {
+                principal,
+                track_id,
+                play_count: 0,
+                download_count: 1,
+                validated_play_count: 0,
+                last_validated_at: None,
+                burst_window_start: 0,
+                burst_plays: 0,
+            });
         }
-        true
+        Ok(())
     })
 }
 
@@ -1564,12 +2117,11 @@ pub struct Message {
 }
 
 #[ic_cdk::update]
-pub fn send_message(to: Principal, content: String) -> Option<Message> {
-    let from = caller();
-    if content.trim().is_empty() {
-        return None;
-    }
+pub fn send_message(to: Principal, content: String) -> Result<Message, ApiError> {
+    let from = active_caller()?;
+    require_non_empty("content", &content)?;
     let now = ic_cdk::api::time() / 1_000_000;
+    check_rate_limit(from, "send_message", now)?;
     let id = MESSAGE_ID.with(|mid| {
         let mut mid = mid.borrow_mut();
         let id = *mid;
@@ -1585,7 +2137,7 @@ pub fn send_message(to: Principal, content: String) -> Option<Message> {
         read: false,
     };
     MESSAGES.with(|m| m.borrow_mut().push(message.clone()));
-    Some(message)
+    Ok(message)
 }
 
 #[ic_cdk::query]
@@ -1597,23 +2149,56 @@ pub fn list_messages_with(user: Principal) -> Vec<Message> {
 }
 
 #[ic_cdk::update]
-pub fn mark_message_read(message_id: u64) -> bool {
+pub fn mark_message_read(message_id: u64) -> Result<(), ApiError> {
     let me = caller();
     MESSAGES.with(|m| {
         let mut m = m.borrow_mut();
         if let Some(msg) = m.iter_mut().find(|msg| msg.id == message_id && msg.to == me) {
             msg.read = true;
-            return true;
+            return Ok(());
         }
-        false
+        Err(ApiError::not_found("message"))
     })
 }
 
+// --- Staff Listing Helpers ---
+// Reports, the moderation queue, suspensions, appeals, users and the audit log name reporters
+// and carry moderation notes, so only staff with a matching permission can list them.
+
+const MAX_PAGE_SIZE: u32 = 100;
+
+fn require_any_permission(principal: Principal, permissions: &[Permission]) -> Result<(), ApiError> {
+    let held = permissions_of(principal);
+    if !permissions.iter().any(|p| held.contains(p)) {
+        return Err(ApiError::unauthorized(&format!("requires one of the {:?} permissions", permissions)));
+    }
+    require_active_user(principal)
+}
+
+fn in_range(timestamp: u64, from: Option<u64>, to: Option<u64>) -> bool {
+    from.is_none_or(|from| timestamp >= from) && to.is_none_or(|to| timestamp < to)
+}
+
+fn matches_filter<T: PartialEq>(wanted: &Option<T>, value: &T) -> bool {
+    wanted.as_ref().is_none_or(|wanted| wanted == value)
+}
+
+/// Pages through `items`, stored oldest first, returning the newest first.
+fn paginate<T: Clone>(items: Vec<T>, page: &PageRequest) -> Page<T> {
+    let total = items.len() as u64;
+    let limit = page.limit.clamp(1, MAX_PAGE_SIZE) as usize;
+    let items: Vec<T> = items.into_iter().rev().skip(usize::try_from(page.offset).unwrap_or(usize::MAX)).take(limit).collect();
+    let end = page.offset.saturating_add(items.len() as u64);
+    Page { items, total, next_offset: (end < total).then_some(end) }
+}
+
 // --- Reporting & Moderation Endpoints ---
 #[ic_cdk::update]
-pub fn report_content(target_type: ReportTargetType, target_id: String, reason: String, details: Option<String>) -> Option<Report> {
-    let reporter = caller();
+pub fn report_content(target_type: ReportTargetType, target_id: String, reason: String, details: Option<String>) -> Result<Report, ApiError> {
+    let reporter = active_caller()?;
+    require_non_empty("reason", &reason)?;
     let now = ic_cdk::api::time() / 1_000_000;
+    check_rate_limit(reporter, "report_content", now)?;
     let id = REPORT_ID.with(|rid| {
         let mut rid = rid.borrow_mut();
         let id = *rid;
@@ -1634,52 +2219,88 @@ pub fn report_content(target_type: ReportTargetType, target_id: String, reason:
         resolution_notes: None,
     };
     REPORTS.with(|r| r.borrow_mut().push(report.clone()));
-    Some(report)
+    Ok(report)
+}
+
+#[ic_cdk::query]
+pub fn list_reports() -> Result<Vec<Report>, ApiError> {
+    require_permission(caller(), Permission::ReviewReports)?;
+    Ok(REPORTS.with(|r| r.borrow().clone()))
+}
+
+fn filter_reports(filter: &ReportFilter) -> Vec<Report> {
+    REPORTS.with(|r| {
+        r.borrow()
+            .iter()
+            .filter(|rep| matches_filter(&filter.status, &rep.status) && matches_filter(&filter.target_type, &rep.target_type))
+            .filter(|rep| in_range(rep.created_at, filter.from, filter.to))
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+pub fn query_reports(filter: ReportFilter, page: PageRequest) -> Result<Page<Report>, ApiError> {
+    require_permission(caller(), Permission::ReviewReports)?;
+    Ok(paginate(filter_reports(&filter), &page))
+}
+
+fn reports_by(reporter: Principal) -> Vec<MyReport> {
+    REPORTS.with(|r| {
+        r.borrow()
+            .iter()
+            .rev()
+            .filter(|rep| rep.reporter == reporter)
+            .map(|rep| MyReport {
+                id: rep.id,
+                target_type: rep.target_type.clone(),
+                target_id: rep.target_id.clone(),
+                reason: rep.reason.clone(),
+                status: rep.status.clone(),
+                created_at: rep.created_at,
+                reviewed_at: rep.reviewed_at,
+            })
+            .collect()
+    })
 }
 
+/// The caller's own reports, newest first.
 #[ic_cdk::query]
-pub fn list_reports() -> Vec<Report> {
-    REPORTS.with(|r| r.borrow().clone())
+pub fn my_reports() -> Result<Vec<MyReport>, ApiError> {
+    let reporter = caller();
+    require_not_banned(reporter)?;
+    Ok(reports_by(reporter))
 }
 
 #[ic_cdk::update]
-pub fn review_report(report_id: u64, status: ReportStatus, resolution_notes: Option<String>) -> bool {
+pub fn review_report(report_id: u64, status: ReportStatus, resolution_notes: Option<String>) -> Result<(), ApiError> {
     let reviewer = caller();
     let now = ic_cdk::api::time() / 1_000_000;
-    if !is_admin(reviewer) {
-        return false;
-    }
-    let mut success = false;
-    let mut target_type = String::new();
-    let mut target_id = String::new();
+    require_permission(reviewer, Permission::ReviewReports)?;
     let status_for_log = status.clone();
-    REPORTS.with(|r| {
+    let (target_type, target_id) = REPORTS.with(|r| {
         let mut r = r.borrow_mut();
-        if let Some(report) = r.iter_mut().find(|rep| rep.id == report_id) {
-            report.status = status;
-            report.reviewed_by = Some(reviewer);
-            report.reviewed_at = Some(now);
-            report.resolution_notes = resolution_notes.clone();
-            target_type = format!("{:?}", report.target_type);
-            target_id = report.target_id.clone();
-            success = true;
-        }
-    });
-    if success {
-        log_admin_action(
-            reviewer,
-            "review_report",
-            &target_type,
-            &target_id,
-            Some(format!("Report {} reviewed: {:?}", report_id, status_for_log)),
-        );
-    }
-    success
+        let report = r.iter_mut().find(|rep| rep.id == report_id).ok_or_else(|| ApiError::not_found("report"))?;
+        report.status = status;
+        report.reviewed_by = Some(reviewer);
+        report.reviewed_at = Some(now);
+        report.resolution_notes = resolution_notes.clone();
+        Ok::<_, ApiError>((format!("{:?}", report.target_type), report.target_id.clone()))
+    })?;
+    log_admin_action(
+        reviewer,
+        "review_report",
+        &target_type,
+        &target_id,
+        Some(format!("Report {} reviewed: {:?}", report_id, status_for_log)),
+    );
+    Ok(())
 }
 
 // --- Track Licensing/Contracts Endpoints ---
 #[ic_cdk::update]
-pub fn set_track_license(track_id: u64, license_type: LicenseType, terms: Option<String>, contract_text: Option<String>) -> Option<TrackLicense> {
+pub fn set_track_license(track_id: u64, license_type: LicenseType, terms: Option<String>, contract_text: Option<String>) -> Result<TrackLicense, ApiError> {
+    with_track_as(track_id, TrackRole::Owner, |_| ())?;
     let now = ic_cdk::api::time() / 1_000_000;
     let license = TrackLicense {
         track_id,
@@ -1697,45 +2318,128 @@ pub fn set_track_license(track_id: u64, license_type: LicenseType, terms: Option
             tl.push(license.clone());
         }
     });
-    Some(license)
+    Ok(license)
 }
 
 #[ic_cdk::query]
-pub fn get_track_license(track_id: u64) -> Option<TrackLicense> {
-    TRACK_LICENSES.with(|tl| tl.borrow().iter().find(|l| l.track_id == track_id).cloned())
+pub fn get_track_license(track_id: u64) -> Result<Option<TrackLicense>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(TRACK_LICENSES.with(|tl| tl.borrow().iter().find(|l| l.track_id == track_id).cloned()))
 }
 
-// --- API Rate Limiting (Basic, for demonstration) ---
-fn check_rate_limit(principal: Principal, max_calls: u32, window_secs: u64) -> bool {
-    let now = ic_cdk::api::time() / 1_000_000;
-    let mut allowed = false;
+// --- API Rate Limiting ---
+// Write endpoints open to every user are limited per principal and endpoint with a fixed window.
+// Endpoints validate their input first, so only calls that would write use up the quota.
+// Admins can retune the policies at runtime; a timer drops counters whose window has ended.
+
+/// How often expired rate-limit counters are dropped
+const RATE_LIMIT_GC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+/// Longest window a policy may use
+const MAX_RATE_LIMIT_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+/// Defaults for every rate-limited endpoint. Only these endpoints accept a policy.
+fn default_rate_limit_policies() -> Vec<RateLimitPolicy> {
+    [("add_comment", 10, 60), ("send_message", 20, 60), ("report_content", 5, 300), ("record_play", 60, 60), ("send_notification", 10, 60)]
+        .into_iter()
+        .map(|(endpoint, max_calls, window_secs)| RateLimitPolicy { endpoint: endpoint.to_string(), max_calls, window_secs })
+        .collect()
+}
+
+fn rate_limit_policy(endpoint: &str) -> Option<RateLimitPolicy> {
+    RATE_LIMIT_POLICIES.with(|policies| policies.borrow().iter().find(|p| p.endpoint == endpoint).cloned())
+}
+
+/// Counts a call by `principal` to `endpoint` at `now` (ms), or reports how long until the
+/// current window ends if the policy's limit is already used up. Anonymous callers all share one
+/// principal, so they are refused rather than counted.
+fn check_rate_limit(principal: Principal, endpoint: &str, now: u64) -> Result<(), ApiError> {
+    if principal == Principal::anonymous() {
+        return Err(ApiError::unauthorized("anonymous callers cannot use this endpoint; sign in first"));
+    }
+    let Some(policy) = rate_limit_policy(endpoint) else { return Ok(()) };
+    let window_ms = policy.window_secs.saturating_mul(1_000);
     RATE_LIMITS.with(|rl| {
         let mut rl = rl.borrow_mut();
-        if let Some(entry) = rl.iter_mut().find(|e| e.principal == principal) {
-            if now - entry.window_start > window_secs {
+        match rl.iter_mut().find(|e| e.principal == principal && e.endpoint == endpoint) {
+            Some(entry) if now >= entry.window_start.saturating_add(window_ms) => {
                 entry.window_start = now;
                 entry.call_count = 1;
-                allowed = true;
-            } else if entry.call_count < max_calls {
+                entry.last_call = now;
+            }
+            Some(entry) if entry.call_count < policy.max_calls => {
                 entry.call_count += 1;
-                allowed = true;
+                entry.last_call = now;
             }
-        } else {
-            rl.push(RateLimitEntry {
-                principal,
-                last_call: now,
-                call_count: 1,
-                window_start: now,
-            });
-            allowed = true;
+            Some(entry) => {
+                let remaining_ms = entry.window_start.saturating_add(window_ms) - now;
+                return Err(ApiError::RateLimited { retry_after_secs: remaining_ms.div_ceil(1_000).max(1) });
+            }
+            None => rl.push(RateLimitEntry { principal, endpoint: endpoint.to_string(), last_call: now, call_count: 1, window_start: now }),
         }
+        Ok(())
+    })
+}
+
+/// Drops counters whose window has ended, or whose endpoint no longer has a policy.
+fn gc_rate_limits(now: u64) -> usize {
+    RATE_LIMITS.with(|rl| {
+        let mut rl = rl.borrow_mut();
+        let before = rl.len();
+        rl.retain(|e| rate_limit_policy(&e.endpoint).is_some_and(|p| now < e.window_start.saturating_add(p.window_secs.saturating_mul(1_000))));
+        before - rl.len()
+    })
+}
+
+#[ic_cdk::query]
+pub fn list_rate_limit_policies() -> Result<Vec<RateLimitPolicy>, ApiError> {
+    require_admin(caller())?;
+    Ok(RATE_LIMIT_POLICIES.with(|policies| policies.borrow().clone()))
+}
+
+// Replace the policy for one of the rate-limited endpoints. Current counters are kept and
+// judged against the new policy from the next call.
+#[ic_cdk::update]
+pub fn set_rate_limit_policy(policy: RateLimitPolicy) -> Result<(), ApiError> {
+    let admin = caller();
+    require_admin(admin)?;
+    validate_rate_limit_policy(&policy)?;
+    let details = format!("{} calls per {}s", policy.max_calls, policy.window_secs);
+    let endpoint = policy.endpoint.clone();
+    RATE_LIMIT_POLICIES.with(|policies| {
+        let mut policies = policies.borrow_mut();
+        policies.retain(|p| p.endpoint != policy.endpoint);
+        policies.push(policy);
     });
-    allowed
+    log_admin_action(admin, "set_rate_limit_policy", "RateLimitPolicy", &endpoint, Some(details));
+    Ok(())
+}
+
+fn validate_rate_limit_policy(policy: &RateLimitPolicy) -> Result<(), ApiError> {
+    if !default_rate_limit_policies().iter().any(|p| p.endpoint == policy.endpoint) {
+        return Err(ApiError::invalid_input("endpoint", "is not a rate-limited endpoint"));
+    }
+    if policy.max_calls == 0 {
+        return Err(ApiError::invalid_input("max_calls", "must be greater than zero"));
+    }
+    if policy.window_secs == 0 || policy.window_secs > MAX_RATE_LIMIT_WINDOW_SECS {
+        return Err(ApiError::invalid_input("window_secs", &format!("must be between 1 and {}", MAX_RATE_LIMIT_WINDOW_SECS)));
+    }
+    Ok(())
+}
+
+// Current counters, e.g. to see who is being throttled
+#[ic_cdk::query]
+pub fn list_rate_limit_entries() -> Result<Vec<RateLimitEntry>, ApiError> {
+    require_admin(caller())?;
+    Ok(RATE_LIMITS.with(|rl| rl.borrow().clone()))
 }
 
 // --- Audit Log Endpoints ---
 fn log_admin_action(admin: Principal, action: &str, target_type: &str, target_id: &str, details: Option<String>) {
-    let now = ic_cdk::api::time() / 1_000_000;
+    push_audit_entry(admin, action, target_type, target_id, details, ic_cdk::api::time() / 1_000_000);
+}
+
+fn push_audit_entry(admin: Principal, action: &str, target_type: &str, target_id: &str, details: Option<String>, now: u64) {
     AUDIT_LOG_ID.with(|aid| {
         let mut aid = aid.borrow_mut();
         let entry = AuditLogEntry {
@@ -1753,14 +2457,33 @@ fn log_admin_action(admin: Principal, action: &str, target_type: &str, target_id
 }
 
 #[ic_cdk::query]
-pub fn list_audit_log() -> Vec<AuditLogEntry> {
-    AUDIT_LOG.with(|log| log.borrow().clone())
+pub fn list_audit_log() -> Result<Vec<AuditLogEntry>, ApiError> {
+    require_permission(caller(), Permission::ViewAuditLog)?;
+    Ok(AUDIT_LOG.with(|log| log.borrow().clone()))
+}
+
+fn filter_audit_log(filter: &AuditLogFilter) -> Vec<AuditLogEntry> {
+    AUDIT_LOG.with(|log| {
+        log.borrow()
+            .iter()
+            .filter(|e| matches_filter(&filter.admin, &e.admin) && matches_filter(&filter.action, &e.action))
+            .filter(|e| matches_filter(&filter.target_type, &e.target_type) && in_range(e.timestamp, filter.from, filter.to))
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+pub fn query_audit_log(filter: AuditLogFilter, page: PageRequest) -> Result<Page<AuditLogEntry>, ApiError> {
+    require_permission(caller(), Permission::ViewAuditLog)?;
+    Ok(paginate(filter_audit_log(&filter), &page))
 }
 
 // --- Moderation Queue Endpoints ---
 #[ic_cdk::update]
-pub fn flag_content_for_moderation(target_type: ModerationTargetType, target_id: String, reason: String) -> Option<ModerationQueueItem> {
-    let flagged_by = Some(caller());
+pub fn flag_content_for_moderation(target_type: ModerationTargetType, target_id: String, reason: String) -> Result<ModerationQueueItem, ApiError> {
+    require_non_empty("reason", &reason)?;
+    let flagged_by = Some(active_caller()?);
     let now = ic_cdk::api::time() / 1_000_000;
     let id = MODERATION_QUEUE_ID.with(|mid| {
         let mut mid = mid.borrow_mut();
@@ -1781,57 +2504,130 @@ pub fn flag_content_for_moderation(target_type: ModerationTargetType, target_id:
         notes: None,
     };
     MODERATION_QUEUE.with(|q| q.borrow_mut().push(item.clone()));
-    Some(item)
+    Ok(item)
+}
+
+#[ic_cdk::query]
+pub fn list_moderation_queue() -> Result<Vec<ModerationQueueItem>, ApiError> {
+    require_permission(caller(), Permission::ReviewModerationQueue)?;
+    Ok(MODERATION_QUEUE.with(|q| q.borrow().clone()))
+}
+
+fn filter_moderation_queue(filter: &ModerationFilter) -> Vec<ModerationQueueItem> {
+    MODERATION_QUEUE.with(|q| {
+        q.borrow()
+            .iter()
+            .filter(|i| matches_filter(&filter.status, &i.status) && matches_filter(&filter.target_type, &i.target_type))
+            .filter(|i| in_range(i.created_at, filter.from, filter.to))
+            .cloned()
+            .collect()
+    })
 }
 
 #[ic_cdk::query]
-pub fn list_moderation_queue() -> Vec<ModerationQueueItem> {
-    MODERATION_QUEUE.with(|q| q.borrow().clone())
+pub fn query_moderation_queue(filter: ModerationFilter, page: PageRequest) -> Result<Page<ModerationQueueItem>, ApiError> {
+    require_permission(caller(), Permission::ReviewModerationQueue)?;
+    Ok(paginate(filter_moderation_queue(&filter), &page))
 }
 
 #[ic_cdk::update]
-pub fn review_moderation_item(item_id: u64, status: ModerationStatus, notes: Option<String>) -> bool {
+pub fn review_moderation_item(item_id: u64, status: ModerationStatus, notes: Option<String>) -> Result<(), ApiError> {
     let reviewer = caller();
     let now = ic_cdk::api::time() / 1_000_000;
-    if !is_admin(reviewer) {
-        return false;
-    }
-    let mut success = false;
-    let mut target_type = String::new();
-    let mut target_id = String::new();
+    require_permission(reviewer, Permission::ReviewModerationQueue)?;
     let status_for_log = status.clone();
-    MODERATION_QUEUE.with(|q| {
+    let (target_type, target_id) = MODERATION_QUEUE.with(|q| {
         let mut q = q.borrow_mut();
-        if let Some(item) = q.iter_mut().find(|i| i.id == item_id) {
-            item.status = status;
-            item.reviewed_by = Some(reviewer);
-            item.reviewed_at = Some(now);
-            item.notes = notes.clone();
-            target_type = format!("{:?}", item.target_type);
-            target_id = item.target_id.clone();
-            success = true;
-        }
-    });
-    if success {
-        log_admin_action(
-            reviewer,
-            "review_moderation_item",
-            &target_type,
-            &target_id,
-            Some(format!("Moderation item {} reviewed: {:?}", item_id, status_for_log)),
-        );
+        let item = q.iter_mut().find(|i| i.id == item_id).ok_or_else(|| ApiError::not_found("moderation item"))?;
+        item.status = status;
+        item.reviewed_by = Some(reviewer);
+        item.reviewed_at = Some(now);
+        item.notes = notes.clone();
+        Ok::<_, ApiError>((format!("{:?}", item.target_type), item.target_id.clone()))
+    })?;
+    log_admin_action(
+        reviewer,
+        "review_moderation_item",
+        &target_type,
+        &target_id,
+        Some(format!("Moderation item {} reviewed: {:?}", item_id, status_for_log)),
+    );
+    Ok(())
+}
+
+// --- Suspension Enforcement ---
+// Every user, artist and track endpoint checks the relevant Active suspensions: users through
+// `active_caller` (or the role and ownership checks), artists through `require_artist_owner` and
+// tracks through `require_track_role` and `can_view_track`. Time-bound suspensions stay Active
+// until the expiry timer marks them Expired, at most a minute late. Appeals, reading
+// notifications and messages, and cancelling a membership remain open to suspended users.
+
+/// How often time-bound suspensions are checked for expiry
+const SUSPENSION_EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+fn active_suspension(target_type: &SuspensionTargetType, target_id: &str) -> Option<Suspension> {
+    SUSPENSIONS.with(|suspensions| {
+        suspensions
+            .borrow()
+            .iter()
+            .find(|s| s.status == SuspensionStatus::Active && &s.target_type == target_type && s.target_id == target_id)
+            .cloned()
+    })
+}
+
+fn is_suspended(target_type: &SuspensionTargetType, target_id: &str) -> bool {
+    active_suspension(target_type, target_id).is_some()
+}
+
+fn require_not_suspended(target_type: SuspensionTargetType, target_id: &str) -> Result<(), ApiError> {
+    match active_suspension(&target_type, target_id) {
+        Some(suspension) => Err(ApiError::Suspended {
+            reason: format!("{:?} {} is suspended (suspension {}): {}", target_type, target_id, suspension.id, suspension.reason),
+        }),
+        None => Ok(()),
     }
-    success
 }
 
-// --- Suspension & Appeals Endpoints ---
-#[ic_cdk::update]
-pub fn suspend_target(target_type: SuspensionTargetType, target_id: String, reason: String, duration_secs: Option<u64>) -> Option<Suspension> {
-    let imposed_by = caller();
-    let now = ic_cdk::api::time() / 1_000_000;
-    if !is_admin(imposed_by) {
-        return None;
+fn require_active_user(principal: Principal) -> Result<(), ApiError> {
+    require_not_banned(principal)?;
+    require_not_suspended(SuspensionTargetType::User, &principal.to_text())
+}
+
+/// The caller, if they are not suspended.
+fn active_caller() -> Result<Principal, ApiError> {
+    let principal = caller();
+    require_active_user(principal)?;
+    Ok(principal)
+}
+
+/// Marks time-bound suspensions whose duration has passed as Expired. Returns how many expired.
+/// Role changes that follow are audited under `actor`, the canister itself when run by the timer.
+fn expire_suspensions(actor: Principal, now: u64) -> u32 {
+    let expired: Vec<u64> = SUSPENSIONS.with(|suspensions| {
+        let mut expired = Vec::new();
+        for s in suspensions.borrow_mut().iter_mut().filter(|s| s.status == SuspensionStatus::Active) {
+            if s.duration_secs.is_some_and(|secs| now >= s.imposed_at.saturating_add(secs.saturating_mul(1_000))) {
+                s.status = SuspensionStatus::Expired;
+                expired.push(s.id);
+            }
+        }
+        expired
+    });
+    for suspension_id in &expired {
+        end_ban(*suspension_id, actor, now);
     }
+    expired.len() as u32
+}
+
+/// Records a new Active suspension.
+fn impose_suspension(
+    target_type: SuspensionTargetType,
+    target_id: String,
+    reason: String,
+    imposed_by: Principal,
+    duration_secs: Option<u64>,
+    now: u64,
+) -> Suspension {
     let id = SUSPENSION_ID.with(|sid| {
         let mut sid = sid.borrow_mut();
         let id = *sid;
@@ -1840,9 +2636,9 @@ pub fn suspend_target(target_type: SuspensionTargetType, target_id: String, reas
     });
     let suspension = Suspension {
         id,
-        target_type: target_type.clone(),
-        target_id: target_id.clone(),
-        reason: reason.clone(),
+        target_type,
+        target_id,
+        reason,
         imposed_by,
         imposed_at: now,
         duration_secs,
@@ -1852,6 +2648,18 @@ pub fn suspend_target(target_type: SuspensionTargetType, target_id: String, reas
         notes: None,
     };
     SUSPENSIONS.with(|s| s.borrow_mut().push(suspension.clone()));
+    suspension
+}
+
+// --- Suspension & Appeals Endpoints ---
+#[ic_cdk::update]
+pub fn suspend_target(target_type: SuspensionTargetType, target_id: String, reason: String, duration_secs: Option<u64>) -> Result<Suspension, ApiError> {
+    let imposed_by = caller();
+    let now = ic_cdk::api::time() / 1_000_000;
+    require_permission(imposed_by, Permission::SuspendTargets)?;
+    check_suspension_scope(imposed_by, &target_type, &target_id, duration_secs)?;
+    require_non_empty("reason", &reason)?;
+    let suspension = impose_suspension(target_type.clone(), target_id.clone(), reason.clone(), imposed_by, duration_secs, now);
     log_admin_action(
         imposed_by,
         "suspend_target",
@@ -1859,51 +2667,81 @@ pub fn suspend_target(target_type: SuspensionTargetType, target_id: String, reas
         &target_id,
         Some(format!("Suspension imposed: {}", reason)),
     );
-    Some(suspension)
+    Ok(suspension)
 }
 
 #[ic_cdk::update]
-pub fn lift_suspension(suspension_id: u64, notes: Option<String>) -> bool {
+pub fn lift_suspension(suspension_id: u64, notes: Option<String>) -> Result<(), ApiError> {
     let lifter = caller();
     let now = ic_cdk::api::time() / 1_000_000;
-    if !is_admin(lifter) {
-        return false;
-    }
-    let mut success = false;
-    let mut target_type = String::new();
-    let mut target_id = String::new();
-    SUSPENSIONS.with(|s| {
+    require_permission(lifter, Permission::LiftSuspensions)?;
+    require_ban_authority(lifter, suspension_id)?;
+    let (target_type, target_id) = SUSPENSIONS.with(|s| {
         let mut s = s.borrow_mut();
-        if let Some(susp) = s.iter_mut().find(|s| s.id == suspension_id && s.status == SuspensionStatus::Active) {
-            susp.status = SuspensionStatus::Lifted;
-            susp.lifted_by = Some(lifter);
-            susp.lifted_at = Some(now);
-            susp.notes = notes.clone();
-            target_type = format!("{:?}", susp.target_type);
-            target_id = susp.target_id.clone();
-            success = true;
+        let susp = s.iter_mut().find(|s| s.id == suspension_id).ok_or_else(|| ApiError::not_found("suspension"))?;
+        if susp.status != SuspensionStatus::Active {
+            return Err(ApiError::conflict("suspension is not active"));
         }
-    });
-    if success {
-        log_admin_action(
-            lifter,
-            "lift_suspension",
-            &target_type,
-            &target_id,
-            Some(format!("Suspension {} lifted", suspension_id)),
-        );
+        susp.status = SuspensionStatus::Lifted;
+        susp.lifted_by = Some(lifter);
+        susp.lifted_at = Some(now);
+        susp.notes = notes.clone();
+        Ok((format!("{:?}", susp.target_type), susp.target_id.clone()))
+    })?;
+    end_ban(suspension_id, lifter, now);
+    log_admin_action(
+        lifter,
+        "lift_suspension",
+        &target_type,
+        &target_id,
+        Some(format!("Suspension {} lifted", suspension_id)),
+    );
+    Ok(())
+}
+
+/// Bans are imposed by admins only, so only admins may lift one or decide its appeal, whatever
+/// the permission matrix grants for ordinary suspensions.
+fn require_ban_authority(principal: Principal, suspension_id: u64) -> Result<(), ApiError> {
+    let ban_backed = BANS.with(|bans| bans.borrow().iter().any(|b| b.suspension_id == suspension_id));
+    if ban_backed && !is_admin(principal) {
+        return Err(ApiError::unauthorized("only admins can lift a ban or review its appeals"));
     }
-    success
+    Ok(())
+}
+
+/// Anyone who can impose, lift or review suspensions can list them and their appeals.
+const SUSPENSION_PERMISSIONS: [Permission; 3] = [Permission::SuspendTargets, Permission::LiftSuspensions, Permission::ReviewAppeals];
+
+#[ic_cdk::query]
+pub fn list_suspensions() -> Result<Vec<Suspension>, ApiError> {
+    require_any_permission(caller(), &SUSPENSION_PERMISSIONS)?;
+    Ok(SUSPENSIONS.with(|s| s.borrow().clone()))
+}
+
+fn filter_suspensions(filter: &SuspensionFilter) -> Vec<Suspension> {
+    SUSPENSIONS.with(|s| {
+        s.borrow()
+            .iter()
+            .filter(|s| matches_filter(&filter.status, &s.status) && matches_filter(&filter.target_type, &s.target_type))
+            .filter(|s| in_range(s.imposed_at, filter.from, filter.to))
+            .cloned()
+            .collect()
+    })
 }
 
 #[ic_cdk::query]
-pub fn list_suspensions() -> Vec<Suspension> {
-    SUSPENSIONS.with(|s| s.borrow().clone())
+pub fn query_suspensions(filter: SuspensionFilter, page: PageRequest) -> Result<Page<Suspension>, ApiError> {
+    require_any_permission(caller(), &SUSPENSION_PERMISSIONS)?;
+    Ok(paginate(filter_suspensions(&filter), &page))
 }
 
 #[ic_cdk::update]
-pub fn submit_suspension_appeal(suspension_id: u64, content: String) -> Option<SuspensionAppeal> {
+pub fn submit_suspension_appeal(suspension_id: u64, content: String) -> Result<SuspensionAppeal, ApiError> {
     let submitted_by = caller();
+    require_non_empty("content", &content)?;
+    if !SUSPENSIONS.with(|s| s.borrow().iter().any(|s| s.id == suspension_id)) {
+        return Err(ApiError::not_found("suspension"));
+    }
     let now = ic_cdk::api::time() / 1_000_000;
     let id = SUSPENSION_APPEAL_ID.with(|aid| {
         let mut aid = aid.borrow_mut();
@@ -1923,45 +2761,251 @@ pub fn submit_suspension_appeal(suspension_id: u64, content: String) -> Option<S
         notes: None,
     };
     SUSPENSION_APPEALS.with(|a| a.borrow_mut().push(appeal.clone()));
-    Some(appeal)
+    Ok(appeal)
 }
 
 #[ic_cdk::update]
-pub fn review_suspension_appeal(appeal_id: u64, status: AppealStatus, notes: Option<String>) -> bool {
+pub fn review_suspension_appeal(appeal_id: u64, status: AppealStatus, notes: Option<String>) -> Result<(), ApiError> {
     let reviewer = caller();
     let now = ic_cdk::api::time() / 1_000_000;
-    if !is_admin(reviewer) {
-        return false;
-    }
-    let mut success = false;
-    let mut suspension_id = 0u64;
+    require_permission(reviewer, Permission::ReviewAppeals)?;
     let status_for_log = status.clone();
-    SUSPENSION_APPEALS.with(|a| {
+    let suspension_id = SUSPENSION_APPEALS.with(|a| {
         let mut a = a.borrow_mut();
-        if let Some(appeal) = a.iter_mut().find(|ap| ap.id == appeal_id) {
-            appeal.status = status;
-            appeal.reviewed_by = Some(reviewer);
-            appeal.reviewed_at = Some(now);
-            appeal.notes = notes.clone();
-            suspension_id = appeal.suspension_id;
-            success = true;
+        let appeal = a.iter_mut().find(|ap| ap.id == appeal_id).ok_or_else(|| ApiError::not_found("appeal"))?;
+        if appeal.status != AppealStatus::Pending {
+            return Err(ApiError::conflict("appeal has already been reviewed"));
+        }
+        require_ban_authority(reviewer, appeal.suspension_id)?;
+        appeal.status = status.clone();
+        appeal.reviewed_by = Some(reviewer);
+        appeal.reviewed_at = Some(now);
+        appeal.notes = notes.clone();
+        Ok::<_, ApiError>(appeal.suspension_id)
+    })?;
+    log_admin_action(
+        reviewer,
+        "review_suspension_appeal",
+        "SuspensionAppeal",
+        &suspension_id.to_string(),
+        Some(format!("Appeal {} reviewed: {:?}", appeal_id, status_for_log)),
+    );
+    if status == AppealStatus::Approved {
+        if let Some((target_type, target_id)) = lift_on_appeal(suspension_id, appeal_id, reviewer, now) {
+            let details = format!("Suspension {} lifted on appeal {}", suspension_id, appeal_id);
+            log_admin_action(reviewer, "lift_suspension", &target_type, &target_id, Some(details));
         }
+    }
+    Ok(())
+}
+
+/// Lifts the suspension an approved appeal was about, unless it already ended. Returns the
+/// lifted target's type and id.
+fn lift_on_appeal(suspension_id: u64, appeal_id: u64, reviewer: Principal, now: u64) -> Option<(String, String)> {
+    SUSPENSIONS.with(|s| {
+        let mut s = s.borrow_mut();
+        let susp = s.iter_mut().find(|s| s.id == suspension_id && s.status == SuspensionStatus::Active)?;
+        susp.status = SuspensionStatus::Lifted;
+        susp.lifted_by = Some(reviewer);
+        susp.lifted_at = Some(now);
+        susp.notes = Some(format!("Lifted on appeal {}", appeal_id));
+        Some((format!("{:?}", susp.target_type), susp.target_id.clone()))
+    })
+    .inspect(|_| end_ban(suspension_id, reviewer, now))
+}
+
+#[ic_cdk::query]
+pub fn list_suspension_appeals() -> Result<Vec<SuspensionAppeal>, ApiError> {
+    require_any_permission(caller(), &SUSPENSION_PERMISSIONS)?;
+    Ok(SUSPENSION_APPEALS.with(|a| a.borrow().clone()))
+}
+
+fn filter_suspension_appeals(filter: &AppealFilter) -> Vec<SuspensionAppeal> {
+    let target_types: Vec<(u64, SuspensionTargetType)> =
+        SUSPENSIONS.with(|s| s.borrow().iter().map(|s| (s.id, s.target_type.clone())).collect());
+    let target_type_of = |suspension_id: u64| target_types.iter().find(|(id, _)| *id == suspension_id).map(|(_, t)| t.clone());
+    SUSPENSION_APPEALS.with(|a| {
+        a.borrow()
+            .iter()
+            .filter(|ap| matches_filter(&filter.status, &ap.status) && in_range(ap.submitted_at, filter.from, filter.to))
+            .filter(|ap| filter.target_type.is_none() || target_type_of(ap.suspension_id) == filter.target_type)
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+pub fn query_suspension_appeals(filter: AppealFilter, page: PageRequest) -> Result<Page<SuspensionAppeal>, ApiError> {
+    require_any_permission(caller(), &SUSPENSION_PERMISSIONS)?;
+    Ok(paginate(filter_suspension_appeals(&filter), &page))
+}
+
+// --- Bans ---
+// A ban wraps a User suspension, so `submit_suspension_appeal` with the ban's suspension id is
+// how a banned user appeals, and an approved appeal, `lift_suspension`, `unban_user` or expiry
+// all end the ban and restore the user's previous role. Ingress from a banned principal is
+// rejected in `inspect_message` except for appeals; `require_active_user` refuses everything
+// else that reaches the canister. Queries skip `inspect_message`, so the query guards check the
+// ban themselves: banned principals see no tracks (`can_view_track`) and their own records are
+// refused through `require_not_banned`. `get_my_ban` stays open so they know what to appeal.
+
+/// The principal's ban, if it is still in force.
+fn active_ban(principal: Principal) -> Option<Ban> {
+    let ban = BANS.with(|bans| bans.borrow().iter().find(|b| b.principal == principal && b.ended_at.is_none()).cloned())?;
+    let in_force = SUSPENSIONS.with(|s| {
+        s.borrow().iter().any(|s| s.id == ban.suspension_id && s.status == SuspensionStatus::Active)
     });
-    if success {
-        log_admin_action(
-            reviewer,
-            "review_suspension_appeal",
-            "SuspensionAppeal",
-            &suspension_id.to_string(),
-            Some(format!("Appeal {} reviewed: {:?}", appeal_id, status_for_log)),
-        );
+    in_force.then_some(ban)
+}
+
+fn require_not_banned(principal: Principal) -> Result<(), ApiError> {
+    match active_ban(principal) {
+        Some(ban) => Err(ApiError::Suspended { reason: ban_message(&ban) }),
+        None => Ok(()),
+    }
+}
+
+fn ban_message(ban: &Ban) -> String {
+    let until = match ban.expires_at {
+        Some(at) => format!("until {}", at),
+        None => "permanently".to_string(),
+    };
+    format!("banned {} (appeal suspension {}): {}", until, ban.suspension_id, ban.reason)
+}
+
+/// Whether an Owner of `track` belongs to a principal banned with their tracks hidden.
+fn hidden_by_ban(track: &Track) -> bool {
+    let hiding: Vec<Principal> = BANS.with(|bans| {
+        bans.borrow().iter().filter(|b| b.hide_tracks && b.ended_at.is_none()).map(|b| b.principal).collect()
+    });
+    if hiding.is_empty() {
+        return false;
+    }
+    let owners: Vec<Principal> = ARTISTS.with(|artists| {
+        artists
+            .borrow()
+            .iter()
+            .filter(|a| track.roles.iter().any(|(id, role)| *id == a.id && *role == TrackRole::Owner))
+            .map(|a| a.user_principal)
+            .collect()
+    });
+    owners.into_iter().filter(|p| hiding.contains(p)).any(|p| active_ban(p).is_some())
+}
+
+fn ban_as(
+    admin: Principal,
+    target: Principal,
+    reason: String,
+    duration_secs: Option<u64>,
+    hide_tracks: bool,
+    now: u64,
+) -> Result<Ban, ApiError> {
+    require_non_empty("reason", &reason)?;
+    if target == admin {
+        return Err(ApiError::invalid_input("principal_to_ban", "admins cannot ban themselves"));
+    }
+    if active_ban(target).is_some() {
+        return Err(ApiError::conflict("principal is already banned"));
+    }
+    let suspension = impose_suspension(SuspensionTargetType::User, target.to_text(), reason.clone(), admin, duration_secs, now);
+    let previous_role = set_role(target, UserRole::Banned, admin, now);
+    let ban = Ban {
+        suspension_id: suspension.id,
+        principal: target,
+        reason,
+        banned_by: admin,
+        banned_at: now,
+        expires_at: duration_secs.map(|secs| now.saturating_add(secs.saturating_mul(1_000))),
+        hide_tracks,
+        previous_role,
+        ended_at: None,
+    };
+    BANS.with(|bans| bans.borrow_mut().push(ban.clone()));
+    Ok(ban)
+}
+
+/// Closes the ban tied to a suspension that just stopped being Active, restoring the user's role.
+fn end_ban(suspension_id: u64, actor: Principal, now: u64) {
+    let ended = BANS.with(|bans| {
+        let mut bans = bans.borrow_mut();
+        let ban = bans.iter_mut().find(|b| b.suspension_id == suspension_id && b.ended_at.is_none())?;
+        ban.ended_at = Some(now);
+        Some(ban.clone())
+    });
+    let Some(ban) = ended else { return };
+    if find_user(ban.principal).is_some_and(|user| user.role == UserRole::Banned) {
+        set_role(ban.principal, ban.previous_role.unwrap_or(UserRole::User), actor, now);
     }
-    success
 }
 
+fn unban_as(admin: Principal, target: Principal, notes: Option<String>, now: u64) -> Result<Ban, ApiError> {
+    let ban = active_ban(target).ok_or_else(|| ApiError::not_found("ban"))?;
+    SUSPENSIONS.with(|s| {
+        if let Some(susp) = s.borrow_mut().iter_mut().find(|s| s.id == ban.suspension_id) {
+            susp.status = SuspensionStatus::Lifted;
+            susp.lifted_by = Some(admin);
+            susp.lifted_at = Some(now);
+            susp.notes = notes;
+        }
+    });
+    end_ban(ban.suspension_id, admin, now);
+    Ok(Ban { ended_at: Some(now), ..ban })
+}
+
+/// Bans a principal until `duration_secs` passes, or for good. `hide_tracks` hides every track
+/// their artists own while the ban lasts.
+#[ic_cdk::update]
+pub fn ban_user(principal_to_ban: Principal, reason: String, duration_secs: Option<u64>, hide_tracks: bool) -> Result<Ban, ApiError> {
+    let principal = caller();
+    require_admin(principal)?;
+    let now = ic_cdk::api::time() / 1_000_000;
+    let ban = ban_as(principal, principal_to_ban, reason, duration_secs, hide_tracks, now)?;
+    log_admin_action(
+        principal,
+        "ban_user",
+        "User",
+        &principal_to_ban.to_text(),
+        Some(format!("{}; tracks {}", ban_message(&ban), if hide_tracks { "hidden" } else { "visible" })),
+    );
+    Ok(ban)
+}
+
+#[ic_cdk::update]
+pub fn unban_user(principal_to_unban: Principal, notes: Option<String>) -> Result<Ban, ApiError> {
+    let principal = caller();
+    require_admin(principal)?;
+    let now = ic_cdk::api::time() / 1_000_000;
+    let ban = unban_as(principal, principal_to_unban, notes.clone(), now)?;
+    log_admin_action(
+        principal,
+        "unban_user",
+        "User",
+        &principal_to_unban.to_text(),
+        Some(format!("Ban lifted (suspension {}){}", ban.suspension_id, notes.map(|n| format!(": {}", n)).unwrap_or_default())),
+    );
+    Ok(ban)
+}
+
+#[ic_cdk::query]
+pub fn list_bans() -> Result<Vec<Ban>, ApiError> {
+    require_admin(caller())?;
+    Ok(BANS.with(|bans| bans.borrow().clone()))
+}
+
+/// The caller's ban, if any, so they know which suspension to appeal.
 #[ic_cdk::query]
-pub fn list_suspension_appeals() -> Vec<SuspensionAppeal> {
-    SUSPENSION_APPEALS.with(|a| a.borrow().clone())
+pub fn get_my_ban() -> Option<Ban> {
+    active_ban(caller())
+}
+
+/// Banned principals only get appeals through to the canister.
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    let method = ic_cdk::api::call::method_name();
+    if method != "submit_suspension_appeal" && active_ban(caller()).is_some() {
+        return;
+    }
+    ic_cdk::api::call::accept_message();
 }
 
 // Simple content check function
@@ -1980,37 +3024,39 @@ fn check_content_for_banned_words(content: &str) -> Option<String> {
 // Auto-flag content if it contains banned words
 fn auto_flag_content_if_needed(target_type: ModerationTargetType, target_id: String, content: &str) {
     if let Some(reason) = check_content_for_banned_words(content) {
-        let flagged_by = None; // Auto-flagged
-        let now = ic_cdk::api::time() / 1_000_000;
-        let id = MODERATION_QUEUE_ID.with(|mid| {
-            let mut mid = mid.borrow_mut();
-            let id = *mid;
-            *mid += 1;
-            id
-        });
-        let item = ModerationQueueItem {
-            id,
-            target_type,
-            target_id,
-            flagged_by,
-            reason,
-            status: ModerationStatus::Pending,
-            created_at: now,
-            reviewed_by: None,
-            reviewed_at: None,
-            notes: Some("Auto-flagged by system".to_string()),
-        };
-        MODERATION_QUEUE.with(|q| q.borrow_mut().push(item));
+        push_moderation_item(target_type, target_id, reason, "Auto-flagged by system", ic_cdk::api::time() / 1_000_000);
     }
 }
 
+/// Queues an item flagged by the system rather than a user.
+fn push_moderation_item(target_type: ModerationTargetType, target_id: String, reason: String, notes: &str, now: u64) {
+    let id = MODERATION_QUEUE_ID.with(|mid| {
+        let mut mid = mid.borrow_mut();
+        let id = *mid;
+        *mid += 1;
+        id
+    });
+    let item = ModerationQueueItem {
+        id,
+        target_type,
+        target_id,
+        flagged_by: None,
+        reason,
+        status: ModerationStatus::Pending,
+        created_at: now,
+        reviewed_by: None,
+        reviewed_at: None,
+        notes: Some(notes.to_string()),
+    };
+    MODERATION_QUEUE.with(|q| q.borrow_mut().push(item));
+}
+
 // --- Automated Content Moderation Endpoints (Backend Only) ---
 #[ic_cdk::update]
-fn add_banned_keyword(keyword: String) -> bool {
+fn add_banned_keyword(keyword: String) -> Result<(), ApiError> {
     let admin = caller();
-    if !is_admin(admin) {
-        return false;
-    }
+    require_permission(admin, Permission::ManageKeywords)?;
+    require_non_empty("keyword", &keyword)?;
     let keyword_lower = keyword.to_lowercase();
     BANNED_KEYWORDS.with(|keywords| {
         let mut keywords = keywords.borrow_mut();
@@ -2023,34 +3069,32 @@ fn add_banned_keyword(keyword: String) -> bool {
                 &keyword,
                 Some("Banned keyword added".to_string()),
             );
-            return true;
+            return Ok(());
         }
-        false
+        Err(ApiError::conflict("keyword is already banned"))
     })
 }
 
 #[ic_cdk::update]
-fn remove_banned_keyword(keyword: String) -> bool {
+fn remove_banned_keyword(keyword: String) -> Result<(), ApiError> {
     let admin = caller();
-    if !is_admin(admin) {
-        return false;
-    }
+    require_permission(admin, Permission::ManageKeywords)?;
     let keyword_lower = keyword.to_lowercase();
     BANNED_KEYWORDS.with(|keywords| {
         let mut keywords = keywords.borrow_mut();
         let len_before = keywords.len();
         keywords.retain(|k| k != &keyword_lower);
-        let removed = keywords.len() < len_before;
-        if removed {
-            log_admin_action(
-                admin,
-                "remove_banned_keyword",
-                "Keyword",
-                &keyword,
-                Some("Banned keyword removed".to_string()),
-            );
+        if keywords.len() == len_before {
+            return Err(ApiError::not_found("keyword"));
         }
-        removed
+        log_admin_action(
+            admin,
+            "remove_banned_keyword",
+            "Keyword",
+            &keyword,
+            Some("Banned keyword removed".to_string()),
+        );
+        Ok(())
     })
 }
 
@@ -2061,68 +3105,51 @@ pub fn list_banned_keywords() -> Vec<String> {
 
 // --- Enhanced Version Management ---
 #[ic_cdk::update]
-fn revert_to_version(track_id: u64, version_number: u32) -> Option<Track> {
+fn revert_to_version(track_id: u64, version_number: u32) -> Result<Track, ApiError> {
     let now = ic_cdk::api::time() / 1_000_000;
     let reverter = caller();
     
     // Check if user has permission to modify this track
-    let has_permission = TRACKS.with(|tracks| {
-        if let Some(track) = tracks.borrow().iter().find(|t| t.id == track_id) {
-            track.contributors.contains(&1) // Simple check - in real app, check actual user ID
-        } else {
-            false
-        }
-    });
-    
-    if !has_permission {
-        return None;
-    }
+    let track = find_track(track_id).ok_or_else(|| ApiError::not_found("track"))?;
+    require_track_role(reverter, &track, TrackRole::Collaborator)?;
     
     TRACK_VERSIONS.with(|tv| {
         let mut tv = tv.borrow_mut();
-        if let Some((_, versions)) = tv.iter_mut().find(|(tid, _)| *tid == track_id) {
-            if let Some(target_version) = versions.iter().find(|v| v.version == version_number) {
-                // Clone the target version data to avoid borrow checker issues
-                let target_title = target_version.title.clone();
-                let target_description = target_version.description.clone();
-                let target_contributors = target_version.contributors.clone();
-                
-                // Create a new version that reverts to the target version
-                let new_version_num = versions.len() as u32 + 1;
-                let revert_version = TrackVersion {
-                    version: new_version_num,
-                    title: target_title.clone(),
-                    description: target_description.clone(),
-                    contributors: target_contributors.clone(),
-                    changed_by: reverter,
-                    changed_at: now,
-                    change_description: Some(format!("Reverted to version {}", version_number)),
-                };
-                versions.push(revert_version);
-                
-                // Update the main track
-                TRACKS.with(|tracks| {
-                    if let Some(track) = tracks.borrow_mut().iter_mut().find(|t| t.id == track_id) {
-                        track.title = target_title;
-                        track.description = target_description;
-                        track.contributors = target_contributors;
-                        track.version = new_version_num;
-                        Some(track.clone())
-                    } else {
-                        None
-                    }
-                })
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+        let (_, versions) = tv.iter_mut().find(|(tid, _)| *tid == track_id).ok_or_else(|| ApiError::not_found("track versions"))?;
+        let target_version = versions.iter().find(|v| v.version == version_number).ok_or_else(|| ApiError::not_found("version"))?;
+        // Clone the target version data to avoid borrow checker issues
+        let target_title = target_version.title.clone();
+        let target_description = target_version.description.clone();
+        let target_contributors = target_version.contributors.clone();
+        
+        // Create a new version that reverts to the target version
+        let new_version_num = versions.len() as u32 + 1;
+        let revert_version = TrackVersion {
+            version: new_version_num,
+            title: target_title.clone(),
+            description: target_description.clone(),
+            contributors: target_contributors.clone(),
+            changed_by: reverter,
+            changed_at: now,
+            change_description: Some(format!("Reverted to version {}", version_number)),
+        };
+        versions.push(revert_version);
+        
+        // Update the main track
+        TRACKS.with(|tracks| {
+            let mut tracks = tracks.borrow_mut();
+            let track = tracks.iter_mut().find(|t| t.id == track_id).ok_or_else(|| ApiError::not_found("track"))?;
+            track.title = target_title;
+            track.description = target_description;
+            track.contributors = target_contributors;
+            track.version = new_version_num;
+            Ok(track.clone())
+        })
     })
 }
 
 #[ic_cdk::query]
-fn get_version_history(track_id: u64) -> Vec<TrackVersion> {
+fn get_version_history(track_id: u64) -> Result<Vec<TrackVersion>, ApiError> {
     get_track_versions(track_id)
 }
 
@@ -2139,34 +3166,28 @@ pub struct VersionComparison {
 }
 
 #[ic_cdk::query]
-fn compare_versions(track_id: u64, version1: u32, version2: u32) -> Option<VersionComparison> {
+fn compare_versions(track_id: u64, version1: u32, version2: u32) -> Result<VersionComparison, ApiError> {
+    require_track_visible(track_id)?;
     TRACK_VERSIONS.with(|tv| {
         let tv = tv.borrow();
-        if let Some((_, versions)) = tv.iter().find(|(tid, _)| *tid == track_id) {
-            let v1 = versions.iter().find(|v| v.version == version1);
-            let v2 = versions.iter().find(|v| v.version == version2);
-            
-            if let (Some(ver1), Some(ver2)) = (v1, v2) {
-                let title_changed = ver1.title != ver2.title;
-                let description_changed = ver1.description != ver2.description;
-                let contributors_changed = ver1.contributors != ver2.contributors;
-                
-                Some(VersionComparison {
-                    version1,
-                    version2,
-                    title_changed,
-                    description_changed,
-                    contributors_changed,
-                    title_diff: if title_changed { Some(format!("{} -> {}", ver1.title, ver2.title)) } else { None },
-                    description_diff: if description_changed { Some(format!("{} -> {}", ver1.description, ver2.description)) } else { None },
-                    contributors_diff: if contributors_changed { Some(format!("{:?} -> {:?}", ver1.contributors, ver2.contributors)) } else { None },
-                })
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+        let (_, versions) = tv.iter().find(|(tid, _)| *tid == track_id).ok_or_else(|| ApiError::not_found("track versions"))?;
+        let ver1 = versions.iter().find(|v| v.version == version1).ok_or_else(|| ApiError::not_found("version1"))?;
+        let ver2 = versions.iter().find(|v| v.version == version2).ok_or_else(|| ApiError::not_found("version2"))?;
+
+        let title_changed = ver1.title != ver2.title;
+        let description_changed = ver1.description != ver2.description;
+        let contributors_changed = ver1.contributors != ver2.contributors;
+
+        Ok(VersionComparison {
+            version1,
+            version2,
+            title_changed,
+            description_changed,
+            contributors_changed,
+            title_diff: if title_changed { Some(format!("{} -> {}", ver1.title, ver2.title)) } else { None },
+            description_diff: if description_changed { Some(format!("{} -> {}", ver1.description, ver2.description)) } else { None },
+            contributors_diff: if contributors_changed { Some(format!("{:?} -> {:?}", ver1.contributors, ver2.contributors)) } else { None },
+        })
     })
 }
 
@@ -2221,7 +3242,8 @@ pub struct PlatformAnalytics {
 
 // --- Advanced Analytics Endpoints ---
 #[ic_cdk::query]
-fn get_track_performance_metrics(track_id: u64) -> Option<TrackPerformanceMetrics> {
+fn get_track_performance_metrics(track_id: u64) -> Result<TrackPerformanceMetrics, ApiError> {
+    require_track_visible(track_id)?;
     TRACKS.with(|tracks| {
         if let Some(track) = tracks.borrow().iter().find(|t| t.id == track_id) {
             let total_plays = track.play_count;
@@ -2246,7 +3268,7 @@ fn get_track_performance_metrics(track_id: u64) -> Option<TrackPerformanceMetric
             
             let growth_rate = 0.0; // Would need historical data
             
-            Some(TrackPerformanceMetrics {
+            Ok(TrackPerformanceMetrics {
                 track_id,
                 total_plays,
                 unique_listeners,
@@ -2259,13 +3281,19 @@ fn get_track_performance_metrics(track_id: u64) -> Option<TrackPerformanceMetric
                 growth_rate,
             })
         } else {
-            None
+            Err(ApiError::not_found("track"))
         }
     })
 }
 
+// Engagement with the artist's tracks that the caller can see
 #[ic_cdk::query]
-fn get_user_engagement_metrics(user_id: u64) -> Option<UserEngagementMetrics> {
+fn get_user_engagement_metrics(user_id: u64) -> Result<UserEngagementMetrics, ApiError> {
+    user_engagement_metrics(caller(), user_id)
+}
+
+fn user_engagement_metrics(viewer: Principal, user_id: u64) -> Result<UserEngagementMetrics, ApiError> {
+    get_artist(user_id)?;
     let mut total_tracks_created = 0;
     let mut total_plays_received = 0;
     let mut total_revenue_earned = 0;
@@ -2273,7 +3301,7 @@ fn get_user_engagement_metrics(user_id: u64) -> Option<UserEngagementMetrics> {
     let mut rating_sum = 0;
     
     TRACKS.with(|tracks| {
-        for track in tracks.borrow().iter() {
+        for track in tracks.borrow().iter().filter(|t| can_view_track(viewer, t)) {
             if track.contributors.contains(&user_id) {
                 total_tracks_created += 1;
                 total_plays_received += track.play_count;
@@ -2303,7 +3331,7 @@ fn get_user_engagement_metrics(user_id: u64) -> Option<UserEngagementMetrics> {
         0.0
     };
     
-    Some(UserEngagementMetrics {
+    Ok(UserEngagementMetrics {
         user_id,
         total_tracks_created,
         total_plays_received,
@@ -2316,15 +3344,20 @@ fn get_user_engagement_metrics(user_id: u64) -> Option<UserEngagementMetrics> {
     })
 }
 
+// Revenue of the tracks the caller can see
 #[ic_cdk::query]
 fn get_revenue_insights() -> RevenueInsights {
+    revenue_insights(caller(), ic_cdk::api::time() / 1_000_000)
+}
+
+fn revenue_insights(viewer: Principal, now: u64) -> RevenueInsights {
     let mut total_platform_revenue = 0;
     let mut track_revenues = Vec::new();
     let mut artist_revenues = std::collections::HashMap::new();
     let mut genre_revenues = std::collections::HashMap::new();
     
     TRACKS.with(|tracks| {
-        for track in tracks.borrow().iter() {
+        for track in tracks.borrow().iter().filter(|t| can_view_track(viewer, t)) {
             let track_revenue = track.payments.iter().map(|p| p.amount).sum::<u64>();
             total_platform_revenue += track_revenue;
             
@@ -2345,17 +3378,17 @@ fn get_revenue_insights() -> RevenueInsights {
     });
     
     // Sort by revenue (descending)
-    track_revenues.sort_by(|a, b| b.1.cmp(&a.1));
+    track_revenues.sort_by_key(|e| std::cmp::Reverse(e.1));
     let top_earning_tracks = track_revenues.into_iter().take(10).collect();
     
     let mut artist_revenue_vec: Vec<(u64, u64)> = artist_revenues.into_iter().collect();
-    artist_revenue_vec.sort_by(|a, b| b.1.cmp(&a.1));
+    artist_revenue_vec.sort_by_key(|e| std::cmp::Reverse(e.1));
     let top_earning_artists = artist_revenue_vec.into_iter().take(10).collect();
     
     let mut genre_revenue_vec: Vec<(String, u64)> = genre_revenues.into_iter().collect();
-    genre_revenue_vec.sort_by(|a, b| b.1.cmp(&a.1));
+    genre_revenue_vec.sort_by_key(|e| std::cmp::Reverse(e.1));
     
-    let monthly_revenue_trend = vec![(ic_cdk::api::time() / 1_000_000, total_platform_revenue)]; // Simplified
+    let monthly_revenue_trend = vec![(now, total_platform_revenue)]; // Simplified
     
     RevenueInsights {
         total_platform_revenue,
@@ -2366,8 +3399,13 @@ fn get_revenue_insights() -> RevenueInsights {
     }
 }
 
+// Activity on the tracks the caller can see
 #[ic_cdk::query]
 fn get_platform_analytics() -> PlatformAnalytics {
+    platform_analytics(caller())
+}
+
+fn platform_analytics(viewer: Principal) -> PlatformAnalytics {
     let mut total_tracks = 0;
     let mut total_plays = 0;
     let mut total_revenue = 0;
@@ -2377,7 +3415,7 @@ fn get_platform_analytics() -> PlatformAnalytics {
     let mut user_activity = std::collections::HashMap::new();
     
     TRACKS.with(|tracks| {
-        for track in tracks.borrow().iter() {
+        for track in tracks.borrow().iter().filter(|t| can_view_track(viewer, t)) {
             total_tracks += 1;
             total_plays += track.play_count;
             total_revenue += track.payments.iter().map(|p| p.amount).sum::<u64>();
@@ -2404,11 +3442,11 @@ fn get_platform_analytics() -> PlatformAnalytics {
     };
     
     let mut genre_vec: Vec<(String, u64)> = genre_counts.into_iter().collect();
-    genre_vec.sort_by(|a, b| b.1.cmp(&a.1));
+    genre_vec.sort_by_key(|e| std::cmp::Reverse(e.1));
     let most_popular_genres = genre_vec.into_iter().take(10).collect();
     
     let mut user_activity_vec: Vec<(u64, u64)> = user_activity.into_iter().collect();
-    user_activity_vec.sort_by(|a, b| b.1.cmp(&a.1));
+    user_activity_vec.sort_by_key(|e| std::cmp::Reverse(e.1));
     let most_active_users = user_activity_vec.into_iter().take(10).collect();
     
     PlatformAnalytics {
@@ -2473,7 +3511,9 @@ pub struct WorkflowTemplate {
 
 // --- Collaborative Workflow Management Endpoints ---
 #[ic_cdk::update]
-fn create_workflow_step(track_id: u64, step_name: String, assigned_to: Vec<u64>, due_date: Option<u64>, notes: Option<String>) -> Option<WorkflowStep> {
+fn create_workflow_step(track_id: u64, step_name: String, assigned_to: Vec<u64>, due_date: Option<u64>, notes: Option<String>) -> Result<WorkflowStep, ApiError> {
+    require_non_empty("step_name", &step_name)?;
+    with_track_as(track_id, TrackRole::Collaborator, |_| ())?;
     let step_id = WORKFLOW_STEP_ID.with(|id| {
         let mut id_mut = id.borrow_mut();
         let current_id = *id_mut;
@@ -2481,7 +3521,6 @@ fn create_workflow_step(track_id: u64, step_name: String, assigned_to: Vec<u64>,
         current_id
     });
     
-    let now = ic_cdk::api::time() / 1_000_000;
     let step = WorkflowStep {
         id: step_id,
         track_id,
@@ -2498,12 +3537,15 @@ fn create_workflow_step(track_id: u64, step_name: String, assigned_to: Vec<u64>,
         steps.borrow_mut().push(step.clone());
     });
     
-    Some(step)
+    Ok(step)
 }
 
 #[ic_cdk::update]
-fn update_workflow_step_status(step_id: u64, status: WorkflowStatus, notes: Option<String>) -> Option<WorkflowStep> {
+fn update_workflow_step_status(step_id: u64, status: WorkflowStatus, notes: Option<String>) -> Result<WorkflowStep, ApiError> {
     let now = ic_cdk::api::time() / 1_000_000;
+    let track_id = WORKFLOW_STEPS.with(|steps| steps.borrow().iter().find(|s| s.id == step_id).map(|s| s.track_id))
+        .ok_or_else(|| ApiError::not_found("workflow step"))?;
+    with_track_as(track_id, TrackRole::Collaborator, |_| ())?;
     WORKFLOW_STEPS.with(|steps| {
         if let Some(step) = steps.borrow_mut().iter_mut().find(|s| s.id == step_id) {
             step.status = status.clone();
@@ -2513,22 +3555,25 @@ fn update_workflow_step_status(step_id: u64, status: WorkflowStatus, notes: Opti
             if matches!(status, WorkflowStatus::Published) {
                 step.completed_at = Some(now);
             }
-            Some(step.clone())
+            Ok(step.clone())
         } else {
-            None
+            Err(ApiError::not_found("workflow step"))
         }
     })
 }
 
 #[ic_cdk::query]
-fn get_track_workflow_steps(track_id: u64) -> Vec<WorkflowStep> {
-    WORKFLOW_STEPS.with(|steps| {
+fn get_track_workflow_steps(track_id: u64) -> Result<Vec<WorkflowStep>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(WORKFLOW_STEPS.with(|steps| {
         steps.borrow().iter().filter(|s| s.track_id == track_id).cloned().collect()
-    })
+    }))
 }
 
 #[ic_cdk::update]
-fn create_collaboration_session(track_id: u64, session_name: String, participants: Vec<u64>, notes: Option<String>) -> Option<CollaborationSession> {
+fn create_collaboration_session(track_id: u64, session_name: String, participants: Vec<u64>, notes: Option<String>) -> Result<CollaborationSession, ApiError> {
+    require_non_empty("session_name", &session_name)?;
+    with_track_as(track_id, TrackRole::Collaborator, |_| ())?;
     let session_id = SESSION_ID.with(|id| {
         let mut id_mut = id.borrow_mut();
         let current_id = *id_mut;
@@ -2552,34 +3597,43 @@ fn create_collaboration_session(track_id: u64, session_name: String, participant
         sessions.borrow_mut().push(session.clone());
     });
     
-    Some(session)
+    Ok(session)
 }
 
 #[ic_cdk::update]
-fn end_collaboration_session(session_id: u64, notes: Option<String>) -> Option<CollaborationSession> {
+fn end_collaboration_session(session_id: u64, notes: Option<String>) -> Result<CollaborationSession, ApiError> {
     let now = ic_cdk::api::time() / 1_000_000;
+    let track_id = COLLABORATION_SESSIONS.with(|sessions| sessions.borrow().iter().find(|s| s.id == session_id).map(|s| s.track_id))
+        .ok_or_else(|| ApiError::not_found("collaboration session"))?;
+    with_track_as(track_id, TrackRole::Collaborator, |_| ())?;
     COLLABORATION_SESSIONS.with(|sessions| {
         if let Some(session) = sessions.borrow_mut().iter_mut().find(|s| s.id == session_id) {
             session.end_time = Some(now);
             if let Some(ref new_notes) = notes {
                 session.notes = Some(new_notes.clone());
             }
-            Some(session.clone())
+            Ok(session.clone())
         } else {
-            None
+            Err(ApiError::not_found("collaboration session"))
         }
     })
 }
 
 #[ic_cdk::query]
-fn get_track_collaboration_sessions(track_id: u64) -> Vec<CollaborationSession> {
-    COLLABORATION_SESSIONS.with(|sessions| {
+fn get_track_collaboration_sessions(track_id: u64) -> Result<Vec<CollaborationSession>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(COLLABORATION_SESSIONS.with(|sessions| {
         sessions.borrow().iter().filter(|s| s.track_id == track_id).cloned().collect()
-    })
+    }))
 }
 
 #[ic_cdk::update]
-fn create_workflow_template(name: String, description: String, steps: Vec<String>, estimated_duration_days: u32, genre_specific: bool, target_genre: Option<String>) -> Option<WorkflowTemplate> {
+fn create_workflow_template(name: String, description: String, steps: Vec<String>, estimated_duration_days: u32, genre_specific: bool, target_genre: Option<String>) -> Result<WorkflowTemplate, ApiError> {
+    active_caller()?;
+    require_non_empty("name", &name)?;
+    if genre_specific && target_genre.is_none() {
+        return Err(ApiError::invalid_input("target_genre", "required for genre-specific templates"));
+    }
     let template_id = TEMPLATE_ID.with(|id| {
         let mut id_mut = id.borrow_mut();
         let current_id = *id_mut;
@@ -2601,7 +3655,7 @@ fn create_workflow_template(name: String, description: String, steps: Vec<String
         templates.borrow_mut().push(template.clone());
     });
     
-    Some(template)
+    Ok(template)
 }
 
 #[ic_cdk::query]
@@ -2619,74 +3673,3718 @@ fn get_workflow_templates_by_genre(genre: String) -> Vec<WorkflowTemplate> {
     })
 }
 
+// --- Ledger Integration ---
+// Payments and payouts move real tokens on an ICRC-1/ICRC-2 ledger. Payers approve this canister
+// as spender; their tokens are pulled into ROYALTIES_SUBACCOUNT, which backs the artist balances
+// in the royalty journal and funds withdrawals.
+
+/// Canister subaccount holding royalty funds: "royalties" zero-padded to 32 bytes.
+const ROYALTIES_SUBACCOUNT: [u8; 32] = *b"royalties\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct TrackFile {
-    pub track_id: u64,
-    pub filename: String,
-    pub content_type: String,
-    pub data: Vec<u8>,
-    pub uploaded_by: Principal,
-    pub uploaded_at: u64,
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
 }
 
-#[ic_cdk::update]
-pub fn upload_track_file(track_id: u64, filename: String, content_type: String, data: Vec<u8>) -> Result<(), String> {
-    let caller = ic_cdk::api::caller();
-    // Check file size
-    if data.len() > MAX_FILE_SIZE {
-        return Err("File too large (max 10MB)".to_string());
-    }
-    // Only owner or collaborator can upload
-    let track = get_track(track_id).ok_or("Track not found")?;
-    let user_id = get_user_id_by_principal(caller).ok_or("User not found")?;
-    let is_owner = track.contributors.get(0) == Some(&user_id);
-    let is_collab = track.contributors.contains(&user_id);
-    if !is_owner && !is_collab {
-        return Err("Not authorized to upload file for this track".to_string());
-    }
-    let now = ic_cdk::api::time() / 1_000_000;
-    let file = TrackFile {
-        track_id,
-        filename,
-        content_type,
-        data,
-        uploaded_by: caller,
-        uploaded_at: now,
-    };
-    TRACK_FILES.with(|files| files.borrow_mut().insert(track_id, file));
-    Ok(())
+#[derive(Clone, Debug, CandidType, Deserialize)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
 }
 
-#[ic_cdk::query]
-pub fn get_track_file(track_id: u64) -> Option<TrackFile> {
-    TRACK_FILES.with(|files| files.borrow().get(&track_id).map(|f| f.clone()))
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
 }
 
-// Helper to get user id by principal
-fn get_user_id_by_principal(principal: Principal) -> Option<u64> {
-    // This is a stub. Replace with your actual user lookup logic.
-    // For now, just return Some(1) for demo purposes.
-    Some(1)
+#[derive(Clone, Debug, CandidType, Deserialize)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
 }
 
-impl Storable for TrackFile {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
-    }
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
-    }
+fn nat_to_u64(n: &Nat) -> u64 {
+    u64::try_from(n.0.clone()).unwrap_or(u64::MAX)
 }
 
-impl BoundedStorable for TrackFile {
-    const MAX_SIZE: u32 = 10 * 1024 * 1024 + 1024; // 10MB + metadata buffer
-    const IS_FIXED_SIZE: bool = false;
+fn ledger_canister() -> Result<Principal, ApiError> {
+    LEDGER_CANISTER.with(|l| *l.borrow()).ok_or_else(|| ApiError::conflict("ledger canister is not configured"))
 }
 
-// --- Backend Administration Functions (Not exposed via Candid) ---
-// These functions can only be called internally or via backend management tools
+fn royalty_account() -> Account {
+    Account { owner: ic_cdk::id(), subaccount: Some(ROYALTIES_SUBACCOUNT.to_vec()) }
+}
 
-/// Add a banned keyword (backend only)
+fn ledger_error(reason: String) -> ApiError {
+    ApiError::Ledger { reason }
+}
+
+/// Pulls `amount` from `from`'s default account into the royalty subaccount via ICRC-2 `transfer_from`.
+async fn pull_payment(from: Principal, amount: u64, memo: Vec<u8>) -> Result<u64, ApiError> {
+    match transfer_from(from, amount, memo).await? {
+        Ok(block_index) => Ok(block_index),
+        Err(TransferFromError::InsufficientFunds { balance }) => Err(ApiError::InsufficientFunds { available: nat_to_u64(&balance) }),
+        Err(TransferFromError::InsufficientAllowance { allowance }) => {
+            Err(ledger_error(format!("allowance of {} does not cover the payment and fee; approve this canister first", allowance)))
+        }
+        Err(err) => Err(ledger_error(format!("icrc2_transfer_from failed: {:?}", err))),
+    }
+}
+
+/// The raw `icrc2_transfer_from` behind `pull_payment`, for callers that handle the ledger's
+/// errors themselves. The outer error means the call itself failed.
+async fn transfer_from(from: Principal, amount: u64, memo: Vec<u8>) -> Result<Result<u64, TransferFromError>, ApiError> {
+    let ledger = ledger_canister()?;
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account { owner: from, subaccount: None },
+        to: royalty_account(),
+        amount: Nat::from(amount),
+        fee: None,
+        memo: Some(memo),
+        created_at_time: Some(ic_cdk::api::time()),
+    };
+    let (result,): (Result<Nat, TransferFromError>,) = ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+        .await
+        .map_err(|(code, message)| ledger_error(format!("icrc2_transfer_from rejected ({:?}): {}", code, message)))?;
+    Ok(result.map(|block_index| nat_to_u64(&block_index)))
+}
+
+/// The ledger's current transfer fee.
+async fn ledger_fee() -> Result<u64, ApiError> {
+    let ledger = ledger_canister()?;
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, message)| ledger_error(format!("icrc1_fee rejected ({:?}): {}", code, message)))?;
+    Ok(nat_to_u64(&fee))
+}
+
+/// Sends `amount` minus the current ledger fee from the royalty subaccount to `to` via ICRC-1 `transfer`.
+/// Returns the block index and the fee charged.
+async fn send_payout(to: Principal, amount: u64) -> Result<(u64, u64), ApiError> {
+    let ledger = ledger_canister()?;
+    let fee = ledger_fee().await?;
+    if amount <= fee {
+        return Err(ApiError::invalid_input("amount", &format!("must exceed the ledger fee of {}", fee)));
+    }
+    let args = TransferArg {
+        from_subaccount: Some(ROYALTIES_SUBACCOUNT.to_vec()),
+        to: Account { owner: to, subaccount: None },
+        amount: Nat::from(amount - fee),
+        // Passing the fee explicitly makes the transfer fail rather than overdraw if it changes meanwhile
+        fee: Some(Nat::from(fee)),
+        memo: None,
+        created_at_time: Some(ic_cdk::api::time()),
+    };
+    let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (args,))
+        .await
+        .map_err(|(code, message)| ledger_error(format!("icrc1_transfer rejected ({:?}): {}", code, message)))?;
+    result
+        .map(|block_index| (nat_to_u64(&block_index), fee))
+        .map_err(|err| ledger_error(format!("icrc1_transfer failed: {:?}", err)))
+}
+
+#[ic_cdk::update]
+pub fn set_ledger_canister(ledger: Principal) -> Result<(), ApiError> {
+    let admin = caller();
+    require_admin(admin)?;
+    LEDGER_CANISTER.with(|l| *l.borrow_mut() = Some(ledger));
+    log_admin_action(admin, "set_ledger_canister", "ledger", &ledger.to_text(), None);
+    Ok(())
+}
+
+#[ic_cdk::query]
+pub fn get_ledger_canister() -> Option<Principal> {
+    LEDGER_CANISTER.with(|l| *l.borrow())
+}
+
+/// The account payments are collected in and withdrawals are paid from.
+#[ic_cdk::query]
+pub fn get_royalty_account() -> Account {
+    royalty_account()
+}
+
+// --- Royalty Journal ---
+// Every movement of royalty funds is an append-only, balanced transaction of journal entries.
+// Balances are never stored: Treasury is debits minus credits (tokens held), every other account
+// is credits minus debits (tokens owed). Reconciliation checks that the two sides agree.
+// Platform fees and advance recoupments are taken out of each artist's credited share and moved
+// to the Platform account in the same transaction, so every deduction is its own entry.
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum RoyaltyAccount {
+    Treasury,       // tokens held in ROYALTIES_SUBACCOUNT
+    Artist(u64),    // owed to an artist
+    PendingPayouts, // reserved for withdrawals and refunds awaiting the ledger
+    Platform,       // platform/label commission and recouped advances
+    Escrow(u64),    // pledges held for a crowdfunding campaign
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum JournalEntryKind {
+    OpeningBalance,
+    PaymentIn,
+    SplitCredit,
+    Remainder,
+    Withdrawal,
+    Fee,
+    Reversal,
+    PlatformFee,
+    Recoupment,
+    Pledge,
+    Refund,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct JournalEntry {
+    pub id: u64,
+    pub transaction_id: u64,
+    pub timestamp: u64,
+    pub kind: JournalEntryKind,
+    pub account: RoyaltyAccount,
+    pub debit: u64,
+    pub credit: u64,
+    pub track_id: Option<u64>,
+    pub block_index: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct RoyaltyReconciliation {
+    pub total_received: u64,   // payments pulled from payers
+    pub total_credited: u64,   // split credits and remainders owed to artists
+    pub total_paid_out: u64,   // withdrawals sent to artists and the platform, excluding fees
+    pub total_fees: u64,       // ledger fees on withdrawals
+    pub treasury_balance: u64, // what ROYALTIES_SUBACCOUNT should hold
+    pub artist_balances: u64,
+    pub pending_payouts: u64,
+    pub platform_balance: u64,
+    pub escrow_balance: u64, // pledges awaiting release or refund
+    pub balanced: bool,
+}
+
+/// One journal line: (kind, account, debit, credit).
+type JournalLine = (JournalEntryKind, RoyaltyAccount, u64, u64);
+
+/// Appends a transaction. Panics (trapping the call) if its debits and credits differ.
+fn post_transaction(timestamp: u64, track_id: Option<u64>, block_index: Option<u64>, lines: Vec<JournalLine>) -> u64 {
+    let debits: u64 = lines.iter().map(|l| l.2).sum();
+    let credits: u64 = lines.iter().map(|l| l.3).sum();
+    assert_eq!(debits, credits, "journal transaction does not balance");
+    let transaction_id = JOURNAL_TRANSACTION_ID.with(|tid| {
+        let mut tid = tid.borrow_mut();
+        let id = *tid;
+        *tid += 1;
+        id
+    });
+    JOURNAL_ENTRIES.with(|entries| {
+        let mut entries = entries.borrow_mut();
+        for (kind, account, debit, credit) in lines {
+            let id = JOURNAL_ENTRY_ID.with(|eid| {
+                let mut eid = eid.borrow_mut();
+                let id = *eid;
+                *eid += 1;
+                id
+            });
+            entries.push(JournalEntry { id, transaction_id, timestamp, kind, account, debit, credit, track_id, block_index });
+        }
+    });
+    transaction_id
+}
+
+fn account_balance(account: &RoyaltyAccount) -> u64 {
+    let (debits, credits) = JOURNAL_ENTRIES.with(|entries| {
+        entries.borrow().iter().filter(|e| &e.account == account).fold((0u64, 0u64), |(d, c), e| (d + e.debit, c + e.credit))
+    });
+    match account {
+        RoyaltyAccount::Treasury => debits.saturating_sub(credits),
+        _ => credits.saturating_sub(debits),
+    }
+}
+
+/// An artist id and the tokens allocated to it.
+type Share = (u64, u64);
+
+/// Splits `amount` by percentage, rounding each share down. The remainder goes to the largest
+/// split (the first one on ties) so no token is lost.
+fn royalty_shares(splits: &[Split], amount: u64) -> (Vec<Share>, Option<Share>) {
+    let shares: Vec<Share> = splits.iter().map(|s| (s.id, (amount as u128 * s.pct as u128 / 100) as u64)).collect();
+    let remainder = amount - shares.iter().map(|(_, share)| share).sum::<u64>();
+    let largest = splits.iter().rev().max_by_key(|s| s.pct).map(|s| s.id);
+    (shares, largest.filter(|_| remainder > 0).map(|id| (id, remainder)))
+}
+
+/// Credits each artist's share of a payment, then deducts the platform fee (`fee_bps` of the
+/// share, rounded down) and recoups outstanding advances from what is left.
+fn record_payment_in(track_id: Option<u64>, splits: &[Split], fee_bps: u16, amount: u64, block_index: u64, timestamp: u64) {
+    distribute_from(RoyaltyAccount::Treasury, track_id, splits, fee_bps, amount, Some(block_index), timestamp);
+}
+
+/// `record_payment_in` for funds already held in `source`, such as a campaign's escrow.
+fn distribute_from(
+    source: RoyaltyAccount,
+    track_id: Option<u64>,
+    splits: &[Split],
+    fee_bps: u16,
+    amount: u64,
+    block_index: Option<u64>,
+    timestamp: u64,
+) {
+    let (shares, remainder) = royalty_shares(splits, amount);
+    let mut lines = vec![(JournalEntryKind::PaymentIn, source, amount, 0)];
+    lines.extend(shares.iter().map(|&(id, share)| (JournalEntryKind::SplitCredit, RoyaltyAccount::Artist(id), 0, share)));
+    lines.extend(remainder.map(|(id, rest)| (JournalEntryKind::Remainder, RoyaltyAccount::Artist(id), 0, rest)));
+    for &(artist_id, share) in &shares {
+        let gross = share + remainder.filter(|(id, _)| *id == artist_id).map(|(_, rest)| rest).unwrap_or(0);
+        let fee = (gross as u128 * fee_bps as u128 / 10_000) as u64;
+        if fee > 0 {
+            lines.push((JournalEntryKind::PlatformFee, RoyaltyAccount::Artist(artist_id), fee, 0));
+            lines.push((JournalEntryKind::PlatformFee, RoyaltyAccount::Platform, 0, fee));
+        }
+        let recouped = recoup_advances(artist_id, gross - fee, timestamp);
+        if recouped > 0 {
+            lines.push((JournalEntryKind::Recoupment, RoyaltyAccount::Artist(artist_id), recouped, 0));
+            lines.push((JournalEntryKind::Recoupment, RoyaltyAccount::Platform, 0, recouped));
+        }
+    }
+    post_transaction(timestamp, track_id, block_index, lines);
+}
+
+/// Moves `amount` of an artist's or the platform's balance into PendingPayouts while the ledger
+/// transfer runs.
+fn reserve_payout(source: RoyaltyAccount, amount: u64, timestamp: u64) -> Result<(), ApiError> {
+    let available = account_balance(&source);
+    if available < amount {
+        return Err(ApiError::InsufficientFunds { available });
+    }
+    post_transaction(timestamp, None, None, vec![
+        (JournalEntryKind::Withdrawal, source, amount, 0),
+        (JournalEntryKind::Withdrawal, RoyaltyAccount::PendingPayouts, 0, amount),
+    ]);
+    Ok(())
+}
+
+/// A reserved payout went through: `amount - fee` reached the payee and `fee` went to the ledger.
+fn settle_payout(amount: u64, fee: u64, block_index: u64, timestamp: u64) {
+    post_transaction(timestamp, None, Some(block_index), vec![
+        (JournalEntryKind::Withdrawal, RoyaltyAccount::PendingPayouts, amount, 0),
+        (JournalEntryKind::Withdrawal, RoyaltyAccount::Treasury, 0, amount - fee),
+        (JournalEntryKind::Fee, RoyaltyAccount::Treasury, 0, fee),
+    ]);
+}
+
+/// A reserved payout failed: the reservation is reversed back to its source.
+fn release_payout(source: RoyaltyAccount, amount: u64, timestamp: u64) {
+    post_transaction(timestamp, None, None, vec![
+        (JournalEntryKind::Reversal, RoyaltyAccount::PendingPayouts, amount, 0),
+        (JournalEntryKind::Reversal, source, 0, amount),
+    ]);
+}
+
+/// Sends `amount` of `source`'s balance, minus the ledger fee, to `to` and journals the outcome.
+/// Returns the ledger block index.
+async fn pay_out(source: RoyaltyAccount, to: Principal, amount: u64) -> Result<u64, ApiError> {
+    // Reserve before awaiting so concurrent withdrawals cannot spend the same balance twice
+    reserve_payout(source.clone(), amount, ic_cdk::api::time() / 1_000_000)?;
+    let result = send_payout(to, amount).await;
+    let now = ic_cdk::api::time() / 1_000_000;
+    match result {
+        Ok((block_index, fee)) => {
+            settle_payout(amount, fee, block_index, now);
+            Ok(block_index)
+        }
+        Err(err) => {
+            release_payout(source, amount, now);
+            Err(err)
+        }
+    }
+}
+
+fn reconcile() -> RoyaltyReconciliation {
+    JOURNAL_ENTRIES.with(|entries| {
+        let entries = entries.borrow();
+        let sum = |f: &dyn Fn(&JournalEntry) -> u64| entries.iter().map(f).sum::<u64>();
+        let total_received = sum(&|e| if e.kind == JournalEntryKind::PaymentIn { e.debit } else { 0 });
+        let total_credited = sum(&|e| match (&e.kind, &e.account) {
+            (JournalEntryKind::SplitCredit | JournalEntryKind::Remainder, RoyaltyAccount::Artist(_)) => e.credit,
+            _ => 0,
+        });
+        let total_paid_out = sum(&|e| if e.kind == JournalEntryKind::Withdrawal && e.account == RoyaltyAccount::Treasury { e.credit } else { 0 });
+        let total_fees = sum(&|e| if e.kind == JournalEntryKind::Fee { e.credit } else { 0 });
+        let side = |account: &dyn Fn(&RoyaltyAccount) -> bool| {
+            entries.iter().filter(|e| account(&e.account)).fold((0u64, 0u64), |(d, c), e| (d + e.debit, c + e.credit))
+        };
+        let (treasury_debits, treasury_credits) = side(&|a| *a == RoyaltyAccount::Treasury);
+        let (artist_debits, artist_credits) = side(&|a| matches!(a, RoyaltyAccount::Artist(_)));
+        let (pending_debits, pending_credits) = side(&|a| *a == RoyaltyAccount::PendingPayouts);
+        let (platform_debits, platform_credits) = side(&|a| *a == RoyaltyAccount::Platform);
+        let (escrow_debits, escrow_credits) = side(&|a| matches!(a, RoyaltyAccount::Escrow(_)));
+        let treasury_balance = treasury_debits.saturating_sub(treasury_credits);
+        let artist_balances = artist_credits.saturating_sub(artist_debits);
+        let pending_payouts = pending_credits.saturating_sub(pending_debits);
+        let platform_balance = platform_credits.saturating_sub(platform_debits);
+        let escrow_balance = escrow_credits.saturating_sub(escrow_debits);
+        RoyaltyReconciliation {
+            total_received,
+            total_credited,
+            total_paid_out,
+            total_fees,
+            treasury_balance,
+            artist_balances,
+            pending_payouts,
+            platform_balance,
+            escrow_balance,
+            balanced: total_credited == total_received
+                && treasury_balance == artist_balances + pending_payouts + platform_balance + escrow_balance,
+        }
+    })
+}
+
+// Journal entries touching one artist's balance. Visible to the artist's owner and admins.
+#[ic_cdk::query]
+pub fn get_royalty_journal(artist_id: u64) -> Result<Vec<JournalEntry>, ApiError> {
+    require_artist_owner_or_admin(caller(), artist_id)?;
+    let account = RoyaltyAccount::Artist(artist_id);
+    Ok(JOURNAL_ENTRIES.with(|entries| entries.borrow().iter().filter(|e| e.account == account).cloned().collect()))
+}
+
+#[ic_cdk::query]
+pub fn list_journal_entries() -> Result<Vec<JournalEntry>, ApiError> {
+    require_admin(caller())?;
+    Ok(JOURNAL_ENTRIES.with(|entries| entries.borrow().clone()))
+}
+
+// Proves that everything received was credited and that the treasury covers all balances.
+#[ic_cdk::query]
+pub fn reconcile_royalties() -> Result<RoyaltyReconciliation, ApiError> {
+    require_admin(caller())?;
+    Ok(reconcile())
+}
+
+// --- Split Sheets ---
+// Royalty splits change only through a proposal that every contributor signs off on. Each agreed
+// sheet is kept with the track version and time it applied from; `Track.splits` mirrors the
+// current one. Payments record which sheet divided them.
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum SplitProposalStatus {
+    Pending,
+    Accepted,
+    Rejected,
+    Superseded, // replaced by a newer proposal before it was agreed
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SplitApproval {
+    pub artist_id: u64,
+    pub signer: Principal,
+    pub approved_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SplitProposal {
+    pub id: u64,
+    pub track_id: u64,
+    pub splits: Vec<Split>,
+    pub proposed_by: Principal,
+    pub proposed_at: u64,
+    pub signers: Vec<u64>, // contributor artist ids that must approve
+    pub approvals: Vec<SplitApproval>,
+    pub status: SplitProposalStatus,
+    pub decided_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SplitSheet {
+    pub track_id: u64,
+    pub version: u32,
+    pub splits: Vec<Split>,
+    pub track_version: u32, // track version the sheet applied from
+    pub effective_from: u64,
+    pub proposal_id: Option<u64>, // None for splits set before agreements existed
+}
+
+/// Splits must name each contributor at most once, with positive shares totalling 100%.
+fn validate_splits(track: &Track, splits: &[Split]) -> Result<(), ApiError> {
+    if splits.is_empty() {
+        return Err(ApiError::invalid_input("splits", "must not be empty"));
+    }
+    for (i, split) in splits.iter().enumerate() {
+        if split.pct == 0 {
+            return Err(ApiError::invalid_input("splits", &format!("artist {} has a zero share", split.id)));
+        }
+        if splits[..i].iter().any(|s| s.id == split.id) {
+            return Err(ApiError::invalid_input("splits", &format!("artist {} appears more than once", split.id)));
+        }
+        if !track.contributors.contains(&split.id) {
+            return Err(ApiError::invalid_input("splits", &format!("artist {} is not a contributor", split.id)));
+        }
+    }
+    let total: u32 = splits.iter().map(|s| s.pct as u32).sum();
+    if total != 100 {
+        return Err(ApiError::invalid_input("splits", &format!("shares total {}%, not 100%", total)));
+    }
+    Ok(())
+}
+
+fn current_split_sheet(track_id: u64) -> Option<SplitSheet> {
+    SPLIT_SHEETS.with(|sheets| sheets.borrow().iter().rev().find(|s| s.track_id == track_id).cloned())
+}
+
+fn find_split_proposal(proposal_id: u64) -> Result<SplitProposal, ApiError> {
+    SPLIT_PROPOSALS.with(|proposals| proposals.borrow().iter().find(|p| p.id == proposal_id).cloned())
+        .ok_or_else(|| ApiError::not_found("split proposal"))
+}
+
+fn save_split_proposal(proposal: &SplitProposal) {
+    SPLIT_PROPOSALS.with(|proposals| {
+        if let Some(p) = proposals.borrow_mut().iter_mut().find(|p| p.id == proposal.id) {
+            *p = proposal.clone();
+        }
+    });
+}
+
+/// Makes `splits` the track's current sheet.
+fn adopt_split_sheet(track_id: u64, splits: Vec<Split>, proposal_id: Option<u64>, now: u64) -> Result<SplitSheet, ApiError> {
+    let version = current_split_sheet(track_id).map(|s| s.version + 1).unwrap_or(1);
+    let track_version = TRACKS.with(|tracks| {
+        let mut tracks = tracks.borrow_mut();
+        let track = tracks.iter_mut().find(|t| t.id == track_id).ok_or_else(|| ApiError::not_found("track"))?;
+        track.splits = Some(splits.clone());
+        Ok(track.version)
+    })?;
+    let sheet = SplitSheet { track_id, version, splits, track_version, effective_from: now, proposal_id };
+    SPLIT_SHEETS.with(|sheets| sheets.borrow_mut().push(sheet.clone()));
+    Ok(sheet)
+}
+
+/// Records sheet 1 for tracks whose splits were set before agreements existed.
+fn seed_split_sheets() {
+    let unrecorded: Vec<(u64, Vec<Split>)> = TRACKS.with(|tracks| {
+        tracks.borrow().iter()
+            .filter(|t| current_split_sheet(t.id).is_none())
+            .filter_map(|t| t.splits.clone().map(|splits| (t.id, splits)))
+            .collect()
+    });
+    for (track_id, splits) in unrecorded {
+        let _ = adopt_split_sheet(track_id, splits, None, 0);
+    }
+}
+
+fn propose_splits_as(principal: Principal, track_id: u64, splits: Vec<Split>, now: u64) -> Result<SplitProposal, ApiError> {
+    let track = find_track(track_id).ok_or_else(|| ApiError::not_found("track"))?;
+    require_track_role(principal, &track, TrackRole::Owner)?;
+    validate_splits(&track, &splits)?;
+    SPLIT_PROPOSALS.with(|proposals| {
+        for p in proposals.borrow_mut().iter_mut().filter(|p| p.track_id == track_id && p.status == SplitProposalStatus::Pending) {
+            p.status = SplitProposalStatus::Superseded;
+            p.decided_at = Some(now);
+        }
+    });
+    let id = SPLIT_PROPOSAL_ID.with(|pid| {
+        let mut pid = pid.borrow_mut();
+        let id = *pid;
+        *pid += 1;
+        id
+    });
+    let proposal = SplitProposal {
+        id,
+        track_id,
+        splits,
+        proposed_by: principal,
+        proposed_at: now,
+        signers: track.contributors.clone(),
+        approvals: vec![],
+        status: SplitProposalStatus::Pending,
+        decided_at: None,
+    };
+    SPLIT_PROPOSALS.with(|proposals| proposals.borrow_mut().push(proposal.clone()));
+    // Proposing counts as signing for the proposer's own contributor artists
+    if track.contributors.iter().any(|id| owns_artist(principal, *id)) {
+        return approve_splits_as(principal, id, now);
+    }
+    Ok(proposal)
+}
+
+fn approve_splits_as(principal: Principal, proposal_id: u64, now: u64) -> Result<SplitProposal, ApiError> {
+    let mut proposal = find_split_proposal(proposal_id)?;
+    if proposal.status != SplitProposalStatus::Pending {
+        return Err(ApiError::conflict("split proposal is no longer pending"));
+    }
+    let signing: Vec<u64> = proposal.signers.iter().copied()
+        .filter(|id| owns_artist(principal, *id) && !proposal.approvals.iter().any(|a| a.artist_id == *id))
+        .collect();
+    if signing.is_empty() {
+        return Err(ApiError::unauthorized("caller has no contributor left to sign for"));
+    }
+    proposal.approvals.extend(signing.into_iter().map(|artist_id| SplitApproval { artist_id, signer: principal, approved_at: now }));
+    if proposal.signers.iter().all(|id| proposal.approvals.iter().any(|a| a.artist_id == *id)) {
+        // Contributors may have changed since the proposal was made
+        let track = find_track(proposal.track_id).ok_or_else(|| ApiError::not_found("track"))?;
+        validate_splits(&track, &proposal.splits)?;
+        adopt_split_sheet(proposal.track_id, proposal.splits.clone(), Some(proposal.id), now)?;
+        proposal.status = SplitProposalStatus::Accepted;
+        proposal.decided_at = Some(now);
+    }
+    save_split_proposal(&proposal);
+    Ok(proposal)
+}
+
+fn reject_splits_as(principal: Principal, proposal_id: u64, now: u64) -> Result<SplitProposal, ApiError> {
+    let mut proposal = find_split_proposal(proposal_id)?;
+    if proposal.status != SplitProposalStatus::Pending {
+        return Err(ApiError::conflict("split proposal is no longer pending"));
+    }
+    if !proposal.signers.iter().any(|id| owns_artist(principal, *id)) {
+        return Err(ApiError::unauthorized("only contributors can reject a split proposal"));
+    }
+    proposal.status = SplitProposalStatus::Rejected;
+    proposal.decided_at = Some(now);
+    save_split_proposal(&proposal);
+    Ok(proposal)
+}
+
+// Propose new splits for a track (owner only). They take effect once every contributor has approved.
+#[ic_cdk::update]
+fn propose_track_splits(track_id: u64, splits: Vec<Split>) -> Result<SplitProposal, ApiError> {
+    propose_splits_as(active_caller()?, track_id, splits, ic_cdk::api::time() / 1_000_000)
+}
+
+// Sign a pending proposal for every contributor artist the caller owns
+#[ic_cdk::update]
+fn approve_split_proposal(proposal_id: u64) -> Result<SplitProposal, ApiError> {
+    approve_splits_as(active_caller()?, proposal_id, ic_cdk::api::time() / 1_000_000)
+}
+
+#[ic_cdk::update]
+fn reject_split_proposal(proposal_id: u64) -> Result<SplitProposal, ApiError> {
+    reject_splits_as(active_caller()?, proposal_id, ic_cdk::api::time() / 1_000_000)
+}
+
+#[ic_cdk::query]
+fn list_split_proposals(track_id: u64) -> Result<Vec<SplitProposal>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(SPLIT_PROPOSALS.with(|proposals| proposals.borrow().iter().filter(|p| p.track_id == track_id).cloned().collect()))
+}
+
+// Every sheet the track has had, oldest first
+#[ic_cdk::query]
+fn get_split_sheets(track_id: u64) -> Result<Vec<SplitSheet>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(SPLIT_SHEETS.with(|sheets| sheets.borrow().iter().filter(|s| s.track_id == track_id).cloned().collect()))
+}
+
+// The sheet that was in force at `timestamp` (ms)
+#[ic_cdk::query]
+fn get_split_sheet_at(track_id: u64, timestamp: u64) -> Result<Option<SplitSheet>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(SPLIT_SHEETS.with(|sheets| {
+        sheets.borrow().iter().rev().find(|s| s.track_id == track_id && s.effective_from <= timestamp).cloned()
+    }))
+}
+
+// --- Fees, Advances & Statements ---
+// Admins set commission rates per track, per genre or globally (most specific wins) and record
+// recoupable advances paid to artists off-platform. Both are applied by `record_payment_in`.
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum FeeScope {
+    Global,
+    Genre(String),
+    Track(u64),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct FeeSchedule {
+    pub scope: FeeScope,
+    pub bps: u16, // basis points of each artist share, 100 = 1%
+    pub set_by: Principal,
+    pub set_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Advance {
+    pub id: u64,
+    pub artist_id: u64,
+    pub amount: u64,
+    pub recouped: u64,
+    pub description: String,
+    pub granted_by: Principal,
+    pub granted_at: u64,
+    pub fully_recouped_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RoyaltyStatement {
+    pub artist_id: u64,
+    pub period_start: u64, // inclusive, ms
+    pub period_end: u64,   // exclusive, ms
+    pub opening_balance: u64,
+    pub gross_royalties: u64,
+    pub platform_fees: u64,
+    pub recouped: u64,
+    pub withdrawn: u64,
+    pub closing_balance: u64,
+    pub outstanding_advances: u64,
+    pub entries: Vec<JournalEntry>,
+}
+
+const MAX_FEE_BPS: u16 = 10_000;
+
+fn effective_fee_bps(track: &Track) -> u16 {
+    FEE_SCHEDULES.with(|schedules| {
+        let schedules = schedules.borrow();
+        let find = |matches: &dyn Fn(&FeeScope) -> bool| schedules.iter().find(|s| matches(&s.scope)).map(|s| s.bps);
+        find(&|scope| *scope == FeeScope::Track(track.id))
+            .or_else(|| {
+                let genre = track.genre.as_deref()?;
+                find(&|scope| matches!(scope, FeeScope::Genre(g) if g.eq_ignore_ascii_case(genre)))
+            })
+            .or_else(|| find(&|scope| *scope == FeeScope::Global))
+            .unwrap_or(0)
+    })
+}
+
+fn outstanding_advances(artist_id: u64) -> u64 {
+    ADVANCES.with(|advances| advances.borrow().iter().filter(|a| a.artist_id == artist_id).map(|a| a.amount - a.recouped).sum())
+}
+
+/// Recoups up to `available` from the artist's advances, oldest first. Returns the amount recouped.
+fn recoup_advances(artist_id: u64, available: u64, now: u64) -> u64 {
+    ADVANCES.with(|advances| {
+        let mut left = available;
+        for advance in advances.borrow_mut().iter_mut().filter(|a| a.artist_id == artist_id && a.recouped < a.amount) {
+            let take = (advance.amount - advance.recouped).min(left);
+            advance.recouped += take;
+            left -= take;
+            if advance.recouped == advance.amount {
+                advance.fully_recouped_at = Some(now);
+            }
+            if left == 0 {
+                break;
+            }
+        }
+        available - left
+    })
+}
+
+/// Statement of one artist's balance over `[from, to)`, built from the journal.
+fn royalty_statement(artist_id: u64, from: u64, to: u64) -> RoyaltyStatement {
+    let account = RoyaltyAccount::Artist(artist_id);
+    let entries: Vec<JournalEntry> = JOURNAL_ENTRIES.with(|entries| {
+        entries.borrow().iter().filter(|e| e.account == account && e.timestamp < to).cloned().collect()
+    });
+    statement_from_entries(artist_id, from, to, entries)
+}
+
+/// Builds the statement from the artist's journal entries dated before `to`.
+fn statement_from_entries(artist_id: u64, from: u64, to: u64, entries: Vec<JournalEntry>) -> RoyaltyStatement {
+    let (before, during): (Vec<JournalEntry>, Vec<JournalEntry>) = entries.into_iter().partition(|e| e.timestamp < from);
+    let opening_balance = before.iter().map(|e| e.credit).sum::<u64>().saturating_sub(before.iter().map(|e| e.debit).sum());
+    let total = |kinds: &[JournalEntryKind], side: fn(&JournalEntry) -> u64| {
+        during.iter().filter(|e| kinds.contains(&e.kind)).map(side).sum::<u64>()
+    };
+    let gross_royalties = total(&[JournalEntryKind::OpeningBalance, JournalEntryKind::SplitCredit, JournalEntryKind::Remainder], |e| e.credit);
+    let platform_fees = total(&[JournalEntryKind::PlatformFee], |e| e.debit);
+    let recouped = total(&[JournalEntryKind::Recoupment], |e| e.debit);
+    let withdrawn = total(&[JournalEntryKind::Withdrawal], |e| e.debit).saturating_sub(total(&[JournalEntryKind::Reversal], |e| e.credit));
+    let closing_balance = (opening_balance + during.iter().map(|e| e.credit).sum::<u64>()).saturating_sub(during.iter().map(|e| e.debit).sum());
+    RoyaltyStatement {
+        artist_id,
+        period_start: from,
+        period_end: to,
+        opening_balance,
+        gross_royalties,
+        platform_fees,
+        recouped,
+        withdrawn,
+        closing_balance,
+        outstanding_advances: outstanding_advances(artist_id),
+        entries: during,
+    }
+}
+
+// Read access to an artist's books, which stays open while the artist is suspended
+fn require_artist_owner_or_admin(principal: Principal, artist_id: u64) -> Result<(), ApiError> {
+    if is_admin(principal) || owns_artist(principal, artist_id) {
+        Ok(())
+    } else {
+        Err(ApiError::unauthorized("caller does not own this artist"))
+    }
+}
+
+// Set the commission for a scope, replacing any existing rate for it
+#[ic_cdk::update]
+pub fn set_fee_schedule(scope: FeeScope, bps: u16) -> Result<(), ApiError> {
+    let admin = caller();
+    require_admin(admin)?;
+    if bps > MAX_FEE_BPS {
+        return Err(ApiError::invalid_input("bps", "must be at most 10000 (100%)"));
+    }
+    if let FeeScope::Track(track_id) = scope {
+        find_track(track_id).ok_or_else(|| ApiError::not_found("track"))?;
+    }
+    let now = ic_cdk::api::time() / 1_000_000;
+    FEE_SCHEDULES.with(|schedules| {
+        let mut schedules = schedules.borrow_mut();
+        schedules.retain(|s| s.scope != scope);
+        schedules.push(FeeSchedule { scope: scope.clone(), bps, set_by: admin, set_at: now });
+    });
+    log_admin_action(admin, "set_fee_schedule", "fee_schedule", &format!("{:?}", scope), Some(format!("{} bps", bps)));
+    Ok(())
+}
+
+#[ic_cdk::update]
+pub fn remove_fee_schedule(scope: FeeScope) -> Result<(), ApiError> {
+    let admin = caller();
+    require_admin(admin)?;
+    let removed = FEE_SCHEDULES.with(|schedules| {
+        let mut schedules = schedules.borrow_mut();
+        let len_before = schedules.len();
+        schedules.retain(|s| s.scope != scope);
+        schedules.len() < len_before
+    });
+    if !removed {
+        return Err(ApiError::not_found("fee schedule"));
+    }
+    log_admin_action(admin, "remove_fee_schedule", "fee_schedule", &format!("{:?}", scope), None);
+    Ok(())
+}
+
+#[ic_cdk::query]
+pub fn list_fee_schedules() -> Vec<FeeSchedule> {
+    FEE_SCHEDULES.with(|schedules| schedules.borrow().clone())
+}
+
+// Commission that would apply to a payment for this track right now
+#[ic_cdk::query]
+pub fn get_effective_fee_bps(track_id: u64) -> Result<u16, ApiError> {
+    visible_track(track_id).map(|t| effective_fee_bps(&t))
+}
+
+#[ic_cdk::update]
+pub fn grant_advance(artist_id: u64, amount: u64, description: String) -> Result<Advance, ApiError> {
+    let admin = caller();
+    require_admin(admin)?;
+    get_artist(artist_id)?;
+    if amount == 0 {
+        return Err(ApiError::invalid_input("amount", "must be greater than zero"));
+    }
+    let id = ADVANCE_ID.with(|aid| {
+        let mut aid = aid.borrow_mut();
+        let id = *aid;
+        *aid += 1;
+        id
+    });
+    let advance = Advance {
+        id,
+        artist_id,
+        amount,
+        recouped: 0,
+        description,
+        granted_by: admin,
+        granted_at: ic_cdk::api::time() / 1_000_000,
+        fully_recouped_at: None,
+    };
+    ADVANCES.with(|advances| advances.borrow_mut().push(advance.clone()));
+    log_admin_action(admin, "grant_advance", "artist", &artist_id.to_string(), Some(format!("{} tokens: {}", amount, advance.description)));
+    Ok(advance)
+}
+
+#[ic_cdk::query]
+pub fn list_advances(artist_id: u64) -> Result<Vec<Advance>, ApiError> {
+    require_artist_owner_or_admin(caller(), artist_id)?;
+    Ok(ADVANCES.with(|advances| advances.borrow().iter().filter(|a| a.artist_id == artist_id).cloned().collect()))
+}
+
+// Pays collected commission and recoupments out of the Platform account to `to`, minus the
+// ledger fee. Returns the ledger block index.
+#[ic_cdk::update]
+pub async fn withdraw_platform_fees(to: Principal, amount: u64) -> Result<u64, ApiError> {
+    if amount == 0 {
+        return Err(ApiError::invalid_input("amount", "must be greater than zero"));
+    }
+    let admin = caller();
+    require_admin(admin)?;
+    let block_index = pay_out(RoyaltyAccount::Platform, to, amount).await?;
+    log_admin_action(admin, "withdraw_platform_fees", "platform", &to.to_text(), Some(format!("{} tokens (block {})", amount, block_index)));
+    Ok(block_index)
+}
+
+// Balance movements for `[from, to)` (ms) with every fee, recoupment and withdrawal itemised
+#[ic_cdk::query]
+pub fn get_royalty_statement(artist_id: u64, from: u64, to: u64) -> Result<RoyaltyStatement, ApiError> {
+    require_artist_owner_or_admin(caller(), artist_id)?;
+    if from >= to {
+        return Err(ApiError::invalid_input("to", "must be after from"));
+    }
+    Ok(royalty_statement(artist_id, from, to))
+}
+
+// --- Monthly Statements ---
+// Once a calendar month (UTC) has ended, a timer snapshots a statement for every artist with a
+// balance or activity in it. Snapshots are never regenerated, so they stay what the artist saw.
+
+/// How often the timer checks whether a month has ended
+const STATEMENT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// Months generated per run at most, so catching up stays within one message's instruction
+/// limit; later runs pick up the rest
+const MAX_STATEMENT_MONTHS_PER_RUN: u32 = 12;
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct TrackEarnings {
+    pub track_id: u64,
+    pub gross: u64,
+    pub platform_fees: u64,
+    pub recouped: u64,
+    pub net: u64,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub struct MembershipEarnings {
+    pub gross: u64,
+    pub platform_fees: u64,
+    pub recouped: u64,
+    pub net: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MonthlyStatement {
+    pub id: u64,
+    pub artist_id: u64,
+    pub period: String, // "YYYY-MM"
+    pub period_start: u64,
+    pub period_end: u64,
+    pub opening_balance: u64,
+    pub tracks: Vec<TrackEarnings>,
+    pub memberships: MembershipEarnings,
+    pub withdrawn: u64,
+    pub closing_balance: u64,
+    pub generated_at: u64,
+}
+
+/// (year, month) of a day count since 1970-01-01, using Howard Hinnant's civil-from-days.
+fn civil_from_days(days: i64) -> (i64, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month)
+}
+
+/// Days since 1970-01-01 of the first day of `month` in `year`.
+fn days_from_civil(year: i64, month: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Start (ms) of the UTC month containing `ms`.
+fn month_start(ms: u64) -> u64 {
+    let (year, month) = civil_from_days((ms / MS_PER_DAY) as i64);
+    days_from_civil(year, month) as u64 * MS_PER_DAY
+}
+
+/// Start (ms) of the month after the one containing `ms`.
+fn next_month_start(ms: u64) -> u64 {
+    let (year, month) = civil_from_days((ms / MS_PER_DAY) as i64);
+    let (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    days_from_civil(year, month) as u64 * MS_PER_DAY
+}
+
+fn month_label(ms: u64) -> String {
+    let (year, month) = civil_from_days((ms / MS_PER_DAY) as i64);
+    format!("{:04}-{:02}", year, month)
+}
+
+/// Earnings in a statement grouped by track, in track id order, plus membership revenue (the
+/// credits posted without a track).
+fn statement_earnings(entries: &[JournalEntry]) -> (Vec<TrackEarnings>, MembershipEarnings) {
+    let mut by_track: BTreeMap<Option<u64>, MembershipEarnings> = BTreeMap::new();
+    for entry in entries {
+        let earnings = by_track.entry(entry.track_id).or_default();
+        match entry.kind {
+            JournalEntryKind::SplitCredit | JournalEntryKind::Remainder => earnings.gross += entry.credit,
+            JournalEntryKind::PlatformFee => earnings.platform_fees += entry.debit,
+            JournalEntryKind::Recoupment => earnings.recouped += entry.debit,
+            _ => continue,
+        }
+        earnings.net = earnings.gross - earnings.platform_fees - earnings.recouped;
+    }
+    let memberships = by_track.remove(&None).unwrap_or_default();
+    let tracks = by_track
+        .into_iter()
+        .filter_map(|(track_id, e)| {
+            Some(TrackEarnings { track_id: track_id?, gross: e.gross, platform_fees: e.platform_fees, recouped: e.recouped, net: e.net })
+        })
+        .collect();
+    (tracks, memberships)
+}
+
+/// Snapshots statements for the month starting at `period_start`, skipping artists with
+/// nothing to report. The journal is read once and split by artist.
+fn generate_statements_for(period_start: u64, now: u64) {
+    let period_end = next_month_start(period_start);
+    let mut entries_by_artist: BTreeMap<u64, Vec<JournalEntry>> = BTreeMap::new();
+    JOURNAL_ENTRIES.with(|entries| {
+        for entry in entries.borrow().iter().filter(|e| e.timestamp < period_end) {
+            if let RoyaltyAccount::Artist(artist_id) = entry.account {
+                entries_by_artist.entry(artist_id).or_default().push(entry.clone());
+            }
+        }
+    });
+    let artist_ids: Vec<u64> = ARTISTS.with(|artists| artists.borrow().iter().map(|a| a.id).collect());
+    for artist_id in artist_ids {
+        let entries = entries_by_artist.remove(&artist_id).unwrap_or_default();
+        let statement = statement_from_entries(artist_id, period_start, period_end, entries);
+        if statement.opening_balance == 0 && statement.entries.is_empty() {
+            continue;
+        }
+        let (tracks, memberships) = statement_earnings(&statement.entries);
+        let id = MONTHLY_STATEMENT_ID.with(|sid| {
+            let mut sid = sid.borrow_mut();
+            let id = *sid;
+            *sid += 1;
+            id
+        });
+        MONTHLY_STATEMENTS.with(|statements| {
+            statements.borrow_mut().push(MonthlyStatement {
+                id,
+                artist_id,
+                period: month_label(period_start),
+                period_start,
+                period_end,
+                opening_balance: statement.opening_balance,
+                tracks,
+                memberships,
+                withdrawn: statement.withdrawn,
+                closing_balance: statement.closing_balance,
+                generated_at: now,
+            })
+        });
+    }
+}
+
+/// Generates the months that have ended since the last run, oldest first and at most
+/// MAX_STATEMENT_MONTHS_PER_RUN of them. The first run only covers the month that just ended.
+fn generate_due_statements(now: u64) -> u32 {
+    let current_month = month_start(now);
+    let mut next = STATEMENTS_GENERATED_THROUGH.with(|t| *t.borrow());
+    if next == 0 {
+        // No month has ended yet before the first one
+        let Some(last_ms_of_previous_month) = current_month.checked_sub(1) else { return 0 };
+        next = month_start(last_ms_of_previous_month);
+    }
+    let mut generated = 0;
+    while next < current_month && generated < MAX_STATEMENT_MONTHS_PER_RUN {
+        generate_statements_for(next, now);
+        next = next_month_start(next);
+        STATEMENTS_GENERATED_THROUGH.with(|t| *t.borrow_mut() = next);
+        generated += 1;
+    }
+    generated
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn statement_csv(statement: &MonthlyStatement) -> String {
+    let title = |track_id: u64| find_track(track_id).map(|t| t.title).unwrap_or_default();
+    let mut csv = String::from("line,period,track_id,title,gross,platform_fees,recouped,net\n");
+    csv.push_str(&format!("opening_balance,{},,,,,,{}\n", statement.period, statement.opening_balance));
+    for t in &statement.tracks {
+        csv.push_str(&format!(
+            "track,{},{},{},{},{},{},{}\n",
+            statement.period, t.track_id, csv_field(&title(t.track_id)), t.gross, t.platform_fees, t.recouped, t.net
+        ));
+    }
+    let m = &statement.memberships;
+    if m.gross > 0 {
+        csv.push_str(&format!("memberships,{},,,{},{},{},{}\n", statement.period, m.gross, m.platform_fees, m.recouped, m.net));
+    }
+    csv.push_str(&format!("withdrawn,{},,,,,,{}\n", statement.period, statement.withdrawn));
+    csv.push_str(&format!("closing_balance,{},,,,,,{}\n", statement.period, statement.closing_balance));
+    csv
+}
+
+#[ic_cdk::query]
+pub fn list_monthly_statements(artist_id: u64) -> Result<Vec<MonthlyStatement>, ApiError> {
+    require_artist_owner_or_admin(caller(), artist_id)?;
+    Ok(MONTHLY_STATEMENTS.with(|statements| statements.borrow().iter().filter(|s| s.artist_id == artist_id).cloned().collect()))
+}
+
+#[ic_cdk::query]
+pub fn export_monthly_statement_csv(statement_id: u64) -> Result<String, ApiError> {
+    let statement = MONTHLY_STATEMENTS.with(|statements| statements.borrow().iter().find(|s| s.id == statement_id).cloned())
+        .ok_or_else(|| ApiError::not_found("statement"))?;
+    require_artist_owner_or_admin(caller(), statement.artist_id)?;
+    Ok(statement_csv(&statement))
+}
+
+// Run the statement job now instead of waiting for the timer. Returns the number of months generated.
+#[ic_cdk::update]
+pub fn generate_monthly_statements() -> Result<u32, ApiError> {
+    require_admin(caller())?;
+    Ok(generate_due_statements(ic_cdk::api::time() / 1_000_000))
+}
+
+// --- Pricing & Entitlements ---
+// Owners price downloads (and optionally full playback) per track. Buying a track pays through
+// the split distribution and records an entitlement for the buyer's principal, which the
+// file-access endpoints check. Role holders and admins never need one.
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PricingMode {
+    Free,
+    PayPerDownload { price: u64 },
+    PayWhatYouWant { minimum: u64 },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct TrackPricing {
+    pub mode: PricingMode,
+    pub paid_streaming: bool, // full playback needs a purchase too; previews stay free
+}
+
+impl Default for TrackPricing {
+    fn default() -> Self {
+        TrackPricing { mode: PricingMode::Free, paid_streaming: false }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Entitlement {
+    pub track_id: u64,
+    pub owner: Principal,
+    pub amount_paid: u64,
+    pub block_index: u64,
+    pub purchased_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TrackAccess {
+    Stream,
+    Download,
+}
+
+fn is_entitled(principal: Principal, track_id: u64) -> bool {
+    ENTITLEMENTS.with(|entitlements| entitlements.borrow().iter().any(|e| e.owner == principal && e.track_id == track_id))
+}
+
+/// Free tracks stream to everyone and download if `downloadable`; priced tracks download (and,
+/// with `paid_streaming`, stream) only for buyers.
+fn has_track_access(principal: Principal, track: &Track, access: TrackAccess) -> bool {
+    if has_track_role(principal, track, &TrackRole::Collaborator) || is_admin(principal) || is_entitled(principal, track.id) {
+        return true;
+    }
+    match access {
+        TrackAccess::Stream => !track.pricing.paid_streaming,
+        TrackAccess::Download => track.downloadable && track.pricing.mode == PricingMode::Free,
+    }
+}
+
+/// Reading the whole full-quality master amounts to downloading it, so it needs download access
+/// unless the track is free and downloadable; everyone else gets the preview.
+fn master_access(track: &Track) -> TrackAccess {
+    if track.pricing.mode == PricingMode::Free && track.downloadable { TrackAccess::Stream } else { TrackAccess::Download }
+}
+
+fn require_track_access(principal: Principal, track: &Track, access: TrackAccess) -> Result<(), ApiError> {
+    if has_track_access(principal, track, access) {
+        return Ok(());
+    }
+    if track.pricing.mode == PricingMode::Free && access == TrackAccess::Download {
+        Err(ApiError::unauthorized("track is not downloadable"))
+    } else {
+        Err(ApiError::unauthorized("track must be purchased first"))
+    }
+}
+
+/// Marks a purchase as in flight until dropped, so a second call can't charge the buyer again
+/// while the first transfer is awaited. Dropping also runs when a trap unwinds the call.
+struct PendingPurchase {
+    buyer: Principal,
+    track_id: u64,
+}
+
+impl PendingPurchase {
+    fn start(buyer: Principal, track_id: u64) -> Result<Self, ApiError> {
+        PENDING_PURCHASES.with(|pending| {
+            let mut pending = pending.borrow_mut();
+            if pending.contains(&(buyer, track_id)) {
+                return Err(ApiError::conflict("a purchase of this track is already in progress"));
+            }
+            pending.push((buyer, track_id));
+            Ok(PendingPurchase { buyer, track_id })
+        })
+    }
+}
+
+impl Drop for PendingPurchase {
+    fn drop(&mut self) {
+        PENDING_PURCHASES.with(|pending| pending.borrow_mut().retain(|p| *p != (self.buyer, self.track_id)));
+    }
+}
+
+fn check_purchase_amount(pricing: &TrackPricing, amount: u64) -> Result<(), ApiError> {
+    match pricing.mode {
+        PricingMode::Free => Err(ApiError::conflict("track is free")),
+        PricingMode::PayPerDownload { price } if amount != price => {
+            Err(ApiError::invalid_input("amount", &format!("must be the track price of {}", price)))
+        }
+        PricingMode::PayWhatYouWant { minimum } if amount < minimum || amount == 0 => {
+            Err(ApiError::invalid_input("amount", &format!("must be at least {}", minimum.max(1))))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[ic_cdk::update]
+pub fn set_track_pricing(track_id: u64, pricing: TrackPricing) -> Result<(), ApiError> {
+    match pricing.mode {
+        PricingMode::PayPerDownload { price: 0 } => return Err(ApiError::invalid_input("pricing", "price must be greater than zero")),
+        PricingMode::Free if pricing.paid_streaming => {
+            return Err(ApiError::invalid_input("pricing", "a free track cannot require payment to stream"))
+        }
+        _ => {}
+    }
+    with_track_as(track_id, TrackRole::Owner, |track| track.pricing = pricing)
+}
+
+#[ic_cdk::query]
+pub fn get_track_pricing(track_id: u64) -> Result<TrackPricing, ApiError> {
+    visible_track(track_id).map(|t| t.pricing)
+}
+
+// Buy a track: pulls `amount` from the caller (approved via icrc2_approve), distributes it by the
+// track's splits and grants the caller download and streaming access.
+#[ic_cdk::update]
+pub async fn purchase_track(track_id: u64, amount: u64) -> Result<Entitlement, ApiError> {
+    let buyer = active_caller()?;
+    let track = visible_track(track_id)?;
+    check_purchase_amount(&track.pricing, amount)?;
+    if is_entitled(buyer, track_id) {
+        return Err(ApiError::conflict("track has already been purchased"));
+    }
+    let _pending = PendingPurchase::start(buyer, track_id)?;
+    let block_index = collect_track_payment(buyer, &track, amount, "purchase_track").await?;
+    let entitlement = Entitlement {
+        track_id,
+        owner: buyer,
+        amount_paid: amount,
+        block_index,
+        purchased_at: ic_cdk::api::time() / 1_000_000,
+    };
+    ENTITLEMENTS.with(|entitlements| entitlements.borrow_mut().push(entitlement.clone()));
+    Ok(entitlement)
+}
+
+#[ic_cdk::query]
+pub fn list_my_entitlements() -> Vec<Entitlement> {
+    let principal = caller();
+    ENTITLEMENTS.with(|entitlements| entitlements.borrow().iter().filter(|e| e.owner == principal).cloned().collect())
+}
+
+// --- Fan Memberships ---
+// Artists offer recurring membership tiers. A subscription is paid up front for one period and
+// renewed by a timer from the subscriber's remaining ICRC-2 allowance; cancelling stops renewal
+// but keeps access until the paid period ends. Membership revenue is credited to the artist in
+// the royalty journal as a payment without a track, after the global platform fee.
+
+/// How often the timer looks for subscriptions that reached the end of their period
+const SUBSCRIPTION_RENEWAL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const MAX_MEMBERSHIP_PERIOD_DAYS: u32 = 366;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MembershipTier {
+    pub id: u64,
+    pub artist_id: u64,
+    pub name: String,
+    pub description: String,
+    pub price: u64, // charged once per period
+    pub period_days: u32,
+    pub active: bool, // retired tiers take no new members and stop renewing
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum SubscriptionStatus {
+    Active,
+    Expired,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Subscription {
+    pub id: u64,
+    pub tier_id: u64,
+    pub artist_id: u64,
+    pub subscriber: Principal,
+    pub started_at: u64,
+    pub expires_at: u64,
+    pub auto_renew: bool,
+    pub status: SubscriptionStatus,
+    pub renewals: u32,
+    pub last_block_index: u64,
+}
+
+fn find_membership_tier(tier_id: u64) -> Option<MembershipTier> {
+    MEMBERSHIP_TIERS.with(|tiers| tiers.borrow().iter().find(|t| t.id == tier_id).cloned())
+}
+
+fn is_subscribed(principal: Principal, artist_id: u64) -> bool {
+    SUBSCRIPTIONS.with(|subs| {
+        subs.borrow().iter().any(|s| s.subscriber == principal && s.artist_id == artist_id && s.status == SubscriptionStatus::Active)
+    })
+}
+
+fn membership_fee_bps() -> u16 {
+    FEE_SCHEDULES.with(|schedules| schedules.borrow().iter().find(|s| s.scope == FeeScope::Global).map(|s| s.bps).unwrap_or(0))
+}
+
+fn subscription_memo(tier_id: u64) -> Vec<u8> {
+    [b"membership:".as_slice(), &tier_id.to_be_bytes()].concat()
+}
+
+/// Credits one period's payment for `tier` to its artist.
+fn record_membership_payment(tier: &MembershipTier, block_index: u64, now: u64) {
+    let splits = [Split { id: tier.artist_id, pct: 100 }];
+    record_payment_in(None, &splits, membership_fee_bps(), tier.price, block_index, now);
+}
+
+fn period_ms(tier: &MembershipTier) -> u64 {
+    tier.period_days as u64 * MS_PER_DAY
+}
+
+fn validate_tier(name: &str, price: u64, period_days: u32) -> Result<(), ApiError> {
+    require_non_empty("name", name)?;
+    if price == 0 {
+        return Err(ApiError::invalid_input("price", "must be greater than zero"));
+    }
+    if period_days == 0 || period_days > MAX_MEMBERSHIP_PERIOD_DAYS {
+        return Err(ApiError::invalid_input("period_days", "must be between 1 and 366"));
+    }
+    Ok(())
+}
+
+/// Records a paid-up subscription and follows the artist on the subscriber's behalf.
+fn activate_subscription(subscriber: Principal, tier: &MembershipTier, block_index: u64, now: u64) -> Subscription {
+    record_membership_payment(tier, block_index, now);
+    let id = SUBSCRIPTION_ID.with(|sid| {
+        let mut sid = sid.borrow_mut();
+        let id = *sid;
+        *sid += 1;
+        id
+    });
+    let subscription = Subscription {
+        id,
+        tier_id: tier.id,
+        artist_id: tier.artist_id,
+        subscriber,
+        started_at: now,
+        expires_at: now + period_ms(tier),
+        auto_renew: true,
+        status: SubscriptionStatus::Active,
+        renewals: 0,
+        last_block_index: block_index,
+    };
+    SUBSCRIPTIONS.with(|subs| subs.borrow_mut().push(subscription.clone()));
+    if let Some(artist) = ARTISTS.with(|artists| artists.borrow().iter().find(|a| a.id == tier.artist_id).cloned()) {
+        FOLLOWED_ARTISTS.with(|fa| {
+            let mut fa = fa.borrow_mut();
+            match fa.iter_mut().find(|(p, _)| *p == subscriber) {
+                Some((_, artists)) if !artists.contains(&artist.user_principal) => artists.push(artist.user_principal),
+                Some(_) => {}
+                None => fa.push((subscriber, vec![artist.user_principal])),
+            }
+        });
+    }
+    subscription
+}
+
+fn update_subscription(subscription_id: u64, f: impl FnOnce(&mut Subscription)) {
+    SUBSCRIPTIONS.with(|subs| {
+        if let Some(sub) = subs.borrow_mut().iter_mut().find(|s| s.id == subscription_id) {
+            f(sub);
+        }
+    });
+}
+
+fn expire_subscription(subscription_id: u64, reason: &str, now: u64) {
+    let mut subscriber = None;
+    update_subscription(subscription_id, |sub| {
+        sub.status = SubscriptionStatus::Expired;
+        sub.auto_renew = false;
+        subscriber = Some(sub.subscriber);
+    });
+    if let Some(subscriber) = subscriber {
+        push_notification(subscriber, format!("Your membership #{} has ended: {}", subscription_id, reason), now);
+    }
+}
+
+/// Expires due subscriptions that won't renew and returns the ones to charge for another period.
+fn due_renewals(now: u64) -> Vec<(Subscription, MembershipTier)> {
+    let due: Vec<Subscription> = SUBSCRIPTIONS.with(|subs| {
+        subs.borrow().iter().filter(|s| s.status == SubscriptionStatus::Active && s.expires_at <= now).cloned().collect()
+    });
+    let mut renewals = Vec::new();
+    for sub in due {
+        match find_membership_tier(sub.tier_id) {
+            Some(tier) if sub.auto_renew && tier.active => renewals.push((sub, tier)),
+            Some(_) if sub.auto_renew => expire_subscription(sub.id, "the tier was retired", now),
+            _ => expire_subscription(sub.id, "it was cancelled", now),
+        }
+    }
+    renewals
+}
+
+fn extend_subscription(subscription_id: u64, tier: &MembershipTier, block_index: u64, now: u64) {
+    record_membership_payment(tier, block_index, now);
+    update_subscription(subscription_id, |sub| {
+        sub.expires_at += period_ms(tier);
+        sub.renewals += 1;
+        sub.last_block_index = block_index;
+    });
+}
+
+/// Charges every due subscription for its next period. Runs from the renewal timer.
+async fn renew_due_subscriptions() {
+    let Some(_running) = JobGuard::acquire(&RENEWALS_IN_FLIGHT) else { return };
+    for (sub, tier) in due_renewals(ic_cdk::api::time() / 1_000_000) {
+        let result = transfer_from(sub.subscriber, tier.price, subscription_memo(tier.id)).await;
+        let now = ic_cdk::api::time() / 1_000_000;
+        match result {
+            Ok(Ok(block_index)) => {
+                RENEWAL_FAILURES.with(|f| f.borrow_mut().remove(&sub.id));
+                extend_subscription(sub.id, &tier, block_index, now);
+            }
+            Ok(Err(err @ (TransferFromError::InsufficientFunds { .. } | TransferFromError::InsufficientAllowance { .. }))) => {
+                renewal_failed(sub.id, true, &format!("{:?}", err), now)
+            }
+            Ok(Err(err)) => renewal_failed(sub.id, false, &format!("{:?}", err), now),
+            Err(err) => renewal_failed(sub.id, false, &format!("{:?}", err), now),
+        }
+    }
+}
+
+/// Renewal charges tried per period before an unavailable ledger ends the membership
+const MAX_RENEWAL_ATTEMPTS: u32 = 3;
+
+/// A renewal charge failed. Running out of funds or allowance ends the membership; other ledger
+/// errors leave it due, so the next runs try again, up to MAX_RENEWAL_ATTEMPTS in all.
+fn renewal_failed(subscription_id: u64, declined: bool, reason: &str, now: u64) {
+    let attempts = RENEWAL_FAILURES.with(|f| {
+        let mut failures = f.borrow_mut();
+        let attempts = failures.entry(subscription_id).or_insert(0);
+        *attempts += 1;
+        *attempts
+    });
+    if declined || attempts >= MAX_RENEWAL_ATTEMPTS {
+        RENEWAL_FAILURES.with(|f| f.borrow_mut().remove(&subscription_id));
+        expire_subscription(subscription_id, &format!("the renewal payment failed ({})", reason), now);
+    }
+}
+
+/// Holds a background job's in-flight flag until dropped, so a run that traps or returns early
+/// never leaves the job blocked.
+struct JobGuard(&'static std::thread::LocalKey<RefCell<bool>>);
+
+impl JobGuard {
+    fn acquire(flag: &'static std::thread::LocalKey<RefCell<bool>>) -> Option<Self> {
+        (!flag.with(|f| f.replace(true))).then_some(JobGuard(flag))
+    }
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        self.0.with(|f| *f.borrow_mut() = false);
+    }
+}
+
+#[ic_cdk::update]
+pub fn create_membership_tier(artist_id: u64, name: String, description: String, price: u64, period_days: u32) -> Result<u64, ApiError> {
+    require_artist_owner(caller(), artist_id)?;
+    validate_tier(&name, price, period_days)?;
+    let id = MEMBERSHIP_TIER_ID.with(|tid| {
+        let mut tid = tid.borrow_mut();
+        let id = *tid;
+        *tid += 1;
+        id
+    });
+    let tier = MembershipTier {
+        id,
+        artist_id,
+        name,
+        description,
+        price,
+        period_days,
+        active: true,
+        created_at: ic_cdk::api::time() / 1_000_000,
+    };
+    MEMBERSHIP_TIERS.with(|tiers| tiers.borrow_mut().push(tier));
+    Ok(id)
+}
+
+// Changes apply from each member's next renewal
+#[ic_cdk::update]
+pub fn update_membership_tier(tier_id: u64, name: String, description: String, price: u64, active: bool) -> Result<MembershipTier, ApiError> {
+    let tier = find_membership_tier(tier_id).ok_or_else(|| ApiError::not_found("membership tier"))?;
+    require_artist_owner(caller(), tier.artist_id)?;
+    validate_tier(&name, price, tier.period_days)?;
+    MEMBERSHIP_TIERS.with(|tiers| {
+        let mut tiers = tiers.borrow_mut();
+        let tier = tiers.iter_mut().find(|t| t.id == tier_id).ok_or_else(|| ApiError::not_found("membership tier"))?;
+        tier.name = name;
+        tier.description = description;
+        tier.price = price;
+        tier.active = active;
+        Ok(tier.clone())
+    })
+}
+
+#[ic_cdk::query]
+pub fn list_membership_tiers(artist_id: u64) -> Vec<MembershipTier> {
+    MEMBERSHIP_TIERS.with(|tiers| tiers.borrow().iter().filter(|t| t.artist_id == artist_id).cloned().collect())
+}
+
+// Join a tier: pulls the first period's price (approved via icrc2_approve). Approving more than
+// one period lets the membership renew automatically.
+#[ic_cdk::update]
+pub async fn subscribe_to_tier(tier_id: u64) -> Result<Subscription, ApiError> {
+    let subscriber = active_caller()?;
+    if subscriber == Principal::anonymous() {
+        return Err(ApiError::unauthorized("anonymous callers cannot subscribe"));
+    }
+    let tier = find_membership_tier(tier_id).filter(|t| t.active).ok_or_else(|| ApiError::not_found("membership tier"))?;
+    if is_subscribed(subscriber, tier.artist_id) {
+        return Err(ApiError::conflict("already a member of this artist; cancel the current membership first"));
+    }
+    let block_index = pull_payment(subscriber, tier.price, subscription_memo(tier.id)).await?;
+    Ok(activate_subscription(subscriber, &tier, block_index, ic_cdk::api::time() / 1_000_000))
+}
+
+// Stop renewing; access continues until the paid period ends
+#[ic_cdk::update]
+pub fn cancel_subscription(subscription_id: u64) -> Result<Subscription, ApiError> {
+    let principal = caller();
+    SUBSCRIPTIONS.with(|subs| {
+        let mut subs = subs.borrow_mut();
+        let sub = subs
+            .iter_mut()
+            .find(|s| s.id == subscription_id && s.subscriber == principal)
+            .ok_or_else(|| ApiError::not_found("subscription"))?;
+        sub.auto_renew = false;
+        Ok(sub.clone())
+    })
+}
+
+#[ic_cdk::query]
+pub fn list_my_subscriptions() -> Vec<Subscription> {
+    let principal = caller();
+    SUBSCRIPTIONS.with(|subs| subs.borrow().iter().filter(|s| s.subscriber == principal).cloned().collect())
+}
+
+#[ic_cdk::query]
+pub fn list_artist_subscribers(artist_id: u64) -> Result<Vec<Subscription>, ApiError> {
+    require_artist_owner_or_admin(caller(), artist_id)?;
+    Ok(SUBSCRIPTIONS.with(|subs| {
+        subs.borrow().iter().filter(|s| s.artist_id == artist_id && s.status == SubscriptionStatus::Active).cloned().collect()
+    }))
+}
+
+// --- Crowdfunding Campaigns ---
+// Track owners raise money for a release in progress. Pledges are pulled from backers into
+// the royalty subaccount and held in the campaign's Escrow journal account. A timer settles
+// campaigns at their deadline: if the goal was met the escrow is distributed by the track's
+// split sheet like a payment, otherwise (or if the campaign is cancelled) every pledge is
+// refunded to its backer, less the ledger fee.
+
+/// How often the timer settles campaigns past their deadline and sends refunds
+const CAMPAIGN_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum CampaignStatus {
+    Active,
+    Succeeded, // escrow released to the track's contributors
+    Failed,    // goal missed; pledges are refunded
+    Cancelled, // closed early by the owner or an admin; pledges are refunded
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RewardTier {
+    pub id: u32,
+    pub title: String,
+    pub description: String,
+    pub min_pledge: u64,
+    pub limit: Option<u32>,
+    pub claimed: u32,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PledgeStatus {
+    Held,
+    Released,
+    RefundPending, // transfer in flight
+    Refunded { block_index: u64 },
+    RefundFailed { reason: String }, // retried on the next run
+}
+
+/// How an admin settles a refund left RefundPending, after checking the ledger.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RefundResolution {
+    /// The transfer reached the backer in `block_index`, charging `fee`
+    Completed { block_index: u64, fee: u64 },
+    /// The transfer never happened; the pledge goes back to escrow and is retried
+    Retry,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Pledge {
+    pub id: u32,
+    pub backer: Principal,
+    pub amount: u64,
+    pub reward_tier: Option<u32>,
+    pub pledged_at: u64,
+    pub block_index: u64,
+    pub status: PledgeStatus,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Campaign {
+    pub id: u64,
+    pub track_id: u64,
+    pub created_by: Principal,
+    pub title: String,
+    pub description: String,
+    pub goal: u64,
+    pub deadline: u64,
+    pub reward_tiers: Vec<RewardTier>,
+    pub pledges: Vec<Pledge>, // the backer list
+    pub total_pledged: u64,
+    pub status: CampaignStatus,
+    pub created_at: u64,
+    pub closed_at: Option<u64>,
+}
+
+fn find_campaign(campaign_id: u64) -> Option<Campaign> {
+    CAMPAIGNS.with(|campaigns| campaigns.borrow().iter().find(|c| c.id == campaign_id).cloned())
+}
+
+fn with_campaign<R>(campaign_id: u64, f: impl FnOnce(&mut Campaign) -> Result<R, ApiError>) -> Result<R, ApiError> {
+    CAMPAIGNS.with(|campaigns| {
+        let mut campaigns = campaigns.borrow_mut();
+        let campaign = campaigns.iter_mut().find(|c| c.id == campaign_id).ok_or_else(|| ApiError::not_found("campaign"))?;
+        f(campaign)
+    })
+}
+
+fn campaign_memo(campaign_id: u64) -> Vec<u8> {
+    [b"campaign:".as_slice(), &campaign_id.to_be_bytes()].concat()
+}
+
+/// The campaign is visible to whoever can see its track.
+fn visible_campaign(campaign_id: u64) -> Result<Campaign, ApiError> {
+    let campaign = find_campaign(campaign_id).ok_or_else(|| ApiError::not_found("campaign"))?;
+    visible_track(campaign.track_id)?;
+    Ok(campaign)
+}
+
+/// Distributes `amount` of the campaign's escrow by the track's current split sheet. Fails,
+/// leaving the escrow untouched, if the track or its splits are gone.
+fn release_escrow(campaign: &Campaign, amount: u64, now: u64) -> Result<(), ApiError> {
+    let track = find_track(campaign.track_id).ok_or_else(|| ApiError::not_found("track"))?;
+    let sheet = current_split_sheet(track.id).ok_or_else(|| ApiError::conflict("track has no agreed royalty splits"))?;
+    distribute_from(RoyaltyAccount::Escrow(campaign.id), Some(track.id), &sheet.splits, effective_fee_bps(&track), amount, None, now);
+    Ok(())
+}
+
+/// A track can't be deleted while it is raising money, since a successful campaign releases its
+/// escrow by the track's splits.
+fn require_no_active_campaign(track_id: u64) -> Result<(), ApiError> {
+    if CAMPAIGNS.with(|c| c.borrow().iter().any(|c| c.track_id == track_id && c.status == CampaignStatus::Active)) {
+        return Err(ApiError::conflict("track has an active campaign"));
+    }
+    Ok(())
+}
+
+/// Claims a reward slot for a pledge about to be made. Returns an error if the tier can't take it.
+fn claim_reward(campaign: &mut Campaign, tier_id: u32, amount: u64) -> Result<(), ApiError> {
+    let tier = campaign.reward_tiers.iter_mut().find(|t| t.id == tier_id).ok_or_else(|| ApiError::not_found("reward tier"))?;
+    if amount < tier.min_pledge {
+        return Err(ApiError::invalid_input("amount", &format!("this reward needs a pledge of at least {}", tier.min_pledge)));
+    }
+    if tier.limit.is_some_and(|limit| tier.claimed >= limit) {
+        return Err(ApiError::conflict("this reward is sold out"));
+    }
+    tier.claimed += 1;
+    Ok(())
+}
+
+fn unclaim_reward(campaign_id: u64, tier_id: u32) {
+    let _ = with_campaign(campaign_id, |campaign| {
+        if let Some(tier) = campaign.reward_tiers.iter_mut().find(|t| t.id == tier_id) {
+            tier.claimed = tier.claimed.saturating_sub(1);
+        }
+        Ok(())
+    });
+}
+
+/// Adds a pledge whose tokens were received in `block_index` and moves them into escrow. A
+/// pledge that lands after its campaign already succeeded is released right away if it still
+/// can be; otherwise, like one that lands after the campaign failed, it is left Held so the
+/// refund job returns it.
+fn record_pledge(campaign_id: u64, backer: Principal, amount: u64, reward_tier: Option<u32>, block_index: u64, now: u64) -> Result<Pledge, ApiError> {
+    post_transaction(now, None, Some(block_index), vec![
+        (JournalEntryKind::Pledge, RoyaltyAccount::Treasury, amount, 0),
+        (JournalEntryKind::Pledge, RoyaltyAccount::Escrow(campaign_id), 0, amount),
+    ]);
+    let (campaign, pledge) = with_campaign(campaign_id, |campaign| {
+        let pledge = Pledge {
+            id: campaign.pledges.len() as u32 + 1,
+            backer,
+            amount,
+            reward_tier,
+            pledged_at: now,
+            block_index,
+            status: PledgeStatus::Held,
+        };
+        campaign.pledges.push(pledge.clone());
+        campaign.total_pledged += amount;
+        Ok((campaign.clone(), pledge))
+    })?;
+    if campaign.status == CampaignStatus::Succeeded && release_escrow(&campaign, amount, now).is_ok() {
+        set_pledge_status(campaign_id, pledge.id, PledgeStatus::Released);
+    }
+    Ok(pledge)
+}
+
+fn set_pledge_status(campaign_id: u64, pledge_id: u32, status: PledgeStatus) {
+    let _ = with_campaign(campaign_id, |campaign| {
+        if let Some(pledge) = campaign.pledges.iter_mut().find(|p| p.id == pledge_id) {
+            pledge.status = status;
+        }
+        Ok(())
+    });
+}
+
+/// Closes every active campaign whose deadline has passed. A funded campaign whose escrow can't
+/// be released is marked Failed so its backers are refunded. Returns the number settled.
+fn settle_due_campaigns(now: u64) -> u32 {
+    let due: Vec<Campaign> = CAMPAIGNS.with(|campaigns| {
+        campaigns.borrow().iter().filter(|c| c.status == CampaignStatus::Active && c.deadline <= now).cloned().collect()
+    });
+    for campaign in &due {
+        let succeeded = campaign.total_pledged >= campaign.goal && release_escrow(campaign, campaign.total_pledged, now).is_ok();
+        let _ = with_campaign(campaign.id, |c| {
+            c.status = if succeeded { CampaignStatus::Succeeded } else { CampaignStatus::Failed };
+            c.closed_at = Some(now);
+            if succeeded {
+                for pledge in c.pledges.iter_mut() {
+                    pledge.status = PledgeStatus::Released;
+                }
+            }
+            Ok(())
+        });
+    }
+    due.len() as u32
+}
+
+/// Pledges still to be returned: (campaign id, pledge). These are the unrefunded pledges of
+/// failed or cancelled campaigns, and late pledges to a succeeded campaign that couldn't be
+/// released.
+fn due_refunds() -> Vec<(u64, Pledge)> {
+    CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .flat_map(|c| {
+                let refunding = matches!(c.status, CampaignStatus::Failed | CampaignStatus::Cancelled);
+                c.pledges
+                    .iter()
+                    .filter(move |p| match p.status {
+                        PledgeStatus::Held => refunding || c.status == CampaignStatus::Succeeded,
+                        PledgeStatus::RefundFailed { .. } => true,
+                        _ => false,
+                    })
+                    .map(move |p| (c.id, p.clone()))
+            })
+            .collect()
+    })
+}
+
+/// Moves a pledge out of escrow into PendingPayouts while its refund transfer runs.
+fn reserve_refund(campaign_id: u64, pledge: &Pledge, now: u64) {
+    post_transaction(now, None, None, vec![
+        (JournalEntryKind::Refund, RoyaltyAccount::Escrow(campaign_id), pledge.amount, 0),
+        (JournalEntryKind::Refund, RoyaltyAccount::PendingPayouts, 0, pledge.amount),
+    ]);
+    set_pledge_status(campaign_id, pledge.id, PledgeStatus::RefundPending);
+}
+
+fn settle_refund(campaign_id: u64, pledge: &Pledge, fee: u64, block_index: u64, now: u64) {
+    post_transaction(now, None, Some(block_index), vec![
+        (JournalEntryKind::Refund, RoyaltyAccount::PendingPayouts, pledge.amount, 0),
+        (JournalEntryKind::Refund, RoyaltyAccount::Treasury, 0, pledge.amount - fee),
+        (JournalEntryKind::Fee, RoyaltyAccount::Treasury, 0, fee),
+    ]);
+    set_pledge_status(campaign_id, pledge.id, PledgeStatus::Refunded { block_index });
+}
+
+fn release_refund(campaign_id: u64, pledge: &Pledge, reason: String, now: u64) {
+    post_transaction(now, None, None, vec![
+        (JournalEntryKind::Reversal, RoyaltyAccount::PendingPayouts, pledge.amount, 0),
+        (JournalEntryKind::Reversal, RoyaltyAccount::Escrow(campaign_id), 0, pledge.amount),
+    ]);
+    set_pledge_status(campaign_id, pledge.id, PledgeStatus::RefundFailed { reason });
+}
+
+/// Settles a RefundPending pledge by hand. Only allowed while no refund job is running, since
+/// the job may still be awaiting that pledge's transfer.
+fn resolve_refund(campaign_id: u64, pledge_id: u32, resolution: RefundResolution, now: u64) -> Result<PledgeStatus, ApiError> {
+    if CAMPAIGN_JOBS_IN_FLIGHT.with(|f| *f.borrow()) {
+        return Err(ApiError::conflict("refunds are being processed; try again shortly"));
+    }
+    let campaign = find_campaign(campaign_id).ok_or_else(|| ApiError::not_found("campaign"))?;
+    let pledge = campaign.pledges.iter().find(|p| p.id == pledge_id).cloned().ok_or_else(|| ApiError::not_found("pledge"))?;
+    if pledge.status != PledgeStatus::RefundPending {
+        return Err(ApiError::conflict("pledge has no refund pending"));
+    }
+    match resolution {
+        RefundResolution::Completed { fee, .. } if fee > pledge.amount => {
+            return Err(ApiError::invalid_input("fee", "must not exceed the pledge"));
+        }
+        RefundResolution::Completed { block_index, fee } => settle_refund(campaign_id, &pledge, fee, block_index, now),
+        RefundResolution::Retry => release_refund(campaign_id, &pledge, "returned to escrow by an admin".to_string(), now),
+    }
+    Ok(find_campaign(campaign_id).and_then(|c| c.pledges.into_iter().find(|p| p.id == pledge_id)).map(|p| p.status).unwrap_or(pledge.status))
+}
+
+/// Settles due campaigns, then refunds backers of failed and cancelled ones. Runs from the
+/// campaign timer. A refund interrupted by an upgrade stays RefundPending until an admin checks
+/// the ledger and settles it with `resolve_pledge_refund`. Pledges no larger than the ledger fee
+/// can't be sent and are left for an admin too rather than retried.
+async fn process_campaigns() {
+    let Some(_running) = JobGuard::acquire(&CAMPAIGN_JOBS_IN_FLIGHT) else { return };
+    settle_due_campaigns(ic_cdk::api::time() / 1_000_000);
+    let refunds = due_refunds();
+    let fee = if refunds.is_empty() { Ok(0) } else { ledger_fee().await };
+    let Ok(fee) = fee else { return };
+    for (campaign_id, pledge) in refunds.into_iter().filter(|(_, p)| p.amount > fee) {
+        reserve_refund(campaign_id, &pledge, ic_cdk::api::time() / 1_000_000);
+        let result = send_payout(pledge.backer, pledge.amount).await;
+        let now = ic_cdk::api::time() / 1_000_000;
+        match result {
+            Ok((block_index, fee)) => settle_refund(campaign_id, &pledge, fee, block_index, now),
+            Err(err) => release_refund(campaign_id, &pledge, format!("{:?}", err), now),
+        }
+    }
+}
+
+// Start a campaign for a track the caller owns. Reward tier ids and claim counts are assigned here.
+#[ic_cdk::update]
+pub fn create_campaign(
+    track_id: u64,
+    title: String,
+    description: String,
+    goal: u64,
+    deadline: u64,
+    reward_tiers: Vec<RewardTier>,
+) -> Result<u64, ApiError> {
+    let principal = caller();
+    let now = ic_cdk::api::time() / 1_000_000;
+    let track = visible_track(track_id)?;
+    require_track_role(principal, &track, TrackRole::Owner)?;
+    require_non_empty("title", &title)?;
+    if goal == 0 {
+        return Err(ApiError::invalid_input("goal", "must be greater than zero"));
+    }
+    if deadline <= now {
+        return Err(ApiError::invalid_input("deadline", "must be in the future"));
+    }
+    if current_split_sheet(track_id).is_none() {
+        return Err(ApiError::conflict("track has no agreed royalty splits to release funds by"));
+    }
+    if CAMPAIGNS.with(|c| c.borrow().iter().any(|c| c.track_id == track_id && c.status == CampaignStatus::Active)) {
+        return Err(ApiError::conflict("track already has an active campaign"));
+    }
+    for tier in &reward_tiers {
+        require_non_empty("reward_tiers.title", &tier.title)?;
+    }
+    let reward_tiers = reward_tiers
+        .into_iter()
+        .enumerate()
+        .map(|(i, tier)| RewardTier { id: i as u32 + 1, claimed: 0, ..tier })
+        .collect();
+    let id = CAMPAIGN_ID.with(|cid| {
+        let mut cid = cid.borrow_mut();
+        let id = *cid;
+        *cid += 1;
+        id
+    });
+    CAMPAIGNS.with(|campaigns| {
+        campaigns.borrow_mut().push(Campaign {
+            id,
+            track_id,
+            created_by: principal,
+            title,
+            description,
+            goal,
+            deadline,
+            reward_tiers,
+            pledges: vec![],
+            total_pledged: 0,
+            status: CampaignStatus::Active,
+            created_at: now,
+            closed_at: None,
+        })
+    });
+    Ok(id)
+}
+
+// Back a campaign: pulls `amount` from the caller (approved via icrc2_approve) into escrow.
+#[ic_cdk::update]
+pub async fn pledge_to_campaign(campaign_id: u64, amount: u64, reward_tier: Option<u32>) -> Result<Pledge, ApiError> {
+    let backer = active_caller()?;
+    if backer == Principal::anonymous() {
+        return Err(ApiError::unauthorized("anonymous callers cannot pledge"));
+    }
+    // A pledge the ledger fee would swallow could never be refunded
+    let fee = ledger_fee().await?;
+    if amount <= fee {
+        return Err(ApiError::invalid_input("amount", &format!("must exceed the ledger fee of {}", fee)));
+    }
+    let campaign = visible_campaign(campaign_id)?;
+    if campaign.status != CampaignStatus::Active || campaign.deadline <= ic_cdk::api::time() / 1_000_000 {
+        return Err(ApiError::conflict("campaign is closed"));
+    }
+    // Hold the reward slot across the transfer so a limited tier can't be oversold
+    if let Some(tier_id) = reward_tier {
+        with_campaign(campaign_id, |campaign| claim_reward(campaign, tier_id, amount))?;
+    }
+    match pull_payment(backer, amount, campaign_memo(campaign_id)).await {
+        Ok(block_index) => record_pledge(campaign_id, backer, amount, reward_tier, block_index, ic_cdk::api::time() / 1_000_000),
+        Err(err) => {
+            if let Some(tier_id) = reward_tier {
+                unclaim_reward(campaign_id, tier_id);
+            }
+            Err(err)
+        }
+    }
+}
+
+// Close a campaign early; its backers are refunded by the timer
+#[ic_cdk::update]
+pub fn cancel_campaign(campaign_id: u64) -> Result<(), ApiError> {
+    let principal = active_caller()?;
+    let campaign = find_campaign(campaign_id).ok_or_else(|| ApiError::not_found("campaign"))?;
+    let owns_track = find_track(campaign.track_id).is_some_and(|track| has_track_role(principal, &track, &TrackRole::Owner));
+    if !owns_track {
+        require_admin(principal)?;
+    }
+    let now = ic_cdk::api::time() / 1_000_000;
+    with_campaign(campaign_id, |campaign| {
+        if campaign.status != CampaignStatus::Active {
+            return Err(ApiError::conflict("campaign is already closed"));
+        }
+        campaign.status = CampaignStatus::Cancelled;
+        campaign.closed_at = Some(now);
+        Ok(())
+    })?;
+    if !owns_track {
+        log_admin_action(principal, "cancel_campaign", "Campaign", &campaign_id.to_string(), None);
+    }
+    Ok(())
+}
+
+#[ic_cdk::query]
+pub fn get_campaign(campaign_id: u64) -> Result<Campaign, ApiError> {
+    visible_campaign(campaign_id)
+}
+
+#[ic_cdk::query]
+pub fn list_track_campaigns(track_id: u64) -> Result<Vec<Campaign>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(CAMPAIGNS.with(|campaigns| campaigns.borrow().iter().filter(|c| c.track_id == track_id).cloned().collect()))
+}
+
+/// Pledges waiting on a refund: unsent, failed and RefundPending ones, as (campaign id, pledge).
+#[ic_cdk::query]
+pub fn list_pending_refunds() -> Result<Vec<(u64, Pledge)>, ApiError> {
+    require_admin(caller())?;
+    let mut pending = due_refunds();
+    pending.extend(CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .flat_map(|c| c.pledges.iter().filter(|p| p.status == PledgeStatus::RefundPending).map(move |p| (c.id, p.clone())))
+            .collect::<Vec<_>>()
+    }));
+    Ok(pending)
+}
+
+#[ic_cdk::update]
+pub fn resolve_pledge_refund(campaign_id: u64, pledge_id: u32, resolution: RefundResolution) -> Result<PledgeStatus, ApiError> {
+    let admin = caller();
+    require_admin(admin)?;
+    let details = format!("Pledge {} resolved: {:?}", pledge_id, resolution);
+    let status = resolve_refund(campaign_id, pledge_id, resolution, ic_cdk::api::time() / 1_000_000)?;
+    log_admin_action(admin, "resolve_pledge_refund", "Campaign", &campaign_id.to_string(), Some(details));
+    Ok(status)
+}
+
+#[ic_cdk::query]
+pub fn list_my_pledges() -> Vec<(u64, Pledge)> {
+    let principal = caller();
+    CAMPAIGNS.with(|campaigns| {
+        campaigns
+            .borrow()
+            .iter()
+            .flat_map(|c| c.pledges.iter().filter(|p| p.backer == principal).map(move |p| (c.id, p.clone())))
+            .collect()
+    })
+}
+
+// --- Track Assets & Chunked Uploads ---
+// A track can hold any number of assets (masters, stems, previews, artwork). Asset bytes are cut
+// into fixed-size chunks so they can be uploaded and read within message size limits. Each chunk
+// is written to its own chunk-sized slot in the chunk data region, ASSET_CHUNKS maps
+// (asset id, chunk index) to the slot, and slots of deleted assets are kept in FREE_CHUNK_SLOTS
+// for reuse. Only metadata is kept on the heap.
+
+/// Every chunk but the last one of an asset is exactly this size: 1MB
+const ASSET_CHUNK_SIZE: usize = 1024 * 1024;
+/// Max asset size: 200MB
+const MAX_ASSET_SIZE: u64 = 200 * 1024 * 1024;
+/// Most file data a single query response carries: 2MB
+const MAX_INLINE_FILE_SIZE: u64 = 2 * 1024 * 1024;
+/// Uncommitted upload sessions are discarded after 24 hours (in ms)
+const UPLOAD_SESSION_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum AssetKind {
+    Master,
+    Stem,
+    Preview,
+    Artwork,
+}
+
+impl AssetKind {
+    /// Artwork must be an image; every other kind is audio.
+    fn accepts(&self, content_type: &str) -> bool {
+        match self {
+            AssetKind::Artwork => content_type.starts_with("image/"),
+            _ => content_type.starts_with("audio/"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TrackAsset {
+    pub id: u64,
+    pub track_id: u64,
+    pub kind: AssetKind,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    pub chunk_count: u32,
+    pub sha256: Vec<u8>,
+    pub uploaded_by: Principal,
+    pub uploaded_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct UploadSession {
+    pub id: u64,
+    pub asset_id: u64, // id the asset gets on commit; chunks are stored under it while uploading
+    pub track_id: u64,
+    pub kind: AssetKind,
+    pub filename: String,
+    pub content_type: String,
+    pub total_size: u64,
+    pub received_chunks: Vec<u32>,
+    pub created_by: Principal,
+    pub created_at: u64,
+}
+
+/// Legacy single-file-per-track record. Still returned by `get_track_file`.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TrackFile {
+    pub track_id: u64,
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+    pub uploaded_by: Principal,
+    pub uploaded_at: u64,
+}
+
+/// Where one asset chunk's bytes are, keyed by (asset id, chunk index) in ASSET_CHUNKS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ChunkSlot {
+    slot: u64,
+    len: u32,
+}
+
+impl Storable for ChunkSlot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.slot.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+        Cow::Owned(bytes)
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ChunkSlot {
+            slot: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            len: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+        }
+    }
+}
+
+impl BoundedStorable for ChunkSlot {
+    const MAX_SIZE: u32 = 12;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+/// Wasm pages in one chunk slot
+const CHUNK_SLOT_PAGES: u64 = ASSET_CHUNK_SIZE as u64 / 65_536;
+
+fn chunk_data_memory() -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(ASSET_CHUNK_DATA_MEMORY_ID))
+}
+
+/// A free slot, reusing one left by a deleted asset before growing the data region.
+fn allocate_chunk_slot() -> u64 {
+    let freed = FREE_CHUNK_SLOTS.with(|free| {
+        let mut free = free.borrow_mut();
+        let slot = free.iter().next().map(|(slot, _)| slot)?;
+        free.remove(&slot);
+        Some(slot)
+    });
+    freed.unwrap_or_else(|| {
+        let memory = chunk_data_memory();
+        let slot = memory.size() / CHUNK_SLOT_PAGES;
+        if memory.grow(CHUNK_SLOT_PAGES) == -1 {
+            ic_cdk::trap("out of stable memory for asset chunks");
+        }
+        slot
+    })
+}
+
+/// Stores chunk `index` of an asset, overwriting it in place if it was already sent.
+fn write_chunk(asset_id: u64, index: u32, data: &[u8]) {
+    let slot = ASSET_CHUNKS.with(|chunks| chunks.borrow().get(&(asset_id, index)))
+        .map(|chunk| chunk.slot)
+        .unwrap_or_else(allocate_chunk_slot);
+    chunk_data_memory().write(slot * ASSET_CHUNK_SIZE as u64, data);
+    ASSET_CHUNKS.with(|chunks| chunks.borrow_mut().insert((asset_id, index), ChunkSlot { slot, len: data.len() as u32 }));
+}
+
+/// Bytes `from..to` of a stored chunk.
+fn read_chunk_bytes(chunk: &ChunkSlot, from: usize, to: usize) -> Vec<u8> {
+    let mut data = vec![0; to - from];
+    chunk_data_memory().read(chunk.slot * ASSET_CHUNK_SIZE as u64 + from as u64, &mut data);
+    data
+}
+
+fn read_chunk(asset_id: u64, index: u32) -> Option<Vec<u8>> {
+    let chunk = ASSET_CHUNKS.with(|chunks| chunks.borrow().get(&(asset_id, index)))?;
+    Some(read_chunk_bytes(&chunk, 0, chunk.len as usize))
+}
+
+/// Every stored chunk of an asset, in order.
+fn asset_chunks(asset_id: u64, chunk_count: u32) -> Vec<ChunkSlot> {
+    ASSET_CHUNKS.with(|chunks| chunks.borrow().range((asset_id, 0)..(asset_id, chunk_count)).map(|(_, chunk)| chunk).collect())
+}
+
+fn chunk_count(size: u64) -> u32 {
+    size.div_ceil(ASSET_CHUNK_SIZE as u64) as u32
+}
+
+/// Length chunk `index` must have in an asset of `size` bytes.
+fn expected_chunk_len(size: u64, index: u32) -> usize {
+    let start = index as u64 * ASSET_CHUNK_SIZE as u64;
+    size.saturating_sub(start).min(ASSET_CHUNK_SIZE as u64) as usize
+}
+
+fn asset_sha256(asset_id: u64, chunk_count: u32) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for chunk in asset_chunks(asset_id, chunk_count) {
+        hasher.update(read_chunk_bytes(&chunk, 0, chunk.len as usize));
+    }
+    hasher.finalize().to_vec()
+}
+
+fn remove_asset_chunks(asset_id: u64) {
+    let freed: Vec<u64> = ASSET_CHUNKS.with(|chunks| {
+        let mut chunks = chunks.borrow_mut();
+        let keys: Vec<(u64, u32)> = chunks.range((asset_id, 0)..=(asset_id, u32::MAX)).map(|(key, _)| key).collect();
+        keys.iter().filter_map(|key| chunks.remove(key)).map(|chunk| chunk.slot).collect()
+    });
+    FREE_CHUNK_SLOTS.with(|free| {
+        let mut free = free.borrow_mut();
+        for slot in freed {
+            free.insert(slot, ());
+        }
+    });
+}
+
+/// Writes `data` into the chunk store as a new asset and records its metadata.
+fn store_asset(track_id: u64, kind: AssetKind, filename: String, content_type: String, data: &[u8], uploaded_by: Principal, uploaded_at: u64) -> TrackAsset {
+    let id = ASSET_ID.with(|aid| {
+        let mut aid = aid.borrow_mut();
+        let id = *aid;
+        *aid += 1;
+        id
+    });
+    for (index, chunk) in data.chunks(ASSET_CHUNK_SIZE).enumerate() {
+        write_chunk(id, index as u32, chunk);
+    }
+    let asset = TrackAsset {
+        id,
+        track_id,
+        kind,
+        filename,
+        content_type,
+        size: data.len() as u64,
+        chunk_count: chunk_count(data.len() as u64),
+        sha256: Sha256::digest(data).to_vec(),
+        uploaded_by,
+        uploaded_at,
+    };
+    TRACK_ASSETS.with(|assets| assets.borrow_mut().push(asset.clone()));
+    asset
+}
+
+/// Drops every asset and pending upload belonging to a deleted track.
+fn delete_track_assets(track_id: u64) {
+    let asset_ids: Vec<u64> = TRACK_ASSETS.with(|assets| {
+        let mut assets = assets.borrow_mut();
+        let ids = assets.iter().filter(|a| a.track_id == track_id).map(|a| a.id).collect();
+        assets.retain(|a| a.track_id != track_id);
+        ids
+    });
+    let pending: Vec<u64> = UPLOAD_SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        let ids = sessions.iter().filter(|s| s.track_id == track_id).map(|s| s.asset_id).collect();
+        sessions.retain(|s| s.track_id != track_id);
+        ids
+    });
+    for asset_id in asset_ids.into_iter().chain(pending) {
+        remove_asset_chunks(asset_id);
+    }
+}
+
+fn discard_expired_uploads(now: u64) {
+    let expired: Vec<u64> = UPLOAD_SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        let ids = sessions.iter().filter(|s| now.saturating_sub(s.created_at) > UPLOAD_SESSION_TTL_MS).map(|s| s.asset_id).collect();
+        sessions.retain(|s| now.saturating_sub(s.created_at) <= UPLOAD_SESSION_TTL_MS);
+        ids
+    });
+    for asset_id in expired {
+        remove_asset_chunks(asset_id);
+    }
+}
+
+/// The caller's own upload session.
+fn upload_session_of(principal: Principal, session_id: u64) -> Result<UploadSession, ApiError> {
+    let session = UPLOAD_SESSIONS.with(|sessions| sessions.borrow().iter().find(|s| s.id == session_id).cloned())
+        .ok_or_else(|| ApiError::not_found("upload session"))?;
+    if session.created_by != principal {
+        return Err(ApiError::unauthorized("upload session belongs to another caller"));
+    }
+    Ok(session)
+}
+
+/// The most recently uploaded asset of `kind` on a track.
+fn latest_asset(track_id: u64, kind: AssetKind) -> Option<TrackAsset> {
+    TRACK_ASSETS.with(|assets| assets.borrow().iter().rev().find(|a| a.track_id == track_id && a.kind == kind).cloned())
+}
+
+/// Header magic of a `StableBTreeMap`. The first deployed build kept its one-file-per-track
+/// `TRACK_FILES` map directly in raw stable memory, before there was a memory manager.
+const LEGACY_TRACK_FILES_MAGIC: &[u8; 3] = b"BTR";
+
+/// Copies the files out of a `TRACK_FILES` map left in raw stable memory by the first deployed
+/// build, then wipes its header so the memory manager lays out a fresh region table over it.
+/// Must run before anything touches `MEMORY_MANAGER`.
+fn take_legacy_track_files<M: ic_stable_structures::Memory + Clone>(memory: &M) -> Vec<TrackFile> {
+    if memory.size() == 0 {
+        return vec![];
+    }
+    let mut magic = [0u8; 3];
+    memory.read(0, &mut magic);
+    if &magic != LEGACY_TRACK_FILES_MAGIC {
+        return vec![];
+    }
+    let files = StableBTreeMap::<u64, TrackFile, M>::load(memory.clone()).iter().map(|(_, file)| file).collect();
+    memory.write(0, &[0; 3]);
+    files
+}
+
+/// Moves files saved by the old one-file-per-track store into the asset store as masters.
+fn migrate_legacy_track_files(files: Vec<TrackFile>) {
+    for file in files {
+        store_asset(file.track_id, AssetKind::Master, file.filename, file.content_type, &file.data, file.uploaded_by, file.uploaded_at);
+    }
+}
+
+// Start a chunked upload. Chunks are then sent with put_chunk and the asset is created by commit_upload.
+#[ic_cdk::update]
+pub fn begin_upload(track_id: u64, kind: AssetKind, filename: String, content_type: String, total_size: u64) -> Result<UploadSession, ApiError> {
+    let uploader = active_caller()?;
+    require_non_empty("filename", &filename)?;
+    if !kind.accepts(&content_type) {
+        return Err(ApiError::invalid_input("content_type", &format!("not valid for a {:?} asset", kind)));
+    }
+    if total_size == 0 || total_size > MAX_ASSET_SIZE {
+        return Err(ApiError::invalid_input("total_size", "must be between 1 byte and 200MB"));
+    }
+    // Only owner or collaborator can upload
+    with_track_as(track_id, TrackRole::Collaborator, |_| ())?;
+    let now = ic_cdk::api::time() / 1_000_000;
+    discard_expired_uploads(now);
+    let asset_id = ASSET_ID.with(|aid| {
+        let mut aid = aid.borrow_mut();
+        let id = *aid;
+        *aid += 1;
+        id
+    });
+    let id = UPLOAD_SESSION_ID.with(|sid| {
+        let mut sid = sid.borrow_mut();
+        let id = *sid;
+        *sid += 1;
+        id
+    });
+    let session = UploadSession {
+        id,
+        asset_id,
+        track_id,
+        kind,
+        filename,
+        content_type,
+        total_size,
+        received_chunks: vec![],
+        created_by: uploader,
+        created_at: now,
+    };
+    UPLOAD_SESSIONS.with(|sessions| sessions.borrow_mut().push(session.clone()));
+    Ok(session)
+}
+
+// Store one chunk. Chunks may arrive in any order and be re-sent; every chunk but the last must be 1MB.
+#[ic_cdk::update]
+pub fn put_chunk(session_id: u64, index: u32, data: Vec<u8>) -> Result<(), ApiError> {
+    let session = upload_session_of(active_caller()?, session_id)?;
+    if index >= chunk_count(session.total_size) {
+        return Err(ApiError::invalid_input("index", "beyond the declared upload size"));
+    }
+    let expected_len = expected_chunk_len(session.total_size, index);
+    if data.len() != expected_len {
+        return Err(ApiError::invalid_input("data", &format!("chunk {} must be {} bytes", index, expected_len)));
+    }
+    write_chunk(session.asset_id, index, &data);
+    UPLOAD_SESSIONS.with(|sessions| {
+        if let Some(s) = sessions.borrow_mut().iter_mut().find(|s| s.id == session_id) {
+            if !s.received_chunks.contains(&index) {
+                s.received_chunks.push(index);
+            }
+        }
+    });
+    Ok(())
+}
+
+// Finish an upload once every chunk is in and the data hashes to `sha256`.
+#[ic_cdk::update]
+pub fn commit_upload(session_id: u64, sha256: Vec<u8>) -> Result<TrackAsset, ApiError> {
+    let session = upload_session_of(active_caller()?, session_id)?;
+    let chunk_count = chunk_count(session.total_size);
+    if let Some(missing) = (0..chunk_count).find(|i| !session.received_chunks.contains(i)) {
+        return Err(ApiError::conflict(&format!("chunk {} has not been uploaded", missing)));
+    }
+    let digest = asset_sha256(session.asset_id, chunk_count);
+    if digest != sha256 {
+        return Err(ApiError::invalid_input("sha256", "does not match the uploaded data"));
+    }
+    // The uploader may have lost their role since the session began
+    with_track_as(session.track_id, TrackRole::Collaborator, |_| ())?;
+    let asset = TrackAsset {
+        id: session.asset_id,
+        track_id: session.track_id,
+        kind: session.kind,
+        filename: session.filename,
+        content_type: session.content_type,
+        size: session.total_size,
+        chunk_count,
+        sha256: digest,
+        uploaded_by: session.created_by,
+        uploaded_at: ic_cdk::api::time() / 1_000_000,
+    };
+    UPLOAD_SESSIONS.with(|sessions| sessions.borrow_mut().retain(|s| s.id != session_id));
+    TRACK_ASSETS.with(|assets| assets.borrow_mut().push(asset.clone()));
+    Ok(asset)
+}
+
+#[ic_cdk::update]
+pub fn abort_upload(session_id: u64) -> Result<(), ApiError> {
+    let session = upload_session_of(active_caller()?, session_id)?;
+    UPLOAD_SESSIONS.with(|sessions| sessions.borrow_mut().retain(|s| s.id != session_id));
+    remove_asset_chunks(session.asset_id);
+    Ok(())
+}
+
+#[ic_cdk::query]
+pub fn list_track_assets(track_id: u64) -> Result<Vec<TrackAsset>, ApiError> {
+    require_track_visible(track_id)?;
+    Ok(TRACK_ASSETS.with(|assets| assets.borrow().iter().filter(|a| a.track_id == track_id).cloned().collect()))
+}
+
+#[ic_cdk::update]
+pub fn delete_track_asset(track_id: u64, asset_id: u64) -> Result<(), ApiError> {
+    with_track_as(track_id, TrackRole::Collaborator, |_| ())?;
+    let removed = TRACK_ASSETS.with(|assets| {
+        let mut assets = assets.borrow_mut();
+        let len_before = assets.len();
+        assets.retain(|a| !(a.id == asset_id && a.track_id == track_id));
+        assets.len() < len_before
+    });
+    if !removed {
+        return Err(ApiError::not_found("asset"));
+    }
+    remove_asset_chunks(asset_id);
+    Ok(())
+}
+
+// Single-call upload of a master for files that fit in one message
+#[ic_cdk::update]
+pub fn upload_track_file(track_id: u64, filename: String, content_type: String, data: Vec<u8>) -> Result<TrackAsset, ApiError> {
+    let session = begin_upload(track_id, AssetKind::Master, filename, content_type, data.len() as u64)?;
+    for (index, chunk) in data.chunks(ASSET_CHUNK_SIZE).enumerate() {
+        put_chunk(session.id, index as u32, chunk.to_vec())?;
+    }
+    commit_upload(session.id, Sha256::digest(&data).to_vec())
+}
+
+// The track's most recent master, returned whole. Larger files must be read in chunks. A query,
+// so the fetch isn't counted; players count it with open_track_stream.
+#[ic_cdk::query]
+pub fn get_track_file(track_id: u64) -> Result<TrackFile, ApiError> {
+    let track = visible_track(track_id)?;
+    require_track_access(caller(), &track, master_access(&track))?;
+    let asset = latest_asset(track_id, AssetKind::Master).ok_or_else(|| ApiError::not_found("track file"))?;
+    if asset.size > MAX_INLINE_FILE_SIZE {
+        return Err(ApiError::conflict("file is too large to return in one response"));
+    }
+    let data = asset_chunks(asset.id, asset.chunk_count).iter().flat_map(|chunk| read_chunk_bytes(chunk, 0, chunk.len as usize)).collect();
+    Ok(TrackFile {
+        track_id,
+        filename: asset.filename,
+        content_type: asset.content_type,
+        data,
+        uploaded_by: asset.uploaded_by,
+        uploaded_at: asset.uploaded_at,
+    })
+}
+
+// --- Asset Streaming ---
+// Players fetch asset metadata, call `open_track_stream` once per play or download (an update, so
+// the count is persisted), then read the bytes progressively with `get_track_file_chunk` queries.
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum StreamPurpose {
+    Play,
+    Download,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StreamInfo {
+    pub asset: TrackAsset,
+    pub chunk_size: u64,
+    pub max_read_len: u64,
+}
+
+/// The asset if the caller may read its bytes. Previews and artwork are open to anyone who can
+/// see the track; masters need `master_access` and stems download access.
+fn readable_asset(track_id: u64, asset_id: u64) -> Result<TrackAsset, ApiError> {
+    require_not_banned(caller())?;
+    let track = visible_track(track_id)?;
+    let asset = TRACK_ASSETS.with(|assets| assets.borrow().iter().find(|a| a.id == asset_id && a.track_id == track_id).cloned())
+        .ok_or_else(|| ApiError::not_found("asset"))?;
+    match asset.kind {
+        AssetKind::Preview | AssetKind::Artwork => {}
+        AssetKind::Master => require_track_access(caller(), &track, master_access(&track))?,
+        AssetKind::Stem => require_track_access(caller(), &track, TrackAccess::Download)?,
+    }
+    Ok(asset)
+}
+
+fn stream_info(asset: TrackAsset) -> StreamInfo {
+    StreamInfo { asset, chunk_size: ASSET_CHUNK_SIZE as u64, max_read_len: MAX_INLINE_FILE_SIZE }
+}
+
+/// Bytes `offset..offset + len` of an asset, clamped to its end.
+fn read_asset_range(asset: &TrackAsset, offset: u64, len: u64) -> Result<Vec<u8>, ApiError> {
+    if offset >= asset.size {
+        return Err(ApiError::invalid_input("offset", &format!("must be below the asset size of {} bytes", asset.size)));
+    }
+    if len == 0 || len > MAX_INLINE_FILE_SIZE {
+        return Err(ApiError::invalid_input("len", "must be between 1 byte and 2MB"));
+    }
+    let end = (offset + len).min(asset.size);
+    let chunk_size = ASSET_CHUNK_SIZE as u64;
+    let first = (offset / chunk_size) as u32;
+    let last = ((end - 1) / chunk_size) as u32;
+    let chunks: Vec<((u64, u32), ChunkSlot)> = ASSET_CHUNKS.with(|chunks| chunks.borrow().range((asset.id, first)..=(asset.id, last)).collect());
+    let mut data = Vec::with_capacity((end - offset) as usize);
+    for ((_, index), chunk) in chunks {
+        let chunk_start = index as u64 * chunk_size;
+        let from = offset.saturating_sub(chunk_start) as usize;
+        let to = (end - chunk_start).min(chunk.len as u64) as usize;
+        data.extend(read_chunk_bytes(&chunk, from, to));
+    }
+    Ok(data)
+}
+
+#[ic_cdk::query]
+pub fn get_track_asset_info(track_id: u64, asset_id: u64) -> Result<StreamInfo, ApiError> {
+    readable_asset(track_id, asset_id).map(stream_info)
+}
+
+// Start a play or download of an asset and count it in PLAY_COUNTS. Downloads need a downloadable
+// track. Opening a play stream only counts a raw play; clients report the listen with record_play.
+#[ic_cdk::update]
+pub fn open_track_stream(track_id: u64, asset_id: u64, purpose: StreamPurpose) -> Result<StreamInfo, ApiError> {
+    let asset = readable_asset(track_id, asset_id)?;
+    count_stream(track_id, purpose)?;
+    Ok(stream_info(asset))
+}
+
+/// Counts a play (raw only) or download of `track_id` by the caller.
+fn count_stream(track_id: u64, purpose: StreamPurpose) -> Result<(), ApiError> {
+    match purpose {
+        StreamPurpose::Play => {
+            let principal = require_identified_listener()?;
+            let now = ic_cdk::api::time() / 1_000_000;
+            check_rate_limit(principal, "record_play", now)?;
+            count_play(principal, track_id, None, now);
+            Ok(())
+        }
+        StreamPurpose::Download => record_download(track_id),
+    }
+}
+
+// Read up to 2MB of an asset starting at `offset`; the result is shorter at the end of the asset.
+#[ic_cdk::query]
+pub fn get_track_file_chunk(track_id: u64, asset_id: u64, offset: u64, len: u64) -> Result<Vec<u8>, ApiError> {
+    let asset = readable_asset(track_id, asset_id)?;
+    read_asset_range(&asset, offset, len)
+}
+
+// --- HTTP Gateway ---
+// Serves public tracks and artist profiles over plain HTTP so they can be linked and embedded:
+//   /tracks/{id}/audio, /tracks/{id}/artwork, /tracks/{id}.json and /artists/{id}.json
+// Gateway requests arrive as anonymous queries and responses are not certified, so these URLs
+// must be fetched through the raw domain (`<canister-id>.raw.icp0.io`).
+// Queries can't persist anything, so a GET that starts playing a track's audio (no range, or one
+// from byte 0) is answered with `upgrade` and the gateway repeats it as `http_request_update`,
+// which adds to the track's `gateway_play_count`. These plays are anonymous, so they are kept out
+// of PLAY_COUNTS and its burst flagging; instead the whole gateway may upgrade at most
+// MAX_GATEWAY_PLAYS_PER_WINDOW requests per window and serves the rest as uncounted queries.
+// Later ranges and streaming callbacks continue a play already counted and stay queries.
+
+const GATEWAY_PLAY_WINDOW_MS: u64 = 60 * 60 * 1000;
+const MAX_GATEWAY_PLAYS_PER_WINDOW: u64 = 1_000;
+
+pub type HeaderField = (String, String);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: Vec<u8>,
+    pub streaming_strategy: Option<StreamingStrategy>,
+    pub upgrade: Option<bool>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StreamingCallbackToken {
+    pub track_id: u64,
+    pub asset_id: u64,
+    pub chunk_index: u32,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: Vec<u8>,
+    pub token: Option<StreamingCallbackToken>,
+}
+
+candid::define_function!(pub StreamingCallback : (StreamingCallbackToken) -> (StreamingCallbackHttpResponse) query);
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum StreamingStrategy {
+    Callback { callback: StreamingCallback, token: StreamingCallbackToken },
+}
+
+#[derive(Serialize)]
+struct TrackJson {
+    id: u64,
+    title: String,
+    description: String,
+    contributors: Vec<u64>,
+    genre: Option<String>,
+    tags: Vec<String>,
+    play_count: u64,
+    downloadable: bool,
+    audio_url: Option<String>,
+    artwork_url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ArtistJson {
+    id: u64,
+    name: String,
+    bio: String,
+    social: Option<String>,
+    profile_image_url: Option<String>,
+    links: Option<Vec<String>>,
+    tracks: Vec<u64>, // public tracks only
+}
+
+enum HttpRoute {
+    TrackAudio(u64),
+    TrackArtwork(u64),
+    TrackJson(u64),
+    ArtistJson(u64),
+}
+
+fn parse_route(url: &str) -> Option<HttpRoute> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["tracks", id, "audio"] => id.parse().ok().map(HttpRoute::TrackAudio),
+        ["tracks", id, "artwork"] => id.parse().ok().map(HttpRoute::TrackArtwork),
+        ["tracks", file] => file.strip_suffix(".json")?.parse().ok().map(HttpRoute::TrackJson),
+        ["artists", file] => file.strip_suffix(".json")?.parse().ok().map(HttpRoute::ArtistJson),
+        _ => None,
+    }
+}
+
+/// Parses a single `bytes=` range into inclusive offsets. `None` if it is malformed or
+/// cannot be satisfied for an asset of `size` bytes; multi-range requests are not supported.
+fn parse_range(value: &str, size: u64) -> Option<(u64, u64)> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (size.checked_sub(suffix.min(size))?, size.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, size.checked_sub(1)?),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(size.checked_sub(1)?)),
+    };
+    if start > end || start >= size {
+        return None;
+    }
+    Some((start, end))
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+}
+
+fn http_response(status_code: u16, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    let headers = [
+        ("Content-Type", content_type),
+        ("Access-Control-Allow-Origin", "*"),
+        ("Access-Control-Allow-Methods", "GET, HEAD, OPTIONS"),
+        ("Access-Control-Allow-Headers", "Range"),
+        ("Access-Control-Expose-Headers", "Content-Length, Content-Range, Accept-Ranges"),
+    ];
+    HttpResponse {
+        status_code,
+        headers: headers.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+        body,
+        streaming_strategy: None,
+        upgrade: None,
+    }
+}
+
+/// Status and plain-text message of a refused gateway request.
+type HttpError = (u16, &'static str);
+
+fn http_error((status_code, message): HttpError) -> HttpResponse {
+    http_response(status_code, "text/plain; charset=utf-8", message.as_bytes().to_vec())
+}
+
+fn with_header(mut response: HttpResponse, name: &str, value: String) -> HttpResponse {
+    response.headers.push((name.to_string(), value));
+    response
+}
+
+/// Anonymous viewers only get public tracks.
+fn public_track(track_id: u64) -> Result<Track, HttpError> {
+    let track = find_track(track_id)
+        .filter(|t| !is_suspended(&SuspensionTargetType::Track, &t.id.to_string()) && !hidden_by_ban(t))
+        .ok_or((404, "track not found"))?;
+    if track.visibility != TrackVisibility::Public {
+        return Err((403, "track is not public"));
+    }
+    Ok(track)
+}
+
+fn json_response(value: &impl Serialize) -> HttpResponse {
+    match serde_json::to_vec(value) {
+        Ok(body) => http_response(200, "application/json", body),
+        Err(_) => http_error((500, "failed to encode response")),
+    }
+}
+
+fn serve_track_json(track_id: u64) -> Result<HttpResponse, HttpError> {
+    let track = public_track(track_id)?;
+    let url_of = |kind: AssetKind, name: &str| latest_asset(track_id, kind).map(|_| format!("/tracks/{}/{}", track_id, name));
+    let audio_kind = gateway_audio_kind(&track).unwrap_or(AssetKind::Master);
+    Ok(json_response(&TrackJson {
+        id: track.id,
+        audio_url: url_of(audio_kind, "audio"),
+        artwork_url: url_of(AssetKind::Artwork, "artwork"),
+        title: track.title,
+        description: track.description,
+        contributors: track.contributors,
+        genre: track.genre,
+        tags: track.tags,
+        play_count: track.play_count,
+        downloadable: track.downloadable,
+    }))
+}
+
+fn serve_artist_json(artist_id: u64) -> Result<HttpResponse, HttpError> {
+    let artist = get_artist(artist_id).map_err(|_| (404, "artist not found"))?;
+    // Listed only if the track itself would be served
+    let tracks = TRACKS.with(|tracks| {
+        tracks.borrow().iter()
+            .filter(|t| t.contributors.contains(&artist_id) && public_track(t.id).is_ok())
+            .map(|t| t.id)
+            .collect()
+    });
+    Ok(json_response(&ArtistJson {
+        id: artist.id,
+        name: artist.name,
+        bio: artist.bio,
+        social: artist.social,
+        profile_image_url: artist.profile_image_url,
+        links: artist.links,
+        tracks,
+    }))
+}
+
+/// Gateway requests are anonymous, so only assets anyone may read are served: artwork, previews,
+/// and masters of free downloadable tracks. Streaming callbacks run the same check.
+fn gateway_asset_access(track: &Track, kind: &AssetKind) -> Result<(), HttpError> {
+    match kind {
+        AssetKind::Artwork | AssetKind::Preview => Ok(()),
+        AssetKind::Master if has_track_access(Principal::anonymous(), track, master_access(track)) => Ok(()),
+        AssetKind::Master if master_access(track) == TrackAccess::Download && track.pricing.mode == PricingMode::Free => {
+            Err((403, "track is not downloadable"))
+        }
+        AssetKind::Master => Err((402, "track must be purchased to stream")),
+        AssetKind::Stem => Err((404, "asset not found")),
+    }
+}
+
+/// `/tracks/{id}/audio` serves the master where anyone may read it, else the preview if there is one.
+fn gateway_audio_kind(track: &Track) -> Result<AssetKind, HttpError> {
+    match gateway_asset_access(track, &AssetKind::Master) {
+        Err(_) if latest_asset(track.id, AssetKind::Preview).is_some() => Ok(AssetKind::Preview),
+        refused => refused.map(|_| AssetKind::Master),
+    }
+}
+
+/// Serves the track's latest asset of `kind`. Range requests get a 206 of at most one chunk, so
+/// replies to requests upgraded to updates stay under the 2MB reply limit; full requests get the
+/// first chunk and stream the rest through the callback.
+fn serve_track_asset(req: &HttpRequest, track_id: u64, kind: AssetKind) -> Result<HttpResponse, HttpError> {
+    let track = public_track(track_id)?;
+    gateway_asset_access(&track, &kind)?;
+    let asset = latest_asset(track.id, kind).ok_or((404, "asset not found"))?;
+    if let Some(range) = header_value(req, "Range") {
+        let Some((start, end)) = parse_range(range, asset.size) else {
+            let response = http_error((416, "range not satisfiable"));
+            return Ok(with_header(response, "Content-Range", format!("bytes */{}", asset.size)));
+        };
+        let end = end.min(start + ASSET_CHUNK_SIZE as u64 - 1);
+        let body = read_asset_range(&asset, start, end - start + 1).map_err(|_| (416, "range not satisfiable"))?;
+        let response = http_response(206, &asset.content_type, body);
+        let response = with_header(response, "Accept-Ranges", "bytes".to_string());
+        let response = with_header(response, "Content-Range", format!("bytes {}-{}/{}", start, end, asset.size));
+        return Ok(with_header(response, "Content-Length", (end - start + 1).to_string()));
+    }
+    let body = read_chunk(asset.id, 0).unwrap_or_default();
+    let mut response = http_response(200, &asset.content_type, body);
+    if asset.chunk_count > 1 {
+        response.streaming_strategy = Some(StreamingStrategy::Callback {
+            callback: StreamingCallback::new(ic_cdk::id(), "http_request_streaming_callback".to_string()),
+            token: StreamingCallbackToken { track_id, asset_id: asset.id, chunk_index: 1 },
+        });
+    }
+    let response = with_header(response, "Accept-Ranges", "bytes".to_string());
+    Ok(with_header(response, "Content-Length", asset.size.to_string()))
+}
+
+#[ic_cdk::query]
+pub fn http_request(req: HttpRequest) -> HttpResponse {
+    serve_http(&req, ic_cdk::api::time() / 1_000_000)
+}
+
+fn serve_http(req: &HttpRequest, now: u64) -> HttpResponse {
+    let response = route_http(req);
+    if gateway_play(req, &response).is_some() && gateway_plays_left(now) {
+        return HttpResponse { upgrade: Some(true), ..http_response(200, "text/plain", vec![]) };
+    }
+    response
+}
+
+#[ic_cdk::update]
+pub fn http_request_update(req: HttpRequest) -> HttpResponse {
+    serve_counted_http(&req, ic_cdk::api::time() / 1_000_000)
+}
+
+fn serve_counted_http(req: &HttpRequest, now: u64) -> HttpResponse {
+    let response = route_http(req);
+    if let Some(track_id) = gateway_play(req, &response) {
+        count_gateway_play(track_id, now);
+    }
+    response
+}
+
+fn gateway_plays_left(now: u64) -> bool {
+    GATEWAY_PLAY_WINDOW.with(|w| {
+        let (start, plays) = *w.borrow();
+        now >= start.saturating_add(GATEWAY_PLAY_WINDOW_MS) || plays < MAX_GATEWAY_PLAYS_PER_WINDOW
+    })
+}
+
+/// Adds a gateway play to the track unless the window's budget is already spent.
+fn count_gateway_play(track_id: u64, now: u64) {
+    if !gateway_plays_left(now) {
+        return;
+    }
+    GATEWAY_PLAY_WINDOW.with(|w| {
+        let mut w = w.borrow_mut();
+        if now >= w.0.saturating_add(GATEWAY_PLAY_WINDOW_MS) {
+            *w = (now, 0);
+        }
+        w.1 += 1;
+    });
+    TRACKS.with(|tracks| {
+        if let Some(track) = tracks.borrow_mut().iter_mut().find(|t| t.id == track_id) {
+            track.gateway_play_count += 1;
+        }
+    });
+}
+
+/// The track whose play `req` starts, if it was served successfully.
+fn gateway_play(req: &HttpRequest, response: &HttpResponse) -> Option<u64> {
+    let Some(HttpRoute::TrackAudio(track_id)) = parse_route(&req.url) else { return None };
+    let from_start = header_value(req, "Range").is_none_or(|range| range.trim().trim_start_matches("bytes=").starts_with("0-"));
+    (req.method.eq_ignore_ascii_case("GET") && from_start && (200..300).contains(&response.status_code)).then_some(track_id)
+}
+
+fn route_http(req: &HttpRequest) -> HttpResponse {
+    match req.method.to_ascii_uppercase().as_str() {
+        "OPTIONS" => return http_response(204, "text/plain", vec![]),
+        "GET" | "HEAD" => {}
+        _ => return http_error((405, "method not allowed")),
+    }
+    let result = match parse_route(&req.url) {
+        Some(HttpRoute::TrackAudio(id)) => public_track(id).and_then(|t| gateway_audio_kind(&t)).and_then(|kind| serve_track_asset(req, id, kind)),
+        Some(HttpRoute::TrackArtwork(id)) => serve_track_asset(req, id, AssetKind::Artwork),
+        Some(HttpRoute::TrackJson(id)) => serve_track_json(id),
+        Some(HttpRoute::ArtistJson(id)) => serve_artist_json(id),
+        None => Err((404, "not found")),
+    };
+    let mut response = result.unwrap_or_else(http_error);
+    if req.method.eq_ignore_ascii_case("HEAD") {
+        response.body.clear();
+        response.streaming_strategy = None;
+    }
+    response
+}
+
+#[ic_cdk::query]
+pub fn http_request_streaming_callback(token: StreamingCallbackToken) -> StreamingCallbackHttpResponse {
+    streaming_chunk(token).unwrap_or_else(|(_, message)| ic_cdk::trap(message))
+}
+
+/// Tokens come from the caller and may be forged, so each one is checked like a fresh request.
+/// The track may also have been made private or the asset replaced mid-stream.
+fn streaming_chunk(token: StreamingCallbackToken) -> Result<StreamingCallbackHttpResponse, HttpError> {
+    let track = public_track(token.track_id)?;
+    let asset = TRACK_ASSETS.with(|assets| assets.borrow().iter().find(|a| a.id == token.asset_id && a.track_id == token.track_id).cloned())
+        .ok_or((404, "asset is no longer available"))?;
+    gateway_asset_access(&track, &asset.kind)?;
+    let body = read_chunk(asset.id, token.chunk_index).ok_or((416, "chunk out of range"))?;
+    let next = token.chunk_index + 1;
+    Ok(StreamingCallbackHttpResponse {
+        body,
+        token: (next < asset.chunk_count).then_some(StreamingCallbackToken { chunk_index: next, ..token }),
+    })
+}
+
+fn require_non_empty(field: &str, value: &str) -> Result<(), ApiError> {
+    if value.trim().is_empty() {
+        Err(ApiError::invalid_input(field, "must not be empty"))
+    } else {
+        Ok(())
+    }
+}
+
+// --- Identity Resolution ---
+// Track-level identities (contributors, roles, invites, ratings, commenters) are artist ids.
+// Callers are resolved to the artists they own through ARTISTS_BY_PRINCIPAL.
+fn find_user(principal: Principal) -> Option<User> {
+    USERS.with(|users| users.borrow().get(&principal).cloned())
+}
+
+/// Artist ids owned by `principal`.
+fn artist_ids_of(principal: Principal) -> Vec<u64> {
+    ARTISTS_BY_PRINCIPAL.with(|index| index.borrow().get(&principal).cloned().unwrap_or_default())
+}
+
+/// Owner of artists created before artists had owners (see `migrate_v1_to_v2`). They stay
+/// unclaimed, owned by nobody, until an admin assigns their owner with `assign_artist_owner`.
+const UNCLAIMED_ARTIST_OWNER: Principal = Principal::anonymous();
+
+fn owns_artist(principal: Principal, artist_id: u64) -> bool {
+    principal != Principal::anonymous() && ARTISTS_BY_PRINCIPAL.with(|index| index.borrow().get(&principal).map(|ids| ids.contains(&artist_id)).unwrap_or(false))
+}
+
+/// Ownership check for acting as an artist: suspended callers and suspended artists are refused.
+fn require_artist_owner(principal: Principal, artist_id: u64) -> Result<(), ApiError> {
+    if !owns_artist(principal, artist_id) {
+        return Err(ApiError::unauthorized("caller does not own this artist"));
+    }
+    require_active_user(principal)?;
+    require_not_suspended(SuspensionTargetType::Artist, &artist_id.to_string())
+}
+
+fn index_artist(principal: Principal, artist_id: u64) {
+    ARTISTS_BY_PRINCIPAL.with(|index| {
+        let mut index = index.borrow_mut();
+        let ids = index.entry(principal).or_default();
+        if !ids.contains(&artist_id) {
+            ids.push(artist_id);
+        }
+    });
+}
+
+fn unindex_artist(principal: Principal, artist_id: u64) {
+    ARTISTS_BY_PRINCIPAL.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(ids) = index.get_mut(&principal) {
+            ids.retain(|id| *id != artist_id);
+            if ids.is_empty() {
+                index.remove(&principal);
+            }
+        }
+    });
+}
+
+/// Rebuilds ARTISTS_BY_PRINCIPAL from ARTISTS. Called after state is restored.
+fn rebuild_identity_index() {
+    let index = ARTISTS.with(|artists| {
+        let mut index: BTreeMap<Principal, Vec<u64>> = BTreeMap::new();
+        for artist in artists.borrow().iter().filter(|a| a.user_principal != UNCLAIMED_ARTIST_OWNER) {
+            index.entry(artist.user_principal).or_default().push(artist.id);
+        }
+        index
+    });
+    ARTISTS_BY_PRINCIPAL.with(|i| *i.borrow_mut() = index);
+}
+
+impl Storable for TrackFile {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        candid::decode_one(&bytes).unwrap()
+    }
+}
+
+impl BoundedStorable for TrackFile {
+    const MAX_SIZE: u32 = 10 * 1024 * 1024 + 1024; // 10MB + metadata buffer
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// --- Upgrade Persistence ---
+// Heap stores are snapshotted into the upgrades memory region in `pre_upgrade` and
+// restored in `post_upgrade`. Asset chunks live in their own stable regions and need no copying.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct StableState {
+    artists: Vec<Artist>,
+    tracks: Vec<Track>,
+    artist_id: u64,
+    track_id: u64,
+    track_versions: Vec<(u64, Vec<TrackVersion>)>,
+    activity_log: Vec<Activity>,
+    collab_requests: Vec<CollabRequest>,
+    collab_request_id: u64,
+    tasks: Vec<Task>,
+    task_id: u64,
+    users: Vec<User>,
+    user_activity_log: Vec<UserActivity>,
+    notifications: Vec<Notification>,
+    notification_id: u64,
+    followed_artists: Vec<(Principal, Vec<Principal>)>,
+    followed_tracks: Vec<(Principal, Vec<u64>)>,
+    playlists: Vec<Playlist>,
+    playlist_id: u64,
+    play_counts: Vec<PlayDownloadCount>,
+    messages: Vec<Message>,
+    message_id: u64,
+    reports: Vec<Report>,
+    report_id: u64,
+    track_licenses: Vec<TrackLicense>,
+    rate_limits: Vec<RateLimitEntry>,
+    rate_limit_policies: Vec<RateLimitPolicy>,
+    audit_log: Vec<AuditLogEntry>,
+    audit_log_id: u64,
+    moderation_queue: Vec<ModerationQueueItem>,
+    moderation_queue_id: u64,
+    suspensions: Vec<Suspension>,
+    suspension_id: u64,
+    suspension_appeals: Vec<SuspensionAppeal>,
+    suspension_appeal_id: u64,
+    bans: Vec<Ban>,
+    role_permissions: Vec<RolePermissions>,
+    moderator_suspension_cap_secs: u64,
+    banned_keywords: Vec<String>,
+    workflow_steps: Vec<WorkflowStep>,
+    collaboration_sessions: Vec<CollaborationSession>,
+    workflow_templates: Vec<WorkflowTemplate>,
+    workflow_step_id: u64,
+    session_id: u64,
+    template_id: u64,
+    migration_history: Vec<MigrationRecord>,
+    track_assets: Vec<TrackAsset>,
+    asset_id: u64,
+    upload_sessions: Vec<UploadSession>,
+    upload_session_id: u64,
+    ledger_canister: Option<Principal>,
+    journal_entries: Vec<JournalEntry>,
+    journal_entry_id: u64,
+    journal_transaction_id: u64,
+    split_proposals: Vec<SplitProposal>,
+    split_proposal_id: u64,
+    split_sheets: Vec<SplitSheet>,
+    fee_schedules: Vec<FeeSchedule>,
+    advances: Vec<Advance>,
+    advance_id: u64,
+    monthly_statements: Vec<MonthlyStatement>,
+    monthly_statement_id: u64,
+    statements_generated_through: u64,
+    entitlements: Vec<Entitlement>,
+    membership_tiers: Vec<MembershipTier>,
+    membership_tier_id: u64,
+    subscriptions: Vec<Subscription>,
+    subscription_id: u64,
+    campaigns: Vec<Campaign>,
+    campaign_id: u64,
+}
+
+fn take<T: Default>(store: &'static std::thread::LocalKey<RefCell<T>>) -> T {
+    store.with(|s| std::mem::take(&mut *s.borrow_mut()))
+}
+
+fn put<T>(store: &'static std::thread::LocalKey<RefCell<T>>, value: T) {
+    store.with(|s| *s.borrow_mut() = value);
+}
+
+/// Moves every heap store out into a `StableState`, leaving the stores empty.
+fn take_state() -> StableState {
+    StableState {
+        artists: take(&ARTISTS),
+        tracks: take(&TRACKS),
+        artist_id: take(&ARTIST_ID),
+        track_id: take(&TRACK_ID),
+        track_versions: take(&TRACK_VERSIONS),
+        activity_log: take(&ACTIVITY_LOG),
+        collab_requests: take(&COLLAB_REQUESTS),
+        collab_request_id: take(&COLLAB_REQUEST_ID),
+        tasks: take(&TASKS),
+        task_id: take(&TASK_ID),
+        users: take(&USERS).into_values().collect(),
+        user_activity_log: take(&USER_ACTIVITY_LOG),
+        notifications: take(&NOTIFICATIONS),
+        notification_id: take(&NOTIFICATION_ID),
+        followed_artists: take(&FOLLOWED_ARTISTS),
+        followed_tracks: take(&FOLLOWED_TRACKS),
+        playlists: take(&PLAYLISTS),
+        playlist_id: take(&PLAYLIST_ID),
+        play_counts: take(&PLAY_COUNTS),
+        messages: take(&MESSAGES),
+        message_id: take(&MESSAGE_ID),
+        reports: take(&REPORTS),
+        report_id: take(&REPORT_ID),
+        track_licenses: take(&TRACK_LICENSES),
+        rate_limits: take(&RATE_LIMITS),
+        rate_limit_policies: take(&RATE_LIMIT_POLICIES),
+        audit_log: take(&AUDIT_LOG),
+        audit_log_id: take(&AUDIT_LOG_ID),
+        moderation_queue: take(&MODERATION_QUEUE),
+        moderation_queue_id: take(&MODERATION_QUEUE_ID),
+        suspensions: take(&SUSPENSIONS),
+        suspension_id: take(&SUSPENSION_ID),
+        suspension_appeals: take(&SUSPENSION_APPEALS),
+        suspension_appeal_id: take(&SUSPENSION_APPEAL_ID),
+        bans: take(&BANS),
+        role_permissions: take(&ROLE_PERMISSIONS),
+        moderator_suspension_cap_secs: take(&MODERATOR_SUSPENSION_CAP_SECS),
+        banned_keywords: take(&BANNED_KEYWORDS),
+        workflow_steps: take(&WORKFLOW_STEPS),
+        collaboration_sessions: take(&COLLABORATION_SESSIONS),
+        workflow_templates: take(&WORKFLOW_TEMPLATES),
+        workflow_step_id: take(&WORKFLOW_STEP_ID),
+        session_id: take(&SESSION_ID),
+        template_id: take(&TEMPLATE_ID),
+        migration_history: take(&MIGRATION_HISTORY),
+        track_assets: take(&TRACK_ASSETS),
+        asset_id: take(&ASSET_ID),
+        upload_sessions: take(&UPLOAD_SESSIONS),
+        upload_session_id: take(&UPLOAD_SESSION_ID),
+        ledger_canister: take(&LEDGER_CANISTER),
+        journal_entries: take(&JOURNAL_ENTRIES),
+        journal_entry_id: take(&JOURNAL_ENTRY_ID),
+        journal_transaction_id: take(&JOURNAL_TRANSACTION_ID),
+        split_proposals: take(&SPLIT_PROPOSALS),
+        split_proposal_id: take(&SPLIT_PROPOSAL_ID),
+        split_sheets: take(&SPLIT_SHEETS),
+        fee_schedules: take(&FEE_SCHEDULES),
+        advances: take(&ADVANCES),
+        advance_id: take(&ADVANCE_ID),
+        monthly_statements: take(&MONTHLY_STATEMENTS),
+        monthly_statement_id: take(&MONTHLY_STATEMENT_ID),
+        statements_generated_through: take(&STATEMENTS_GENERATED_THROUGH),
+        entitlements: take(&ENTITLEMENTS),
+        membership_tiers: take(&MEMBERSHIP_TIERS),
+        membership_tier_id: take(&MEMBERSHIP_TIER_ID),
+        subscriptions: take(&SUBSCRIPTIONS),
+        subscription_id: take(&SUBSCRIPTION_ID),
+        campaigns: take(&CAMPAIGNS),
+        campaign_id: take(&CAMPAIGN_ID),
+    }
+}
+
+/// Replaces every heap store with the contents of `state`.
+fn restore_state(state: StableState) {
+    put(&ARTISTS, state.artists);
+    put(&TRACKS, state.tracks);
+    put(&ARTIST_ID, state.artist_id);
+    put(&TRACK_ID, state.track_id);
+    put(&TRACK_VERSIONS, state.track_versions);
+    put(&ACTIVITY_LOG, state.activity_log);
+    put(&COLLAB_REQUESTS, state.collab_requests);
+    put(&COLLAB_REQUEST_ID, state.collab_request_id);
+    put(&TASKS, state.tasks);
+    put(&TASK_ID, state.task_id);
+    put(&USERS, state.users.into_iter().map(|u| (u.principal, u)).collect());
+    put(&USER_ACTIVITY_LOG, state.user_activity_log);
+    put(&NOTIFICATIONS, state.notifications);
+    put(&NOTIFICATION_ID, state.notification_id);
+    put(&FOLLOWED_ARTISTS, state.followed_artists);
+    put(&FOLLOWED_TRACKS, state.followed_tracks);
+    put(&PLAYLISTS, state.playlists);
+    put(&PLAYLIST_ID, state.playlist_id);
+    put(&PLAY_COUNTS, state.play_counts);
+    put(&MESSAGES, state.messages);
+    put(&MESSAGE_ID, state.message_id);
+    put(&REPORTS, state.reports);
+    put(&REPORT_ID, state.report_id);
+    put(&TRACK_LICENSES, state.track_licenses);
+    put(&RATE_LIMITS, state.rate_limits);
+    put(&RATE_LIMIT_POLICIES, state.rate_limit_policies);
+    put(&AUDIT_LOG, state.audit_log);
+    put(&AUDIT_LOG_ID, state.audit_log_id);
+    put(&MODERATION_QUEUE, state.moderation_queue);
+    put(&MODERATION_QUEUE_ID, state.moderation_queue_id);
+    put(&SUSPENSIONS, state.suspensions);
+    put(&SUSPENSION_ID, state.suspension_id);
+    put(&SUSPENSION_APPEALS, state.suspension_appeals);
+    put(&SUSPENSION_APPEAL_ID, state.suspension_appeal_id);
+    put(&BANS, state.bans);
+    put(&ROLE_PERMISSIONS, state.role_permissions);
+    put(&MODERATOR_SUSPENSION_CAP_SECS, state.moderator_suspension_cap_secs);
+    put(&BANNED_KEYWORDS, state.banned_keywords);
+    put(&WORKFLOW_STEPS, state.workflow_steps);
+    put(&COLLABORATION_SESSIONS, state.collaboration_sessions);
+    put(&WORKFLOW_TEMPLATES, state.workflow_templates);
+    put(&WORKFLOW_STEP_ID, state.workflow_step_id);
+    put(&SESSION_ID, state.session_id);
+    put(&TEMPLATE_ID, state.template_id);
+    put(&MIGRATION_HISTORY, state.migration_history);
+    put(&TRACK_ASSETS, state.track_assets);
+    put(&ASSET_ID, state.asset_id);
+    put(&UPLOAD_SESSIONS, state.upload_sessions);
+    put(&UPLOAD_SESSION_ID, state.upload_session_id);
+    put(&LEDGER_CANISTER, state.ledger_canister);
+    put(&JOURNAL_ENTRIES, state.journal_entries);
+    put(&JOURNAL_ENTRY_ID, state.journal_entry_id);
+    put(&JOURNAL_TRANSACTION_ID, state.journal_transaction_id);
+    put(&SPLIT_PROPOSALS, state.split_proposals);
+    put(&SPLIT_PROPOSAL_ID, state.split_proposal_id);
+    put(&SPLIT_SHEETS, state.split_sheets);
+    put(&FEE_SCHEDULES, state.fee_schedules);
+    put(&ADVANCES, state.advances);
+    put(&ADVANCE_ID, state.advance_id);
+    put(&MONTHLY_STATEMENTS, state.monthly_statements);
+    put(&MONTHLY_STATEMENT_ID, state.monthly_statement_id);
+    put(&STATEMENTS_GENERATED_THROUGH, state.statements_generated_through);
+    put(&ENTITLEMENTS, state.entitlements);
+    put(&MEMBERSHIP_TIERS, state.membership_tiers);
+    put(&MEMBERSHIP_TIER_ID, state.membership_tier_id);
+    put(&SUBSCRIPTIONS, state.subscriptions);
+    put(&SUBSCRIPTION_ID, state.subscription_id);
+    put(&CAMPAIGNS, state.campaigns);
+    put(&CAMPAIGN_ID, state.campaign_id);
+    rebuild_identity_index();
+}
+
+/// Writes `state` at the start of `memory` as a header (magic, schema version, length)
+/// followed by the Candid-encoded payload.
+fn write_state(memory: &mut Memory, state: &StableState) {
+    let bytes = candid::encode_one(state).expect("failed to encode stable state");
+    let mut writer = Writer::new(memory, 0);
+    writer.write(STATE_MAGIC).expect("failed to write stable state");
+    writer.write(&SCHEMA_VERSION.to_le_bytes()).expect("failed to write stable state");
+    writer.write(&(bytes.len() as u64).to_le_bytes()).expect("failed to write stable state");
+    writer.write(&bytes).expect("failed to write stable state");
+}
+
+/// Reads the state saved by `write_state`, migrating it to `SCHEMA_VERSION` if it was written
+/// by an older build. Returns `None` if the region is empty.
+fn read_state(memory: &Memory, now: u64) -> Option<StableState> {
+    if memory.size() == 0 {
+        return None;
+    }
+    let mut reader = Reader::new(memory, 0);
+    let mut magic = [0u8; 4];
+    reader.read(&mut magic).expect("failed to read stable state");
+    if &magic != STATE_MAGIC {
+        ic_cdk::trap("the upgrades region holds no saved state");
+    }
+    let mut version_bytes = [0u8; 4];
+    reader.read(&mut version_bytes).expect("failed to read stable state");
+    let version = u32::from_le_bytes(version_bytes);
+    let mut len_bytes = [0u8; 8];
+    reader.read(&mut len_bytes).expect("failed to read stable state");
+    let len = u64::from_le_bytes(len_bytes) as usize;
+    let mut bytes = vec![0u8; len];
+    reader.read(&mut bytes).expect("failed to read stable state");
+    Some(decode_state(version, &bytes, now).unwrap_or_else(|e| ic_cdk::trap(&e)))
+}
+
+fn upgrades_memory() -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(UPGRADES_MEMORY_ID))
+}
+
+fn restore_from_stable_memory(now: u64, legacy_files: Vec<TrackFile>) {
+    if let Some(state) = read_state(&upgrades_memory(), now) {
+        restore_state(state);
+    }
+    migrate_legacy_track_files(legacy_files);
+    seed_split_sheets();
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let state = take_state();
+    write_state(&mut upgrades_memory(), &state);
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct InitArgs {
+    /// Principals made admins at install, normally the canister's controllers
+    pub admins: Vec<Principal>,
+}
+
+#[ic_cdk::init]
+fn init(args: Option<InitArgs>) {
+    let installer = caller();
+    let now = ic_cdk::api::time() / 1_000_000;
+    for admin in args.map(|args| args.admins).unwrap_or_default() {
+        backend_initialize_admin(admin, installer, now);
+    }
+    start_timers();
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    let legacy_files = take_legacy_track_files(&DefaultMemoryImpl::default());
+    restore_from_stable_memory(ic_cdk::api::time() / 1_000_000, legacy_files);
+    start_timers();
+}
+
+/// Timers don't survive upgrades, so every recurring job is registered here from both
+/// `init` and `post_upgrade`.
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(STATEMENT_CHECK_INTERVAL, || {
+        generate_due_statements(ic_cdk::api::time() / 1_000_000);
+    });
+    ic_cdk_timers::set_timer_interval(SUBSCRIPTION_RENEWAL_INTERVAL, || ic_cdk::spawn(renew_due_subscriptions()));
+    ic_cdk_timers::set_timer_interval(CAMPAIGN_CHECK_INTERVAL, || ic_cdk::spawn(process_campaigns()));
+    ic_cdk_timers::set_timer_interval(RATE_LIMIT_GC_INTERVAL, || {
+        gc_rate_limits(ic_cdk::api::time() / 1_000_000);
+    });
+    ic_cdk_timers::set_timer_interval(SUSPENSION_EXPIRY_INTERVAL, || {
+        expire_suspensions(ic_cdk::api::id(), ic_cdk::api::time() / 1_000_000);
+    });
+}
+
+// --- Schema Versioning & Migrations ---
+/// Schema version of `StableState` written by this build. Bump it and register a migration
+/// in `MIGRATIONS` whenever a persisted type changes shape.
+const SCHEMA_VERSION: u32 = 16;
+const STATE_MAGIC: &[u8; 4] = b"TSPH";
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct MigrationRecord {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub description: String,
+    pub applied_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SchemaInfo {
+    pub current_version: u32,
+    pub migration_history: Vec<MigrationRecord>,
+}
+
+/// Upgrades a state encoded at `from_version` to `from_version + 1`. Migrations work on the
+/// untyped Candid value so old encodings can be reshaped before decoding into current types.
+struct Migration {
+    from_version: u32,
+    description: &'static str,
+    apply: fn(&mut IDLValue) -> Result<(), String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from_version: 1,
+        description: "Backfill Track, Artist and User fields added after launch; start migration history",
+        apply: migrate_v1_to_v2,
+    },
+    Migration {
+        from_version: 2,
+        description: "Add track asset and upload session stores",
+        apply: migrate_v2_to_v3,
+    },
+    Migration {
+        from_version: 3,
+        description: "Add ledger canister setting and ledger references on payments",
+        apply: migrate_v3_to_v4,
+    },
+    Migration {
+        from_version: 4,
+        description: "Move artist royalty balances into the royalty journal as opening balances",
+        apply: migrate_v4_to_v5,
+    },
+    Migration {
+        from_version: 5,
+        description: "Add split proposals and split sheets; record the sheet used by each payment",
+        apply: migrate_v5_to_v6,
+    },
+    Migration {
+        from_version: 6,
+        description: "Add fee schedules and recoupable advances",
+        apply: migrate_v6_to_v7,
+    },
+    Migration {
+        from_version: 7,
+        description: "Add monthly royalty statements",
+        apply: migrate_v7_to_v8,
+    },
+    Migration {
+        from_version: 8,
+        description: "Add track pricing and purchase entitlements",
+        apply: migrate_v8_to_v9,
+    },
+    Migration {
+        from_version: 9,
+        description: "Add membership tiers, subscriptions and membership earnings on statements",
+        apply: migrate_v9_to_v10,
+    },
+    Migration {
+        from_version: 10,
+        description: "Add crowdfunding campaigns",
+        apply: migrate_v10_to_v11,
+    },
+    Migration {
+        from_version: 11,
+        description: "Key rate-limit counters by endpoint and add rate-limit policies",
+        apply: migrate_v11_to_v12,
+    },
+    Migration {
+        from_version: 12,
+        description: "Keep raw and validated play counts separately",
+        apply: migrate_v12_to_v13,
+    },
+    Migration {
+        from_version: 13,
+        description: "Add the ban store",
+        apply: migrate_v13_to_v14,
+    },
+    Migration {
+        from_version: 14,
+        description: "Add the role permission matrix and moderator suspension cap",
+        apply: migrate_v14_to_v15,
+    },
+    Migration {
+        from_version: 15,
+        description: "Count anonymous gateway plays apart from listener plays",
+        apply: migrate_v15_to_v16,
+    },
+];
+
+fn migrate_v1_to_v2(state: &mut IDLValue) -> Result<(), String> {
+    for_each_record(state, "tracks", |track| {
+        set_default_field(track, "genre", IDLValue::None);
+        set_default_field(track, "play_count", IDLValue::Nat64(0));
+        set_default_field(track, "downloadable", IDLValue::Bool(true));
+    })?;
+    for_each_record(state, "artists", |artist| {
+        set_default_field(artist, "profile_image_url", IDLValue::None);
+        set_default_field(artist, "links", IDLValue::None);
+        set_default_field(artist, "user_principal", IDLValue::Principal(UNCLAIMED_ARTIST_OWNER));
+    })?;
+    for_each_record(state, "users", |user| {
+        set_default_field(user, "role", enum_value("User"));
+    })?;
+    set_default_field(record_fields(state)?, "migration_history", IDLValue::Vec(vec![]));
+    Ok(())
+}
+
+fn migrate_v2_to_v3(state: &mut IDLValue) -> Result<(), String> {
+    let fields = record_fields(state)?;
+    set_default_field(fields, "track_assets", IDLValue::Vec(vec![]));
+    set_default_field(fields, "asset_id", IDLValue::Nat64(1));
+    set_default_field(fields, "upload_sessions", IDLValue::Vec(vec![]));
+    set_default_field(fields, "upload_session_id", IDLValue::Nat64(1));
+    Ok(())
+}
+
+fn migrate_v3_to_v4(state: &mut IDLValue) -> Result<(), String> {
+    for_each_record(state, "tracks", |track| {
+        for_each_nested_record(track, "payments", |payment| {
+            set_default_field(payment, "from", IDLValue::None);
+            set_default_field(payment, "block_index", IDLValue::None);
+        });
+    })?;
+    set_default_field(record_fields(state)?, "ledger_canister", IDLValue::None);
+    Ok(())
+}
+
+fn migrate_v4_to_v5(state: &mut IDLValue) -> Result<(), String> {
+    let balance_id = Label::Named("royalty_balance".to_string());
+    let id_id = Label::Named("id".to_string());
+    let mut openings = vec![];
+    for_each_record(state, "artists", |artist| {
+        let id = artist.iter().find_map(|f| match (&f.id, &f.val) {
+            (label, IDLValue::Nat64(id)) if *label == id_id => Some(*id),
+            _ => None,
+        });
+        let balance = artist.iter().find_map(|f| match (&f.id, &f.val) {
+            (label, IDLValue::Nat64(balance)) if *label == balance_id => Some(*balance),
+            _ => None,
+        });
+        artist.retain(|f| f.id != balance_id);
+        if let (Some(id), Some(balance)) = (id, balance.filter(|b| *b > 0)) {
+            openings.push((id, balance));
+        }
+    })?;
+    // Opening balances predate the journal, so they are dated 0 and share one transaction
+    let mut entries = vec![];
+    for (artist_id, balance) in &openings {
+        for (account, debit, credit) in [(RoyaltyAccount::Treasury, *balance, 0), (RoyaltyAccount::Artist(*artist_id), 0, *balance)] {
+            entries.push(JournalEntry {
+                id: entries.len() as u64 + 1,
+                transaction_id: 1,
+                timestamp: 0,
+                kind: JournalEntryKind::OpeningBalance,
+                account,
+                debit,
+                credit,
+                track_id: None,
+                block_index: None,
+            });
+        }
+    }
+    let next_entry_id = entries.len() as u64 + 1;
+    let next_transaction_id = if openings.is_empty() { 1 } else { 2 };
+    let entries = IDLValue::try_from_candid_type(&entries).map_err(|e| format!("failed to encode opening balances: {}", e))?;
+    let fields = record_fields(state)?;
+    set_default_field(fields, "journal_entries", entries);
+    set_default_field(fields, "journal_entry_id", IDLValue::Nat64(next_entry_id));
+    set_default_field(fields, "journal_transaction_id", IDLValue::Nat64(next_transaction_id));
+    Ok(())
+}
+
+// Existing split sheets are seeded from `Track.splits` after restore, see `seed_split_sheets`.
+fn migrate_v5_to_v6(state: &mut IDLValue) -> Result<(), String> {
+    for_each_record(state, "tracks", |track| {
+        for_each_nested_record(track, "payments", |payment| set_default_field(payment, "split_sheet", IDLValue::None));
+    })?;
+    let fields = record_fields(state)?;
+    set_default_field(fields, "split_proposals", IDLValue::Vec(vec![]));
+    set_default_field(fields, "split_proposal_id", IDLValue::Nat64(1));
+    set_default_field(fields, "split_sheets", IDLValue::Vec(vec![]));
+    Ok(())
+}
+
+fn migrate_v6_to_v7(state: &mut IDLValue) -> Result<(), String> {
+    let fields = record_fields(state)?;
+    set_default_field(fields, "fee_schedules", IDLValue::Vec(vec![]));
+    set_default_field(fields, "advances", IDLValue::Vec(vec![]));
+    set_default_field(fields, "advance_id", IDLValue::Nat64(1));
+    Ok(())
+}
+
+fn migrate_v7_to_v8(state: &mut IDLValue) -> Result<(), String> {
+    let fields = record_fields(state)?;
+    set_default_field(fields, "monthly_statements", IDLValue::Vec(vec![]));
+    set_default_field(fields, "monthly_statement_id", IDLValue::Nat64(1));
+    set_default_field(fields, "statements_generated_through", IDLValue::Nat64(0));
+    Ok(())
+}
+
+fn migrate_v8_to_v9(state: &mut IDLValue) -> Result<(), String> {
+    let free = IDLValue::try_from_candid_type(&TrackPricing::default()).map_err(|e| format!("failed to encode default pricing: {}", e))?;
+    for_each_record(state, "tracks", |track| set_default_field(track, "pricing", free.clone()))?;
+    set_default_field(record_fields(state)?, "entitlements", IDLValue::Vec(vec![]));
+    Ok(())
+}
+
+fn migrate_v9_to_v10(state: &mut IDLValue) -> Result<(), String> {
+    let none = IDLValue::try_from_candid_type(&MembershipEarnings::default()).map_err(|e| format!("failed to encode earnings: {}", e))?;
+    for_each_record(state, "monthly_statements", |statement| set_default_field(statement, "memberships", none.clone()))?;
+    let fields = record_fields(state)?;
+    set_default_field(fields, "membership_tiers", IDLValue::Vec(vec![]));
+    set_default_field(fields, "membership_tier_id", IDLValue::Nat64(1));
+    set_default_field(fields, "subscriptions", IDLValue::Vec(vec![]));
+    set_default_field(fields, "subscription_id", IDLValue::Nat64(1));
+    Ok(())
+}
+
+fn migrate_v10_to_v11(state: &mut IDLValue) -> Result<(), String> {
+    let fields = record_fields(state)?;
+    set_default_field(fields, "campaigns", IDLValue::Vec(vec![]));
+    set_default_field(fields, "campaign_id", IDLValue::Nat64(1));
+    Ok(())
+}
+
+fn migrate_v11_to_v12(state: &mut IDLValue) -> Result<(), String> {
+    // Counters were never enforced and carry no endpoint; they are dropped rather than guessed
+    let policies = IDLValue::try_from_candid_type(&default_rate_limit_policies()).map_err(|e| format!("failed to encode policies: {}", e))?;
+    let fields = record_fields(state)?;
+    fields.retain(|f| f.id != Label::Named("rate_limits".to_string()));
+    set_default_field(fields, "rate_limits", IDLValue::Vec(vec![]));
+    set_default_field(fields, "rate_limit_policies", policies);
+    Ok(())
+}
+
+fn migrate_v12_to_v13(state: &mut IDLValue) -> Result<(), String> {
+    // Plays counted so far were never validated, so they become raw counts; the validated
+    // counts, on tracks and per listener, start from zero
+    for_each_record(state, "tracks", |track| {
+        let plays = track.iter().find(|f| f.id == Label::Named("play_count".to_string())).map(|f| f.val.clone());
+        set_default_field(track, "raw_play_count", plays.unwrap_or(IDLValue::Nat64(0)));
+        set_field(track, "play_count", IDLValue::Nat64(0));
+    })?;
+    for_each_record(state, "play_counts", |entry| {
+        set_default_field(entry, "validated_play_count", IDLValue::Nat64(0));
+        set_default_field(entry, "last_validated_at", IDLValue::None);
+        set_default_field(entry, "burst_window_start", IDLValue::Nat64(0));
+        set_default_field(entry, "burst_plays", IDLValue::Nat32(0));
+    })?;
+    Ok(())
+}
+
+fn migrate_v13_to_v14(state: &mut IDLValue) -> Result<(), String> {
+    // Earlier bans only reset the role to User and left nothing to carry over
+    set_default_field(record_fields(state)?, "bans", IDLValue::Vec(vec![]));
+    Ok(())
+}
+
+fn migrate_v14_to_v15(state: &mut IDLValue) -> Result<(), String> {
+    let permissions = IDLValue::try_from_candid_type(&default_role_permissions()).map_err(|e| format!("failed to encode permissions: {}", e))?;
+    let fields = record_fields(state)?;
+    set_default_field(fields, "role_permissions", permissions);
+    set_default_field(fields, "moderator_suspension_cap_secs", IDLValue::Nat64(DEFAULT_MODERATOR_SUSPENSION_CAP_SECS));
+    Ok(())
+}
+
+fn migrate_v15_to_v16(state: &mut IDLValue) -> Result<(), String> {
+    // Gateway plays counted before this version stay in the raw counts
+    for_each_record(state, "tracks", |track| set_default_field(track, "gateway_play_count", IDLValue::Nat64(0)))?;
+    Ok(())
+}
+
+fn record_fields(value: &mut IDLValue) -> Result<&mut Vec<IDLField>, String> {
+    match value {
+        IDLValue::Record(fields) => Ok(fields),
+        other => Err(format!("expected a record, found {}", other)),
+    }
+}
+
+/// Adds `name` to a record with the given value unless the field is already present.
+fn set_default_field(fields: &mut Vec<IDLField>, name: &str, default: IDLValue) {
+    let id = Label::Named(name.to_string());
+    if !fields.iter().any(|f| f.id == id) {
+        fields.push(IDLField { id, val: default });
+    }
+}
+
+/// Sets `name` on a record, replacing any value it already has.
+fn set_field(fields: &mut Vec<IDLField>, name: &str, val: IDLValue) {
+    let id = Label::Named(name.to_string());
+    match fields.iter_mut().find(|f| f.id == id) {
+        Some(field) => field.val = val,
+        None => fields.push(IDLField { id, val }),
+    }
+}
+
+/// Applies `f` to every record in the `store` vector of the top-level state record.
+fn for_each_record(state: &mut IDLValue, store: &str, mut f: impl FnMut(&mut Vec<IDLField>)) -> Result<(), String> {
+    let id = Label::Named(store.to_string());
+    let field = record_fields(state)?
+        .iter_mut()
+        .find(|field| field.id == id)
+        .ok_or_else(|| format!("stable state has no `{}` store", store))?;
+    if let IDLValue::Vec(items) = &mut field.val {
+        for item in items.iter_mut() {
+            f(record_fields(item)?);
+        }
+    }
+    Ok(())
+}
+
+/// Applies `f` to every record in the `field` vector of `record`.
+fn for_each_nested_record(record: &mut [IDLField], field: &str, mut f: impl FnMut(&mut Vec<IDLField>)) {
+    let id = Label::Named(field.to_string());
+    if let Some(IDLField { val: IDLValue::Vec(items), .. }) = record.iter_mut().find(|f| f.id == id) {
+        for item in items.iter_mut() {
+            if let IDLValue::Record(fields) = item {
+                f(fields);
+            }
+        }
+    }
+}
+
+fn enum_value(tag: &str) -> IDLValue {
+    IDLValue::Variant(VariantValue(Box::new(IDLField { id: Label::Named(tag.to_string()), val: IDLValue::Null }), 0))
+}
+
+/// Decodes a state payload written at `version`, running every migration needed to reach
+/// `SCHEMA_VERSION` and recording each one in the migration history.
+fn decode_state(version: u32, bytes: &[u8], now: u64) -> Result<StableState, String> {
+    if version == SCHEMA_VERSION {
+        return candid::decode_one(bytes).map_err(|e| format!("failed to decode stable state: {}", e));
+    }
+    if version > SCHEMA_VERSION {
+        return Err(format!("stable state has schema version {} but this build only supports up to {}", version, SCHEMA_VERSION));
+    }
+    let mut value = IDLArgs::from_bytes(bytes)
+        .map_err(|e| format!("failed to decode stable state v{}: {}", version, e))?
+        .args
+        .pop()
+        .ok_or("stable state is empty")?;
+    let mut applied = Vec::new();
+    for from_version in version..SCHEMA_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|m| m.from_version == from_version)
+            .ok_or_else(|| format!("no migration registered from schema version {}", from_version))?;
+        (migration.apply)(&mut value).map_err(|e| format!("migration from v{} failed: {}", from_version, e))?;
+        applied.push(MigrationRecord {
+            from_version,
+            to_version: from_version + 1,
+            description: migration.description.to_string(),
+            applied_at: now,
+        });
+    }
+    let bytes = IDLArgs::new(&[value])
+        .to_bytes_with_types(&TypeEnv::new(), &[StableState::ty()])
+        .map_err(|e| format!("failed to re-encode migrated state: {}", e))?;
+    let mut state: StableState = candid::decode_one(&bytes).map_err(|e| format!("failed to decode migrated state: {}", e))?;
+    state.migration_history.extend(applied);
+    Ok(state)
+}
+
+#[ic_cdk::query]
+fn get_schema_info() -> Result<SchemaInfo, ApiError> {
+    require_admin(caller())?;
+    Ok(SchemaInfo {
+        current_version: SCHEMA_VERSION,
+        migration_history: MIGRATION_HISTORY.with(|h| h.borrow().clone()),
+    })
+}
+
+// --- Backend Administration Functions (Not exposed via Candid) ---
+// These functions can only be called internally or via backend management tools
+
+/// Add a banned keyword (backend only)
+#[allow(dead_code)]
 fn backend_add_banned_keyword(keyword: String) -> bool {
     let keyword_lower = keyword.to_lowercase();
     BANNED_KEYWORDS.with(|keywords| {
@@ -2700,6 +7398,7 @@ fn backend_add_banned_keyword(keyword: String) -> bool {
 }
 
 /// Remove a banned keyword (backend only)
+#[allow(dead_code)]
 fn backend_remove_banned_keyword(keyword: String) -> bool {
     let keyword_lower = keyword.to_lowercase();
     BANNED_KEYWORDS.with(|keywords| {
@@ -2710,42 +7409,1537 @@ fn backend_remove_banned_keyword(keyword: String) -> bool {
     })
 }
 
-/// Promote a user to admin by principal (backend only)
-fn backend_promote_user_to_admin(user_principal: Principal) -> bool {
+/// Initialize an admin (backend only) - called from `init` for each principal in `InitArgs`
+fn backend_initialize_admin(admin_principal: Principal, installer: Principal, now: u64) {
     USERS.with(|users| {
-        let mut users = users.borrow_mut();
-        if let Some(user) = users.iter_mut().find(|u| u.principal == user_principal) {
-            user.role = UserRole::Admin;
-            return true;
-        }
-        false
-    })
+        // Admins without a profile get a placeholder one to hold the role
+        users.borrow_mut().entry(admin_principal).or_insert_with(|| User {
+            principal: admin_principal,
+            username: "Administrator".to_string(),
+            bio: Some("System Administrator".to_string()),
+            avatar_url: None,
+            role: UserRole::User,
+        });
+    });
+    set_role(admin_principal, UserRole::Admin, installer, now);
 }
 
-/// Initialize default admin (backend only) - call this during deployment
-fn backend_initialize_admin(admin_principal: Principal) -> bool {
-    USERS.with(|users| {
-        let mut users = users.borrow_mut();
-        
-        // Check if admin already exists
-        if users.iter().any(|u| u.principal == admin_principal) {
-            // User already exists, just promote to admin
-            if let Some(user) = users.iter_mut().find(|u| u.principal == admin_principal) {
-                user.role = UserRole::Admin;
-                return true;
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(n: u8) -> Principal {
+        Principal::from_slice(&[n; 29])
+    }
+
+    fn sample_track() -> Track {
+        Track {
+            id: 3,
+            title: "Night Drive".to_string(),
+            description: "Synthwave".to_string(),
+            contributors: vec![1, 2],
+            version: 2,
+            splits: Some(vec![Split { id: 1, pct: 60 }, Split { id: 2, pct: 40 }]),
+            comments: vec![Comment { commenter: 2, text: "nice".to_string() }],
+            payments: vec![Payment {
+                payer: 9,
+                amount: 500,
+                timestamp: 10,
+                from: Some(principal(9)),
+                block_index: Some(11),
+                split_sheet: Some(1),
+            }],
+            visibility: TrackVisibility::InviteOnly,
+            invited: vec![4],
+            roles: vec![(1, TrackRole::Owner), (2, TrackRole::Collaborator)],
+            ratings: vec![(4, 5)],
+            tags: vec!["retro".to_string()],
+            genre: Some("electronic".to_string()),
+            play_count: 42,
+            raw_play_count: 57,
+            gateway_play_count: 9,
+            downloadable: false,
+            pricing: TrackPricing { mode: PricingMode::PayWhatYouWant { minimum: 100 }, paid_streaming: true },
+        }
+    }
+
+    /// One populated entry per store. Listing every field keeps this in sync with `StableState`.
+    fn sample_state() -> StableState {
+        StableState {
+            artists: vec![Artist {
+                id: 1,
+                name: "Alice".to_string(),
+                bio: "Producer".to_string(),
+                social: Some("@alice".to_string()),
+                profile_image_url: Some("https://img".to_string()),
+                links: Some(vec!["https://alice.fm".to_string()]),
+                user_principal: principal(1),
+            }],
+            tracks: vec![sample_track()],
+            artist_id: 2,
+            track_id: 4,
+            track_versions: vec![(3, vec![TrackVersion {
+                version: 1,
+                title: "Night Drive".to_string(),
+                description: "Synthwave".to_string(),
+                contributors: vec![1],
+                changed_by: principal(1),
+                changed_at: 5,
+                change_description: Some("Initial version".to_string()),
+            }])],
+            activity_log: vec![Activity { user_id: 1, action: "create_track".to_string(), timestamp: 5, details: "Track 3 created".to_string() }],
+            collab_requests: vec![CollabRequest { id: 1, from: 1, to: 2, track_id: 3, message: None, status: CollabRequestStatus::Accepted, timestamp: 6 }],
+            collab_request_id: 2,
+            tasks: vec![Task { id: 1, track_id: 3, assigned_to: 2, description: "Mix".to_string(), status: TaskStatus::InProgress, created_at: 6, updated_at: 7 }],
+            task_id: 2,
+            users: vec![User { principal: principal(1), username: "alice".to_string(), bio: None, avatar_url: None, role: UserRole::Admin }],
+            user_activity_log: vec![UserActivity { principal: principal(1), action: "register_user".to_string(), timestamp: 1, details: "Registered user: alice".to_string() }],
+            notifications: vec![Notification { id: 1, user_principal: principal(2), message: "hi".to_string(), timestamp: 8, read: true }],
+            notification_id: 2,
+            followed_artists: vec![(principal(2), vec![principal(1)])],
+            followed_tracks: vec![(principal(2), vec![3])],
+            playlists: vec![Playlist { id: 1, owner: principal(2), name: "Faves".to_string(), description: None, track_ids: vec![3], created_at: 9, updated_at: 9 }],
+            playlist_id: 2,
+            play_counts: vec![PlayDownloadCount {
+                principal: principal(2),
+                track_id: 3,
+                play_count: 7,
+                download_count: 1,
+                validated_play_count: 5,
+                last_validated_at: Some(13),
+                burst_window_start: 11,
+                burst_plays: 2,
+            }],
+            messages: vec![Message { id: 1, from: principal(1), to: principal(2), content: "hey".to_string(), timestamp: 10, read: false }],
+            message_id: 2,
+            reports: vec![Report {
+                id: 1,
+                reporter: principal(2),
+                target_type: ReportTargetType::Comment,
+                target_id: "c1".to_string(),
+                reason: "rude".to_string(),
+                details: None,
+                status: ReportStatus::Resolved,
+                created_at: 11,
+                reviewed_by: Some(principal(1)),
+                reviewed_at: Some(12),
+                resolution_notes: Some("removed".to_string()),
+            }],
+            report_id: 2,
+            track_licenses: vec![TrackLicense { track_id: 3, license_type: LicenseType::CreativeCommons, terms: Some("BY".to_string()), contract_text: None, issued_at: 13 }],
+            rate_limits: vec![RateLimitEntry { principal: principal(2), endpoint: "add_comment".to_string(), last_call: 14, call_count: 3, window_start: 12 }],
+            rate_limit_policies: vec![RateLimitPolicy { endpoint: "add_comment".to_string(), max_calls: 5, window_secs: 30 }],
+            audit_log: vec![AuditLogEntry { id: 1, admin: principal(1), action: "ban_user".to_string(), target_type: "User".to_string(), target_id: "x".to_string(), timestamp: 15, details: None }],
+            audit_log_id: 2,
+            moderation_queue: vec![ModerationQueueItem {
+                id: 1,
+                target_type: ModerationTargetType::Track,
+                target_id: "3".to_string(),
+                flagged_by: None,
+                reason: "Contains banned keyword: spam".to_string(),
+                status: ModerationStatus::Pending,
+                created_at: 16,
+                reviewed_by: None,
+                reviewed_at: None,
+                notes: Some("Auto-flagged by system".to_string()),
+            }],
+            moderation_queue_id: 2,
+            suspensions: vec![Suspension {
+                id: 1,
+                target_type: SuspensionTargetType::User,
+                target_id: principal(2).to_text(),
+                reason: "spam".to_string(),
+                imposed_by: principal(1),
+                imposed_at: 17,
+                duration_secs: Some(3600),
+                status: SuspensionStatus::Active,
+                lifted_by: None,
+                lifted_at: None,
+                notes: None,
+            }],
+            suspension_id: 2,
+            suspension_appeals: vec![SuspensionAppeal {
+                id: 1,
+                suspension_id: 1,
+                submitted_by: principal(2),
+                submitted_at: 18,
+                content: "sorry".to_string(),
+                status: AppealStatus::Pending,
+                reviewed_by: None,
+                reviewed_at: None,
+                notes: None,
+            }],
+            suspension_appeal_id: 2,
+            bans: vec![Ban {
+                suspension_id: 1,
+                principal: principal(2),
+                reason: "spam".to_string(),
+                banned_by: principal(1),
+                banned_at: 17,
+                expires_at: Some(3_600_017),
+                hide_tracks: false,
+                previous_role: None,
+                ended_at: None,
+            }],
+            role_permissions: vec![RolePermissions { role: UserRole::Moderator, permissions: vec![Permission::ReviewReports] }],
+            moderator_suspension_cap_secs: 3_600,
+            banned_keywords: vec!["spam".to_string(), "bootleg".to_string()],
+            workflow_steps: vec![WorkflowStep {
+                id: 1,
+                track_id: 3,
+                step_name: "Mastering".to_string(),
+                status: WorkflowStatus::Mastering,
+                assigned_to: vec![2],
+                due_date: Some(20),
+                completed_at: None,
+                notes: None,
+                dependencies: vec![],
+            }],
+            collaboration_sessions: vec![CollaborationSession {
+                id: 1,
+                track_id: 3,
+                session_name: "Vocals".to_string(),
+                participants: vec![1, 2],
+                start_time: 19,
+                end_time: Some(21),
+                notes: None,
+                recording_url: None,
+            }],
+            workflow_templates: vec![WorkflowTemplate {
+                id: 1,
+                name: "Single".to_string(),
+                description: "Release a single".to_string(),
+                steps: vec!["Record".to_string(), "Mix".to_string()],
+                estimated_duration_days: 14,
+                genre_specific: false,
+                target_genre: None,
+            }],
+            workflow_step_id: 2,
+            session_id: 2,
+            template_id: 2,
+            migration_history: vec![MigrationRecord {
+                from_version: 1,
+                to_version: 2,
+                description: "Backfill".to_string(),
+                applied_at: 23,
+            }],
+            track_assets: vec![TrackAsset {
+                id: 1,
+                track_id: 3,
+                kind: AssetKind::Stem,
+                filename: "drums.wav".to_string(),
+                content_type: "audio/wav".to_string(),
+                size: 4,
+                chunk_count: 1,
+                sha256: vec![7; 32],
+                uploaded_by: principal(1),
+                uploaded_at: 24,
+            }],
+            asset_id: 3,
+            upload_sessions: vec![UploadSession {
+                id: 1,
+                asset_id: 2,
+                track_id: 3,
+                kind: AssetKind::Artwork,
+                filename: "cover.png".to_string(),
+                content_type: "image/png".to_string(),
+                total_size: 3 * 1024 * 1024,
+                received_chunks: vec![0, 2],
+                created_by: principal(2),
+                created_at: 25,
+            }],
+            upload_session_id: 2,
+            ledger_canister: Some(principal(7)),
+            journal_entries: [(RoyaltyAccount::Treasury, 300, 0), (RoyaltyAccount::Artist(1), 0, 300)]
+                .into_iter()
+                .enumerate()
+                .map(|(i, (account, debit, credit))| JournalEntry {
+                    id: i as u64 + 1,
+                    transaction_id: 1,
+                    timestamp: 26,
+                    kind: JournalEntryKind::PaymentIn,
+                    account,
+                    debit,
+                    credit,
+                    track_id: Some(3),
+                    block_index: Some(11),
+                })
+                .collect(),
+            journal_entry_id: 3,
+            journal_transaction_id: 2,
+            split_proposals: vec![SplitProposal {
+                id: 1,
+                track_id: 3,
+                splits: vec![Split { id: 1, pct: 50 }, Split { id: 2, pct: 50 }],
+                proposed_by: principal(1),
+                proposed_at: 27,
+                signers: vec![1, 2],
+                approvals: vec![SplitApproval { artist_id: 1, signer: principal(1), approved_at: 27 }],
+                status: SplitProposalStatus::Pending,
+                decided_at: None,
+            }],
+            split_proposal_id: 2,
+            split_sheets: vec![SplitSheet {
+                track_id: 3,
+                version: 1,
+                splits: vec![Split { id: 1, pct: 60 }, Split { id: 2, pct: 40 }],
+                track_version: 1,
+                effective_from: 5,
+                proposal_id: None,
+            }],
+            fee_schedules: vec![FeeSchedule { scope: FeeScope::Genre("electronic".to_string()), bps: 1_500, set_by: principal(1), set_at: 28 }],
+            advances: vec![Advance {
+                id: 1,
+                artist_id: 1,
+                amount: 1_000,
+                recouped: 250,
+                description: "Album advance".to_string(),
+                granted_by: principal(1),
+                granted_at: 29,
+                fully_recouped_at: None,
+            }],
+            advance_id: 2,
+            monthly_statements: vec![MonthlyStatement {
+                id: 1,
+                artist_id: 1,
+                period: "1970-01".to_string(),
+                period_start: 0,
+                period_end: 31 * MS_PER_DAY,
+                opening_balance: 0,
+                tracks: vec![TrackEarnings { track_id: 3, gross: 300, platform_fees: 0, recouped: 0, net: 300 }],
+                memberships: MembershipEarnings { gross: 50, platform_fees: 5, recouped: 0, net: 45 },
+                withdrawn: 0,
+                closing_balance: 300,
+                generated_at: 31 * MS_PER_DAY,
+            }],
+            monthly_statement_id: 2,
+            statements_generated_through: 31 * MS_PER_DAY,
+            entitlements: vec![Entitlement { track_id: 3, owner: principal(2), amount_paid: 150, block_index: 12, purchased_at: 40 }],
+            membership_tiers: vec![MembershipTier {
+                id: 1,
+                artist_id: 1,
+                name: "Supporter".to_string(),
+                description: "Early demos".to_string(),
+                price: 50,
+                period_days: 30,
+                active: true,
+                created_at: 41,
+            }],
+            membership_tier_id: 2,
+            subscriptions: vec![Subscription {
+                id: 1,
+                tier_id: 1,
+                artist_id: 1,
+                subscriber: principal(4),
+                started_at: 42,
+                expires_at: 42 + 30 * MS_PER_DAY,
+                auto_renew: true,
+                status: SubscriptionStatus::Active,
+                renewals: 0,
+                last_block_index: 13,
+            }],
+            subscription_id: 2,
+            campaigns: vec![Campaign {
+                id: 1,
+                track_id: 3,
+                created_by: principal(1),
+                title: "Finish the album".to_string(),
+                description: "Mixing and mastering".to_string(),
+                goal: 1_000,
+                deadline: 90,
+                reward_tiers: vec![RewardTier {
+                    id: 1,
+                    title: "Signed vinyl".to_string(),
+                    description: "Limited run".to_string(),
+                    min_pledge: 200,
+                    limit: Some(50),
+                    claimed: 1,
+                }],
+                pledges: vec![Pledge {
+                    id: 1,
+                    backer: principal(4),
+                    amount: 250,
+                    reward_tier: Some(1),
+                    pledged_at: 50,
+                    block_index: 14,
+                    status: PledgeStatus::Held,
+                }],
+                total_pledged: 250,
+                status: CampaignStatus::Active,
+                created_at: 45,
+                closed_at: None,
+            }],
+            campaign_id: 2,
+        }
+    }
+
+    #[test]
+    fn upgrade_round_trips_every_store() {
+        let expected = sample_state();
+        restore_state(expected.clone());
+        write_chunk(1, 0, &[1, 2, 3, 4]);
+
+        pre_upgrade();
+        assert!(TRACKS.with(|t| t.borrow().is_empty()));
+        restore_from_stable_memory(0, vec![]);
+
+        assert_eq!(artist_ids_of(principal(1)), vec![1]);
+        assert_eq!(format!("{:?}", take_state()), format!("{:?}", expected));
+        assert_eq!(read_chunk(1, 0).expect("asset chunk survives upgrade"), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn legacy_track_files_become_master_assets() {
+        // Stable memory as the first deployed build left it: TRACK_FILES straight on raw memory
+        let image = DefaultMemoryImpl::default();
+        let data: Vec<u8> = (0..ASSET_CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        StableBTreeMap::<u64, TrackFile, _>::new(image.clone()).insert(3, TrackFile {
+            track_id: 3,
+            filename: "night-drive.mp3".to_string(),
+            content_type: "audio/mpeg".to_string(),
+            data: data.clone(),
+            uploaded_by: principal(1),
+            uploaded_at: 22,
+        });
+
+        let legacy_files = take_legacy_track_files(&image);
+        assert_eq!(legacy_files.len(), 1);
+        assert!(take_legacy_track_files(&image).is_empty());
+        // The memory manager then sets up its regions over the old map
+        let mut upgrades = MemoryManager::init(image.clone()).get(UPGRADES_MEMORY_ID);
+        assert!(read_state(&upgrades, 0).is_none());
+        write_state(&mut upgrades, &sample_state());
+        assert!(read_state(&upgrades, 0).is_some());
+        assert!(take_legacy_track_files(&DefaultMemoryImpl::default()).is_empty());
+
+        restore_from_stable_memory(0, legacy_files);
+
+        let asset = TRACK_ASSETS.with(|a| a.borrow()[0].clone());
+        assert_eq!((asset.track_id, asset.kind.clone(), asset.size, asset.chunk_count), (3, AssetKind::Master, data.len() as u64, 2));
+        assert_eq!(asset.sha256, Sha256::digest(&data).to_vec());
+        assert_eq!(asset_sha256(asset.id, asset.chunk_count), asset.sha256);
+        assert_eq!(expected_chunk_len(asset.size, 0), ASSET_CHUNK_SIZE);
+        assert_eq!(expected_chunk_len(asset.size, 1), 10);
+    }
+
+    /// Sends a GET like the HTTP gateway does, repeating it as an update when asked to upgrade.
+    fn get(url: &str, headers: &[(&str, &str)]) -> HttpResponse {
+        let req = HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: headers.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+            body: vec![],
+        };
+        let response = serve_http(&req, 0);
+        if response.upgrade == Some(true) { serve_counted_http(&req, 0) } else { response }
+    }
+
+    fn response_header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn ranged_gateway_replies_fit_in_one_chunk() {
+        TRACKS.with(|t| t.borrow_mut().push(Track { visibility: TrackVisibility::Public, pricing: TrackPricing::default(), downloadable: true, ..sample_track() }));
+        let audio: Vec<u8> = (0..2 * ASSET_CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        store_asset(3, AssetKind::Master, "a.wav".to_string(), "audio/wav".to_string(), &audio, principal(1), 0);
+
+        let first = get("/tracks/3/audio", &[("Range", "bytes=0-")]);
+        assert_eq!((first.status_code, first.body.len()), (206, ASSET_CHUNK_SIZE));
+        assert_eq!(response_header(&first, "Content-Range"), Some(format!("bytes 0-{}/{}", ASSET_CHUNK_SIZE - 1, audio.len()).as_str()));
+        let rest = get("/tracks/3/audio", &[("Range", &format!("bytes={}-", ASSET_CHUNK_SIZE + 50))]);
+        assert_eq!(rest.body, audio[ASSET_CHUNK_SIZE + 50..2 * ASSET_CHUNK_SIZE + 50]);
+    }
+
+    #[test]
+    fn http_gateway_serves_public_tracks_only() {
+        let mut public = Track { visibility: TrackVisibility::Public, pricing: TrackPricing::default(), downloadable: true, ..sample_track() };
+        public.id = 5;
+        TRACKS.with(|t| t.borrow_mut().extend([sample_track(), public]));
+        ARTISTS.with(|a| a.borrow_mut().push(sample_state().artists[0].clone()));
+        let audio: Vec<u8> = (0..100).collect();
+        store_asset(5, AssetKind::Master, "a.mp3".to_string(), "audio/mpeg".to_string(), &audio, principal(1), 0);
+        store_asset(3, AssetKind::Master, "b.mp3".to_string(), "audio/mpeg".to_string(), &audio, principal(1), 0);
+
+        let full = get("/tracks/5/audio", &[]);
+        assert_eq!((full.status_code, full.body.clone()), (200, audio.clone()));
+        assert_eq!(response_header(&full, "Content-Type"), Some("audio/mpeg"));
+        assert_eq!(response_header(&full, "Access-Control-Allow-Origin"), Some("*"));
+        assert!(full.streaming_strategy.is_none());
+
+        let partial = get("/tracks/5/audio?v=1", &[("range", "bytes=10-19")]);
+        assert_eq!((partial.status_code, partial.body.clone()), (206, audio[10..20].to_vec()));
+        assert_eq!(response_header(&partial, "Content-Range"), Some("bytes 10-19/100"));
+        assert_eq!(get("/tracks/5/audio", &[("Range", "bytes=-5")]).body, audio[95..].to_vec());
+        assert_eq!(get("/tracks/5/audio", &[("Range", "bytes=100-")]).status_code, 416);
+        // Only the full request started a play: it was upgraded to an update and counted apart
+        // from listener plays
+        let gateway_plays = || TRACKS.with(|t| t.borrow()[1].gateway_play_count);
+        assert_eq!(gateway_plays(), sample_track().gateway_play_count + 1);
+        assert_eq!(TRACKS.with(|t| t.borrow()[1].raw_play_count), sample_track().raw_play_count);
+        assert!(PLAY_COUNTS.with(|pc| pc.borrow().is_empty()));
+        let req = |method: &str, range: &str| HttpRequest {
+            method: method.to_string(),
+            url: "/tracks/5/audio".to_string(),
+            headers: vec![("Range".to_string(), range.to_string())],
+            body: vec![],
+        };
+        assert_eq!(serve_http(&req("GET", "bytes=0-"), 0).upgrade, Some(true));
+        assert_eq!(serve_http(&req("HEAD", "bytes=0-"), 0).upgrade, None);
+        assert_eq!(serve_http(&req("GET", "bytes=50-"), 0).upgrade, None);
+        serve_counted_http(&req("GET", "bytes=0-9"), 0);
+        assert_eq!(gateway_plays(), sample_track().gateway_play_count + 2);
+        // Once the window's budget is spent plays are served as plain queries, uncounted
+        GATEWAY_PLAY_WINDOW.with(|w| w.borrow_mut().1 = MAX_GATEWAY_PLAYS_PER_WINDOW);
+        let served = serve_http(&req("GET", "bytes=0-9"), 10);
+        assert_eq!((served.upgrade, served.status_code), (None, 206));
+        serve_counted_http(&req("GET", "bytes=0-9"), 10);
+        assert_eq!(gateway_plays(), sample_track().gateway_play_count + 2);
+        assert_eq!(serve_http(&req("GET", "bytes=0-9"), GATEWAY_PLAY_WINDOW_MS).upgrade, Some(true));
+        serve_counted_http(&req("GET", "bytes=0-9"), GATEWAY_PLAY_WINDOW_MS);
+        assert_eq!(gateway_plays(), sample_track().gateway_play_count + 3);
+        assert_eq!(GATEWAY_PLAY_WINDOW.with(|w| *w.borrow()), (GATEWAY_PLAY_WINDOW_MS, 1));
+
+        // Track 3 is invite-only
+        assert_eq!(get("/tracks/3/audio", &[]).status_code, 403);
+        assert_eq!(get("/tracks/3.json", &[]).status_code, 403);
+        assert_eq!(get("/tracks/5/artwork", &[]).status_code, 404);
+        assert_eq!(get("/tracks/9.json", &[]).status_code, 404);
+        // Callback tokens are checked like requests, so forged ones can't reach other assets
+        let stem = store_asset(5, AssetKind::Stem, "s.wav".to_string(), "audio/wav".to_string(), &audio, principal(1), 0);
+        let token = |asset_id| StreamingCallbackToken { track_id: 5, asset_id, chunk_index: 0 };
+        assert_eq!(streaming_chunk(token(1)).unwrap().body, audio);
+        assert_eq!(streaming_chunk(token(stem.id)).unwrap_err().0, 404);
+        assert_eq!(streaming_chunk(StreamingCallbackToken { track_id: 3, ..token(2) }).unwrap_err().0, 403);
+        TRACKS.with(|t| t.borrow_mut()[1].pricing.paid_streaming = true);
+        assert_eq!(get("/tracks/5/audio", &[]).status_code, 402);
+        assert_eq!(streaming_chunk(token(1)).unwrap_err().0, 402);
+        assert_eq!(gateway_plays(), sample_track().gateway_play_count + 3);
+        // Masters of free tracks that aren't downloadable are withheld; the preview plays instead
+        TRACKS.with(|t| {
+            let mut tracks = t.borrow_mut();
+            tracks[1].pricing.paid_streaming = false;
+            tracks[1].downloadable = false;
+        });
+        assert_eq!(get("/tracks/5/audio", &[]).status_code, 403);
+        assert_eq!(streaming_chunk(token(1)).unwrap_err().0, 403);
+        store_asset(5, AssetKind::Preview, "p.mp3".to_string(), "audio/mpeg".to_string(), &audio[..30], principal(1), 0);
+        assert_eq!(get("/tracks/5/audio", &[]).body, audio[..30].to_vec());
+
+        let track: serde_json::Value = serde_json::from_slice(&get("/tracks/5.json", &[]).body).unwrap();
+        assert_eq!(track["title"], "Night Drive");
+        assert_eq!(track["audio_url"], "/tracks/5/audio");
+        assert!(track["artwork_url"].is_null());
+        assert!(track.get("payments").is_none());
+
+        // Suspended tracks are left out of the artist's listing like they are refused on their own
+        let suspended = Track { id: 6, ..TRACKS.with(|t| t.borrow()[1].clone()) };
+        TRACKS.with(|t| t.borrow_mut().push(suspended));
+        impose_suspension(SuspensionTargetType::Track, "6".to_string(), "spam".to_string(), principal(9), None, 0);
+        assert_eq!(get("/tracks/6.json", &[]).status_code, 404);
+        let artist: serde_json::Value = serde_json::from_slice(&get("/artists/1.json", &[]).body).unwrap();
+        assert_eq!(artist["name"], "Alice");
+        assert_eq!(artist["tracks"], serde_json::json!([5]));
+        assert!(artist.get("royalty_balance").is_none());
+    }
+
+    #[test]
+    fn byte_ranges_parse_against_asset_size() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range("bytes=90-200", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=50-", 100), Some((50, 99)));
+        assert_eq!(parse_range("bytes=-500", 100), Some((0, 99)));
+        assert_eq!(parse_range("bytes=10-5", 100), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), None);
+        assert_eq!(parse_range("items=0-1", 100), None);
+    }
+
+    #[test]
+    fn asset_range_reads_span_chunks() {
+        let data: Vec<u8> = (0..2 * ASSET_CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        let asset = store_asset(1, AssetKind::Master, "a.wav".to_string(), "audio/wav".to_string(), &data, principal(1), 0);
+        let boundary = ASSET_CHUNK_SIZE as u64;
+
+        assert_eq!(read_asset_range(&asset, 0, 10).unwrap(), data[..10]);
+        assert_eq!(read_asset_range(&asset, boundary - 5, 10).unwrap(), data[ASSET_CHUNK_SIZE - 5..ASSET_CHUNK_SIZE + 5]);
+        assert_eq!(read_asset_range(&asset, 10, MAX_INLINE_FILE_SIZE).unwrap(), data[10..10 + MAX_INLINE_FILE_SIZE as usize]);
+        // Reads past the end are clamped
+        assert_eq!(read_asset_range(&asset, asset.size - 4, 100).unwrap(), data[data.len() - 4..]);
+
+        assert!(matches!(read_asset_range(&asset, asset.size, 1), Err(ApiError::InvalidInput { .. })));
+        assert!(matches!(read_asset_range(&asset, 0, 0), Err(ApiError::InvalidInput { .. })));
+        assert!(matches!(read_asset_range(&asset, 0, MAX_INLINE_FILE_SIZE + 1), Err(ApiError::InvalidInput { .. })));
+    }
+
+    #[test]
+    fn chunks_take_one_slot_each_and_reuse_freed_slots() {
+        let slots_used = || chunk_data_memory().size() / CHUNK_SLOT_PAGES;
+        let data: Vec<u8> = (0..ASSET_CHUNK_SIZE + 10).map(|i| (i % 251) as u8).collect();
+        let master = store_asset(1, AssetKind::Master, "a.wav".to_string(), "audio/wav".to_string(), &data, principal(1), 0);
+        let artwork = store_asset(1, AssetKind::Artwork, "a.png".to_string(), "image/png".to_string(), &[1, 2, 3], principal(1), 0);
+        assert_eq!(slots_used(), 3);
+        // The slot index itself stays small
+        assert!(MEMORY_MANAGER.with(|m| m.borrow().get(ASSET_CHUNKS_MEMORY_ID).size()) <= 1);
+
+        remove_asset_chunks(master.id);
+        assert_eq!(FREE_CHUNK_SLOTS.with(|free| free.borrow().len()), 2);
+        let replacement = store_asset(1, AssetKind::Master, "b.wav".to_string(), "audio/wav".to_string(), &data, principal(1), 0);
+        assert_eq!(slots_used(), 3);
+        assert!(FREE_CHUNK_SLOTS.with(|free| free.borrow().is_empty()));
+        assert_eq!(read_asset_range(&replacement, 0, data.len() as u64).unwrap(), data);
+        assert_eq!(asset_sha256(replacement.id, replacement.chunk_count), replacement.sha256);
+
+        // A re-sent chunk overwrites its slot
+        write_chunk(artwork.id, 0, &[4, 5, 6]);
+        assert_eq!((read_chunk(artwork.id, 0), slots_used()), (Some(vec![4, 5, 6]), 3));
+    }
+
+    #[test]
+    fn post_upgrade_without_saved_state_keeps_defaults() {
+        assert!(read_state(&upgrades_memory(), 0).is_none());
+        restore_from_stable_memory(0, vec![]);
+        assert_eq!(TRACK_ID.with(|id| *id.borrow()), 1);
+        assert!(!BANNED_KEYWORDS.with(|k| k.borrow().is_empty()));
+    }
+
+    fn remove_field(fields: &mut Vec<IDLField>, name: &str) {
+        let id = Label::Named(name.to_string());
+        fields.retain(|f| f.id != id);
+    }
+
+    #[test]
+    fn v13_migration_moves_unvalidated_plays_to_raw_counts() {
+        let mut value = IDLValue::try_from_candid_type(&sample_state()).unwrap();
+        for_each_record(&mut value, "tracks", |t| remove_field(t, "raw_play_count")).unwrap();
+        migrate_v12_to_v13(&mut value).unwrap();
+
+        let mut counts = vec![];
+        for_each_record(&mut value, "tracks", |t| {
+            let get = |name: &str| t.iter().find(|f| f.id == Label::Named(name.to_string())).map(|f| f.val.clone());
+            counts.push((get("play_count"), get("raw_play_count")));
+        }).unwrap();
+        assert_eq!(counts, vec![(Some(IDLValue::Nat64(0)), Some(IDLValue::Nat64(42)))]);
+    }
+
+    #[test]
+    fn post_upgrade_migrates_v1_state() {
+        // Build a v1 payload: no migration history, and a track/artist/user from before
+        // `play_count`, `downloadable`, `genre`, `user_principal`, `role` and ledger references existed.
+        let mut state = sample_state();
+        state.migration_history.clear();
+        let mut value = IDLValue::try_from_candid_type(&state).unwrap();
+        remove_field(record_fields(&mut value).unwrap(), "migration_history");
+        for_each_record(&mut value, "tracks", |t| {
+            for name in ["play_count", "raw_play_count", "gateway_play_count", "downloadable", "genre", "pricing"] {
+                remove_field(t, name);
             }
-        } else {
-            // Create new admin user
-            let admin_user = User {
-                principal: admin_principal,
-                username: "Administrator".to_string(),
-                bio: Some("System Administrator".to_string()),
-                avatar_url: None,
-                role: UserRole::Admin,
-            };
-            users.push(admin_user);
-            return true;
+            for_each_nested_record(t, "payments", |payment| {
+                for name in ["from", "block_index", "split_sheet"] {
+                    remove_field(payment, name);
+                }
+            });
+        }).unwrap();
+        for_each_record(&mut value, "artists", |a| {
+            remove_field(a, "user_principal");
+            a.push(IDLField { id: Label::Named("royalty_balance".to_string()), val: IDLValue::Nat64(300) });
+            a.sort_by_key(|f| f.id.get_id());
+        }).unwrap();
+        for_each_record(&mut value, "users", |u| remove_field(u, "role")).unwrap();
+        for name in ["track_assets", "asset_id", "upload_sessions", "upload_session_id", "ledger_canister", "journal_entries", "journal_entry_id", "journal_transaction_id",
+            "split_proposals", "split_proposal_id", "split_sheets", "fee_schedules", "advances", "advance_id",
+            "monthly_statements", "monthly_statement_id", "statements_generated_through", "entitlements",
+            "membership_tiers", "membership_tier_id", "subscriptions", "subscription_id", "campaigns", "campaign_id", "rate_limit_policies", "bans", "role_permissions", "moderator_suspension_cap_secs"] {
+            remove_field(record_fields(&mut value).unwrap(), name);
         }
-        false
-    })
-}
\ No newline at end of file
+        let bytes = IDLArgs::new(&[value]).to_bytes().unwrap();
+
+        let mut memory = upgrades_memory();
+        let mut writer = Writer::new(&mut memory, 0);
+        writer.write(STATE_MAGIC).unwrap();
+        writer.write(&1u32.to_le_bytes()).unwrap();
+        writer.write(&(bytes.len() as u64).to_le_bytes()).unwrap();
+        writer.write(&bytes).unwrap();
+
+        restore_from_stable_memory(99, vec![]);
+
+        let track = TRACKS.with(|t| t.borrow()[0].clone());
+        assert_eq!((track.play_count, track.raw_play_count), (0, 0));
+        assert!(track.downloadable);
+        assert_eq!(track.genre, None);
+        assert_eq!(track.pricing, TrackPricing::default());
+        assert!(ENTITLEMENTS.with(|e| e.borrow().is_empty()));
+        assert!(RATE_LIMITS.with(|r| r.borrow().is_empty()));
+        assert_eq!(RATE_LIMIT_POLICIES.with(|p| p.borrow().clone()), default_rate_limit_policies());
+        assert_eq!(ROLE_PERMISSIONS.with(|p| p.borrow().clone()), default_role_permissions());
+        assert_eq!(MODERATOR_SUSPENSION_CAP_SECS.with(|cap| *cap.borrow()), DEFAULT_MODERATOR_SUSPENSION_CAP_SECS);
+        assert_eq!(track.title, "Night Drive");
+        assert_eq!((track.payments[0].amount, track.payments[0].from, track.payments[0].block_index), (500, None, None));
+        assert_eq!(LEDGER_CANISTER.with(|l| *l.borrow()), None);
+        // The stored balance became an opening balance in the journal
+        assert_eq!(get_royalty_balance(1), Ok(300));
+        assert!(reconcile().balanced);
+        assert_eq!(JOURNAL_ENTRY_ID.with(|id| *id.borrow()), 3);
+        // Splits set before agreements existed become the first sheet
+        let sheet = current_split_sheet(3).unwrap();
+        assert_eq!((sheet.version, sheet.effective_from, sheet.proposal_id), (1, 0, None));
+        assert_eq!(sheet.splits, track.splits.unwrap());
+        // Artists from before owners existed are unclaimed until an admin assigns them
+        assert_eq!(ARTISTS.with(|a| a.borrow()[0].user_principal), UNCLAIMED_ARTIST_OWNER);
+        assert!(artist_ids_of(Principal::anonymous()).is_empty());
+        assert!(!owns_artist(Principal::anonymous(), 1));
+        assert!(matches!(claim_artist(1, Principal::anonymous()), Err(ApiError::InvalidInput { .. })));
+        assert_eq!(claim_artist(1, principal(1)).unwrap().user_principal, principal(1));
+        assert!(owns_artist(principal(1), 1));
+        assert!(matches!(claim_artist(1, principal(2)), Err(ApiError::Conflict { .. })));
+        assert_eq!(find_user(principal(1)).unwrap().role, UserRole::User);
+        assert!(TRACK_ASSETS.with(|a| a.borrow().is_empty()));
+        assert_eq!(ASSET_ID.with(|id| *id.borrow()), 1);
+        let history = MIGRATION_HISTORY.with(|h| h.borrow().clone());
+        assert_eq!(history.len(), MIGRATIONS.len());
+        assert_eq!((history[0].from_version, history[0].to_version, history[0].applied_at), (1, 2, 99));
+
+        // Saving again writes the current version, which loads without migrating.
+        pre_upgrade();
+        restore_from_stable_memory(100, vec![]);
+        assert_eq!(MIGRATION_HISTORY.with(|h| h.borrow().len()), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn split_validation_rejects_bad_sheets() {
+        let track = sample_track();
+        let split = |id, pct| Split { id, pct };
+        assert!(validate_splits(&track, &[split(1, 60), split(2, 40)]).is_ok());
+        for bad in [vec![], vec![split(1, 60), split(2, 50)], vec![split(1, 60), split(1, 40)], vec![split(1, 60), split(9, 40)], vec![split(1, 100), split(2, 0)]] {
+            assert!(matches!(validate_splits(&track, &bad), Err(ApiError::InvalidInput { .. })), "{:?} accepted", bad);
+        }
+    }
+
+    #[test]
+    fn split_proposals_need_every_contributor() {
+        TRACKS.with(|t| t.borrow_mut().push(sample_track()));
+        index_artist(principal(1), 1);
+        index_artist(principal(2), 2);
+        let fifty_fifty = vec![Split { id: 1, pct: 50 }, Split { id: 2, pct: 50 }];
+
+        // Only the owner may propose; proposing signs for the proposer's artists
+        assert!(matches!(propose_splits_as(principal(2), 3, fifty_fifty.clone(), 10), Err(ApiError::Unauthorized { .. })));
+        let first = propose_splits_as(principal(1), 3, fifty_fifty.clone(), 10).unwrap();
+        assert_eq!((first.status.clone(), first.approvals.len()), (SplitProposalStatus::Pending, 1));
+        assert!(matches!(approve_splits_as(principal(1), first.id, 11), Err(ApiError::Unauthorized { .. })));
+
+        // A newer proposal supersedes the pending one
+        let second = propose_splits_as(principal(1), 3, fifty_fifty.clone(), 12).unwrap();
+        assert_eq!(find_split_proposal(first.id).unwrap().status, SplitProposalStatus::Superseded);
+        assert!(current_split_sheet(3).is_none());
+
+        let accepted = approve_splits_as(principal(2), second.id, 13).unwrap();
+        assert_eq!(accepted.status, SplitProposalStatus::Accepted);
+        let sheet = current_split_sheet(3).unwrap();
+        assert_eq!((sheet.version, sheet.track_version, sheet.effective_from, sheet.proposal_id), (1, 2, 13, Some(second.id)));
+        assert_eq!(find_track(3).unwrap().splits, Some(fifty_fifty));
+
+        let third = propose_splits_as(principal(1), 3, vec![Split { id: 1, pct: 100 }], 20).unwrap();
+        assert_eq!(reject_splits_as(principal(2), third.id, 21).unwrap().status, SplitProposalStatus::Rejected);
+        assert_eq!(current_split_sheet(3).unwrap().version, 1);
+    }
+
+    #[test]
+    fn fees_and_recoupment_are_deducted_from_shares() {
+        let mut track = sample_track();
+        let schedule = |scope, bps| FeeSchedule { scope, bps, set_by: principal(1), set_at: 0 };
+        FEE_SCHEDULES.with(|s| s.borrow_mut().extend([schedule(FeeScope::Global, 2_000), schedule(FeeScope::Genre("Electronic".to_string()), 1_000)]));
+        assert_eq!(effective_fee_bps(&track), 1_000);
+        FEE_SCHEDULES.with(|s| s.borrow_mut().push(schedule(FeeScope::Track(3), 500)));
+        assert_eq!(effective_fee_bps(&track), 500);
+        track.id = 4;
+        track.genre = None;
+        assert_eq!(effective_fee_bps(&track), 2_000);
+
+        let advance = |id, amount| Advance {
+            id,
+            artist_id: 1,
+            amount,
+            recouped: 0,
+            description: String::new(),
+            granted_by: principal(1),
+            granted_at: 0,
+            fully_recouped_at: None,
+        };
+        ADVANCES.with(|a| a.borrow_mut().extend([advance(1, 100), advance(2, 1_000)]));
+
+        // Artist 1 gets 600, pays a 60 fee, and 540 goes to advances; artist 2 keeps 400 - 40
+        let splits = [Split { id: 1, pct: 60 }, Split { id: 2, pct: 40 }];
+        record_payment_in(Some(3), &splits, 1_000, 1_000, 7, 10);
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(1)), 0);
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(2)), 360);
+        assert_eq!(account_balance(&RoyaltyAccount::Platform), 640);
+        assert_eq!(outstanding_advances(1), 560);
+        assert_eq!(ADVANCES.with(|a| a.borrow()[0].fully_recouped_at), Some(10));
+        assert!(reconcile().balanced);
+
+        let statement = royalty_statement(1, 0, 11);
+        assert_eq!(
+            (statement.gross_royalties, statement.platform_fees, statement.recouped, statement.closing_balance, statement.outstanding_advances),
+            (600, 60, 540, 0, 560)
+        );
+        assert_eq!(royalty_statement(2, 10, 11).closing_balance, 360);
+        assert_eq!(royalty_statement(2, 11, 20).opening_balance, 360);
+        assert!(royalty_statement(2, 11, 20).entries.is_empty());
+
+        // The platform withdraws its share the same way artists do
+        assert_eq!(reserve_payout(RoyaltyAccount::Platform, 641, 12), Err(ApiError::InsufficientFunds { available: 640 }));
+        reserve_payout(RoyaltyAccount::Platform, 600, 12).unwrap();
+        settle_payout(600, 10, 8, 13);
+        assert_eq!(account_balance(&RoyaltyAccount::Platform), 40);
+        let reconciliation = reconcile();
+        assert_eq!((reconciliation.total_paid_out, reconciliation.treasury_balance), (590, 400));
+        assert!(reconciliation.balanced);
+        assert_eq!(royalty_statement(1, 0, 20).closing_balance, 0);
+    }
+
+    #[test]
+    fn month_boundaries_follow_the_calendar() {
+        // 2024-02-29T12:00:00Z, a leap day
+        let leap_day = 1_709_208_000_000;
+        assert_eq!(month_label(leap_day), "2024-02");
+        assert_eq!(month_start(leap_day), 1_706_745_600_000); // 2024-02-01
+        assert_eq!(next_month_start(leap_day), 1_709_251_200_000); // 2024-03-01
+        assert_eq!(next_month_start(1_703_980_800_000), 1_704_067_200_000); // 2023-12-31 -> 2024-01-01
+        assert_eq!(month_start(0), 0);
+    }
+
+    #[test]
+    fn monthly_statements_snapshot_each_ended_month() {
+        let (jan, feb, mar) = (1_704_067_200_000, 1_706_745_600_000, 1_709_251_200_000); // 2024-01/02/03-01
+        ARTISTS.with(|a| a.borrow_mut().extend([sample_state().artists[0].clone(), Artist { id: 2, ..sample_state().artists[0].clone() }]));
+        TRACKS.with(|t| t.borrow_mut().push(Track { title: "Night, Drive".to_string(), ..sample_track() }));
+        let splits = [Split { id: 1, pct: 100 }];
+        record_payment_in(Some(3), &splits, 1_000, 500, 1, jan + 5);
+        record_payment_in(Some(3), &splits, 1_000, 300, 2, feb + 5);
+        reserve_payout(RoyaltyAccount::Artist(1), 200, feb + 6).unwrap();
+        settle_payout(200, 10, 3, feb + 6);
+        assert_eq!(generate_due_statements(5), 0); // January 1970 has no month before it
+
+        // The first run only covers the month that just ended
+        assert_eq!(generate_due_statements(mar + 1), 1);
+        assert_eq!(generate_due_statements(mar + 2), 0);
+        let statements = MONTHLY_STATEMENTS.with(|s| s.borrow().clone());
+        assert_eq!(statements.len(), 1); // artist 2 had nothing to report
+        let february = &statements[0];
+        assert_eq!((february.period.as_str(), february.opening_balance, february.withdrawn, february.closing_balance), ("2024-02", 450, 200, 520));
+        assert_eq!(february.tracks, vec![TrackEarnings { track_id: 3, gross: 300, platform_fees: 30, recouped: 0, net: 270 }]);
+
+        let csv = statement_csv(february);
+        assert_eq!(csv.lines().nth(2), Some("track,2024-02,3,\"Night, Drive\",300,30,0,270"));
+        assert_eq!(csv.lines().last(), Some("closing_balance,2024-02,,,,,,520"));
+
+        // Missed months are caught up in order
+        assert_eq!(generate_due_statements(next_month_start(next_month_start(mar)) + 1), 2);
+        let periods: Vec<String> = MONTHLY_STATEMENTS.with(|s| s.borrow().iter().map(|s| s.period.clone()).collect());
+        assert_eq!(periods, ["2024-02", "2024-03", "2024-04"]);
+        assert_eq!(MONTHLY_STATEMENTS.with(|s| s.borrow()[2].closing_balance), 520);
+
+        // Long gaps are caught up a batch at a time: 2024-05 to 2026-02 is 22 months
+        let march_2026 = 1_772_323_200_000;
+        assert_eq!(generate_due_statements(march_2026), MAX_STATEMENT_MONTHS_PER_RUN);
+        assert_eq!(generate_due_statements(march_2026), 22 - MAX_STATEMENT_MONTHS_PER_RUN);
+        assert_eq!(generate_due_statements(march_2026), 0);
+        assert_eq!(MONTHLY_STATEMENTS.with(|s| s.borrow().last().map(|s| s.period.clone())), Some("2026-02".to_string()));
+    }
+
+    #[test]
+    fn memberships_gate_tracks_and_renew_into_balances() {
+        restore_state(sample_state());
+        let track = Track { visibility: TrackVisibility::SubscribersOnly, ..sample_track() };
+        let (member, fan, other) = (principal(4), principal(7), principal(8));
+        assert!(can_view_track(member, &track));
+        assert!(!can_view_track(fan, &track));
+
+        let tier = find_membership_tier(1).unwrap();
+        let schedule = FeeSchedule { scope: FeeScope::Global, bps: 1_000, set_by: principal(1), set_at: 0 };
+        FEE_SCHEDULES.with(|s| *s.borrow_mut() = vec![schedule]);
+        ADVANCES.with(|a| a.borrow_mut().clear());
+        JOURNAL_ENTRIES.with(|j| j.borrow_mut().clear());
+        let joined = activate_subscription(fan, &tier, 20, 100);
+        assert_eq!(joined.expires_at, 100 + 30 * MS_PER_DAY);
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(1)), 45);
+        assert!(can_view_track(fan, &track));
+        assert_eq!(FOLLOWED_ARTISTS.with(|fa| fa.borrow().iter().find(|(p, _)| *p == fan).unwrap().1.clone()), vec![principal(1)]);
+        let left = activate_subscription(other, &tier, 21, 100);
+
+        // At the end of the period cancelled memberships lapse and the rest are charged again
+        update_subscription(left.id, |s| s.auto_renew = false);
+        let due = due_renewals(joined.expires_at);
+        let due_ids: Vec<u64> = due.iter().map(|(s, _)| s.id).collect();
+        assert_eq!(due_ids, vec![1, joined.id]);
+        assert!(!is_subscribed(other, 1));
+        assert!(NOTIFICATIONS.with(|n| n.borrow().iter().any(|n| n.user_principal == other)));
+        extend_subscription(joined.id, &tier, 22, joined.expires_at);
+        let renewed = SUBSCRIPTIONS.with(|s| s.borrow().iter().find(|s| s.id == joined.id).cloned()).unwrap();
+        assert_eq!((renewed.expires_at, renewed.renewals, renewed.last_block_index), (100 + 60 * MS_PER_DAY, 1, 22));
+        assert!(reconcile().balanced);
+
+        // An unavailable ledger is retried a few times; a declined charge ends the membership
+        renewal_failed(1, false, "TemporarilyUnavailable", 200);
+        renewal_failed(1, false, "TemporarilyUnavailable", 300);
+        assert!(is_subscribed(member, 1));
+        renewal_failed(1, false, "TemporarilyUnavailable", 400);
+        assert!(!is_subscribed(member, 1));
+        renewal_failed(joined.id, true, "InsufficientAllowance", 500);
+        assert!(!is_subscribed(fan, 1));
+        assert!(RENEWAL_FAILURES.with(|f| f.borrow().is_empty()));
+
+        // Membership income shows up separately from track earnings
+        let statement = royalty_statement(1, 100, 100 + 31 * MS_PER_DAY);
+        let (tracks, memberships) = statement_earnings(&statement.entries);
+        assert!(tracks.is_empty());
+        assert_eq!(memberships, MembershipEarnings { gross: 150, platform_fees: 15, recouped: 0, net: 135 });
+    }
+
+    fn campaign(id: u64, goal: u64) -> Campaign {
+        Campaign {
+            id,
+            track_id: 3,
+            created_by: principal(1),
+            title: format!("Campaign {}", id),
+            description: String::new(),
+            goal,
+            deadline: 100,
+            reward_tiers: vec![RewardTier { id: 1, title: "Vinyl".to_string(), description: String::new(), min_pledge: 200, limit: Some(1), claimed: 0 }],
+            pledges: vec![],
+            total_pledged: 0,
+            status: CampaignStatus::Active,
+            created_at: 0,
+            closed_at: None,
+        }
+    }
+
+    #[test]
+    fn job_guards_release_their_flag_when_dropped() {
+        let running = JobGuard::acquire(&RENEWALS_IN_FLIGHT).unwrap();
+        assert!(JobGuard::acquire(&RENEWALS_IN_FLIGHT).is_none());
+        assert!(JobGuard::acquire(&CAMPAIGN_JOBS_IN_FLIGHT).is_some());
+        drop(running);
+        assert!(JobGuard::acquire(&RENEWALS_IN_FLIGHT).is_some());
+        assert!(!RENEWALS_IN_FLIGHT.with(|f| *f.borrow()));
+    }
+
+    #[test]
+    fn campaigns_release_or_refund_escrow_at_the_deadline() {
+        TRACKS.with(|t| t.borrow_mut().push(sample_track()));
+        seed_split_sheets();
+        CAMPAIGNS.with(|c| c.borrow_mut().extend([campaign(1, 500), campaign(2, 1_000)]));
+        let mut funded = campaign(1, 500);
+        assert!(claim_reward(&mut funded, 1, 250).is_ok());
+        assert!(matches!(claim_reward(&mut funded, 1, 250), Err(ApiError::Conflict { .. })));
+        assert!(matches!(claim_reward(&mut campaign(1, 500), 1, 100), Err(ApiError::InvalidInput { .. })));
+
+        record_pledge(1, principal(4), 300, None, 1, 10).unwrap();
+        record_pledge(1, principal(5), 300, None, 2, 11).unwrap();
+        record_pledge(2, principal(4), 200, None, 3, 12).unwrap();
+        record_pledge(2, principal(5), 50, None, 4, 13).unwrap();
+        let held = reconcile();
+        assert_eq!((held.escrow_balance, held.artist_balances, held.balanced), (850, 0, true));
+
+        assert_eq!(settle_due_campaigns(99), 0);
+        assert_eq!(settle_due_campaigns(100), 2);
+        let (funded, missed) = (find_campaign(1).unwrap(), find_campaign(2).unwrap());
+        assert_eq!((funded.status, missed.status), (CampaignStatus::Succeeded, CampaignStatus::Failed));
+        assert!(funded.pledges.iter().all(|p| p.status == PledgeStatus::Released));
+        assert_eq!((account_balance(&RoyaltyAccount::Artist(1)), account_balance(&RoyaltyAccount::Artist(2))), (360, 240));
+
+        // A pledge that lands after success is released straight away
+        record_pledge(1, principal(6), 100, None, 5, 101).unwrap();
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(1)), 420);
+
+        let refunds = due_refunds();
+        assert_eq!(refunds.iter().map(|(c, p)| (*c, p.backer)).collect::<Vec<_>>(), vec![(2, principal(4)), (2, principal(5))]);
+        let (first, second) = (&refunds[0].1, &refunds[1].1);
+        reserve_refund(2, first, 102);
+        settle_refund(2, first, 10, 6, 102);
+        reserve_refund(2, second, 102);
+        release_refund(2, second, "amount must exceed the ledger fee".to_string(), 102);
+        assert_eq!(find_campaign(2).unwrap().pledges[0].status, PledgeStatus::Refunded { block_index: 6 });
+        // Failed refunds go back into escrow and are retried
+        assert_eq!(due_refunds().iter().map(|(_, p)| p.id).collect::<Vec<_>>(), vec![second.id]);
+        let after = reconcile();
+        assert_eq!((after.escrow_balance, after.pending_payouts, after.balanced), (50, 0, true));
+
+        // Refunds stuck in flight are settled by an admin after checking the ledger
+        let stuck = find_campaign(2).unwrap().pledges[1].clone();
+        assert!(matches!(resolve_refund(2, stuck.id, RefundResolution::Retry, 103), Err(ApiError::Conflict { .. })));
+        reserve_refund(2, &stuck, 103);
+        assert!(matches!(resolve_refund(2, stuck.id, RefundResolution::Completed { block_index: 7, fee: 51 }, 104), Err(ApiError::InvalidInput { .. })));
+        assert_eq!(resolve_refund(2, stuck.id, RefundResolution::Completed { block_index: 7, fee: 10 }, 104), Ok(PledgeStatus::Refunded { block_index: 7 }));
+        let settled = reconcile();
+        assert_eq!((settled.escrow_balance, settled.pending_payouts, settled.balanced), (0, 0, true));
+    }
+
+    #[test]
+    fn campaigns_that_cannot_release_are_refunded() {
+        TRACKS.with(|t| t.borrow_mut().push(sample_track()));
+        seed_split_sheets();
+        CAMPAIGNS.with(|c| c.borrow_mut().extend([campaign(1, 500), Campaign { track_id: 8, ..campaign(2, 100) }]));
+        assert!(matches!(require_no_active_campaign(3), Err(ApiError::Conflict { .. })));
+        record_pledge(2, principal(4), 300, None, 1, 10).unwrap();
+
+        // Campaign 2's track is gone, so reaching its goal still ends in refunds
+        CAMPAIGNS.with(|c| c.borrow_mut().retain(|c| c.id != 1));
+        assert!(require_no_active_campaign(3).is_ok());
+        assert_eq!(settle_due_campaigns(100), 1);
+        let failed = find_campaign(2).unwrap();
+        assert_eq!((failed.status, failed.pledges[0].status.clone()), (CampaignStatus::Failed, PledgeStatus::Held));
+        assert_eq!(reconcile().escrow_balance, 300);
+
+        // A late pledge to a succeeded campaign whose track was deleted is refunded too
+        CAMPAIGNS.with(|c| c.borrow_mut().push(Campaign { status: CampaignStatus::Succeeded, track_id: 8, ..campaign(3, 100) }));
+        record_pledge(3, principal(5), 100, None, 2, 101).unwrap();
+        assert_eq!(due_refunds().iter().map(|(c, p)| (*c, p.backer)).collect::<Vec<_>>(), vec![(2, principal(4)), (3, principal(5))]);
+    }
+
+    #[test]
+    fn rate_limits_apply_per_endpoint_and_expire() {
+        RATE_LIMIT_POLICIES.with(|p| {
+            p.borrow_mut().retain(|p| p.endpoint != "send_message");
+            p.borrow_mut().push(RateLimitPolicy { endpoint: "send_message".to_string(), max_calls: 2, window_secs: 10 });
+        });
+        let (spammer, other) = (principal(1), principal(2));
+        assert!(check_rate_limit(spammer, "send_message", 1_000).is_ok());
+        assert!(check_rate_limit(spammer, "send_message", 1_500).is_ok());
+        assert_eq!(check_rate_limit(spammer, "send_message", 2_000), Err(ApiError::RateLimited { retry_after_secs: 9 }));
+        assert_eq!(check_rate_limit(spammer, "send_message", 10_999), Err(ApiError::RateLimited { retry_after_secs: 1 }));
+        // Other principals and endpoints have their own counters
+        assert!(check_rate_limit(other, "send_message", 2_000).is_ok());
+        assert!(check_rate_limit(spammer, "add_comment", 2_000).is_ok());
+        assert!(check_rate_limit(spammer, "not_limited", 2_000).is_ok());
+        assert!(matches!(check_rate_limit(Principal::anonymous(), "not_limited", 2_000), Err(ApiError::Unauthorized { .. })));
+
+        assert!(check_rate_limit(spammer, "send_message", 11_000).is_ok());
+        assert_eq!(gc_rate_limits(12_000), 1); // other's window ended at 12s
+        assert_eq!(gc_rate_limits(62_000), 2);
+        assert!(RATE_LIMITS.with(|r| r.borrow().is_empty()));
+
+        let policy = |window_secs| RateLimitPolicy { endpoint: "send_message".to_string(), max_calls: 2, window_secs };
+        assert!(validate_rate_limit_policy(&policy(MAX_RATE_LIMIT_WINDOW_SECS)).is_ok());
+        assert!(matches!(validate_rate_limit_policy(&policy(0)), Err(ApiError::InvalidInput { .. })));
+        assert!(matches!(validate_rate_limit_policy(&policy(u64::MAX)), Err(ApiError::InvalidInput { .. })));
+        assert!(matches!(validate_rate_limit_policy(&RateLimitPolicy { max_calls: 0, ..policy(10) }), Err(ApiError::InvalidInput { .. })));
+        // Windows stored before the bound existed can't overflow the counters
+        RATE_LIMIT_POLICIES.with(|p| p.borrow_mut().iter_mut().for_each(|p| p.window_secs = u64::MAX));
+        assert!(check_rate_limit(spammer, "send_message", 70_000).is_ok());
+        assert!(check_rate_limit(spammer, "send_message", 70_001).is_ok());
+        assert!(matches!(check_rate_limit(spammer, "send_message", 70_002), Err(ApiError::RateLimited { .. })));
+        assert_eq!(gc_rate_limits(u64::MAX - 1), 0);
+    }
+
+    #[test]
+    fn plays_are_validated_deduplicated_and_burst_checked() {
+        TRACKS.with(|t| t.borrow_mut().push(Track { play_count: 0, raw_play_count: 0, ..sample_track() }));
+        let listener = principal(4);
+        let minute = 60 * 1000;
+        assert!(!count_play(listener, 3, Some(MIN_LISTEN_SECS - 1), 0)); // too short
+        assert!(!count_play(listener, 3, None, minute)); // no listen reported
+        assert!(count_play(listener, 3, Some(MIN_LISTEN_SECS), 2 * minute));
+        assert!(!count_play(listener, 3, Some(200), 3 * minute)); // within the dedup window
+        assert!(count_play(listener, 3, Some(200), 2 * minute + PLAY_DEDUP_WINDOW_MS));
+        assert!(count_play(principal(5), 3, Some(200), 3 * minute)); // other listeners count separately
+        let track = find_track(3).unwrap();
+        assert_eq!((track.play_count, track.raw_play_count), (3, 6));
+
+        // Past the burst threshold plays stop validating and the track is flagged once
+        let start = 10 * PLAY_BURST_WINDOW_MS;
+        for i in 0..PLAY_BURST_THRESHOLD + 5 {
+            count_play(principal(6), 3, Some(200), start + i as u64);
+        }
+        assert!(!count_play(principal(6), 3, Some(200), start + PLAY_DEDUP_WINDOW_MS));
+        let flags = MODERATION_QUEUE.with(|q| q.borrow().clone());
+        assert_eq!(flags.len(), 1);
+        assert_eq!((flags[0].target_type.clone(), flags[0].target_id.as_str()), (ModerationTargetType::Track, "3"));
+        // A new window starts clean
+        assert!(count_play(principal(6), 3, Some(200), start + PLAY_BURST_WINDOW_MS));
+        let entry = PLAY_COUNTS.with(|pc| pc.borrow().iter().find(|e| e.principal == principal(6)).cloned()).unwrap();
+        assert_eq!((entry.play_count, entry.validated_play_count), (PLAY_BURST_THRESHOLD as u64 + 7, 2));
+    }
+
+    fn suspension(id: u64, target_type: SuspensionTargetType, target_id: &str, duration_secs: Option<u64>) -> Suspension {
+        Suspension {
+            id,
+            target_type,
+            target_id: target_id.to_string(),
+            reason: "abuse".to_string(),
+            imposed_by: principal(9),
+            imposed_at: 1_000,
+            duration_secs,
+            status: SuspensionStatus::Active,
+            lifted_by: None,
+            lifted_at: None,
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn suspensions_block_users_artists_and_tracks_until_they_end() {
+        let track = Track { visibility: TrackVisibility::Public, ..sample_track() };
+        TRACKS.with(|t| t.borrow_mut().push(track.clone()));
+        index_artist(principal(1), 1);
+        index_artist(principal(2), 2);
+        SUSPENSIONS.with(|s| s.borrow_mut().extend([
+            suspension(1, SuspensionTargetType::User, &principal(2).to_text(), Some(60)),
+            suspension(2, SuspensionTargetType::Track, "3", None),
+            suspension(3, SuspensionTargetType::Artist, "1", Some(120)),
+            // Long enough to overflow if the expiry weren't saturating
+            suspension(4, SuspensionTargetType::User, &principal(5).to_text(), Some(u64::MAX)),
+        ]));
+
+        assert!(matches!(require_active_user(principal(2)), Err(ApiError::Suspended { .. })));
+        assert!(require_active_user(principal(1)).is_ok());
+        // Suspended tracks are hidden from the public and frozen for their owners
+        assert!(!can_view_track(principal(7), &track));
+        assert!(can_view_track(principal(1), &track));
+        assert!(matches!(require_track_role(principal(1), &track, TrackRole::Owner), Err(ApiError::Suspended { .. })));
+        assert!(matches!(require_artist_owner(principal(1), 1), Err(ApiError::Suspended { .. })));
+        assert!(require_artist_owner_or_admin(principal(1), 1).is_ok());
+        assert!(matches!(require_artist_owner(principal(2), 2), Err(ApiError::Suspended { .. })));
+
+        assert_eq!(expire_suspensions(principal(0), 1_000 + 60_000 - 1), 0);
+        assert_eq!(expire_suspensions(principal(0), 1_000 + 60_000), 1);
+        assert!(require_artist_owner(principal(2), 2).is_ok());
+        assert_eq!(expire_suspensions(principal(0), 1_000 + 120_000), 1);
+        assert!(require_artist_owner(principal(1), 1).is_ok());
+
+        // An approved appeal lifts what the timer never will
+        assert_eq!(lift_on_appeal(2, 7, principal(9), 5_000), Some(("Track".to_string(), "3".to_string())));
+        assert_eq!(lift_on_appeal(2, 8, principal(9), 6_000), None);
+        let lifted = SUSPENSIONS.with(|s| s.borrow()[1].clone());
+        assert_eq!((lifted.status, lifted.lifted_by, lifted.lifted_at), (SuspensionStatus::Lifted, Some(principal(9)), Some(5_000)));
+        assert!(can_view_track(principal(7), &track));
+    }
+
+    #[test]
+    fn bans_refuse_the_user_and_end_with_their_suspension() {
+        let track = Track { visibility: TrackVisibility::Public, ..sample_track() };
+        let state = sample_state();
+        TRACKS.with(|t| t.borrow_mut().push(track.clone()));
+        ARTISTS.with(|a| a.borrow_mut().extend(state.artists));
+        USERS.with(|u| u.borrow_mut().extend(state.users.into_iter().map(|user| (user.principal, user))));
+        index_artist(principal(1), 1);
+
+        assert!(matches!(ban_as(principal(9), principal(9), "x".to_string(), None, false, 1_000), Err(ApiError::InvalidInput { .. })));
+        let ban = ban_as(principal(9), principal(1), "fraud".to_string(), Some(60), true, 1_000).unwrap();
+        assert_eq!((ban.suspension_id, ban.expires_at, ban.previous_role.clone()), (1, Some(61_000), Some(UserRole::Admin)));
+        assert_eq!(find_user(principal(1)).unwrap().role, UserRole::Banned);
+        assert!(!is_admin(principal(1)));
+        match require_active_user(principal(1)) {
+            Err(ApiError::Suspended { reason }) => assert!(reason.contains("appeal suspension 1")),
+            other => panic!("expected a ban, got {:?}", other),
+        }
+        assert!(matches!(ban_as(principal(9), principal(1), "again".to_string(), None, false, 2_000), Err(ApiError::Conflict { .. })));
+        // Their tracks are hidden from everyone else while the ban lasts, and they see none at all
+        assert!(!can_view_track(principal(7), &track));
+        assert!(!can_view_track(principal(1), &track));
+        assert!(matches!(require_not_banned(principal(1)), Err(ApiError::Suspended { .. })));
+        assert!(require_not_banned(principal(7)).is_ok());
+
+        // Expiry ends the ban and restores the role
+        assert_eq!(expire_suspensions(principal(0), 61_000), 1);
+        assert!(active_ban(principal(1)).is_none());
+        assert_eq!(find_user(principal(1)).unwrap().role, UserRole::Admin);
+        assert!(can_view_track(principal(7), &track));
+
+        // So does an approved appeal on the ban's suspension
+        let ban = ban_as(principal(9), principal(1), "again".to_string(), None, false, 70_000).unwrap();
+        assert!(can_view_track(principal(7), &track));
+        assert!(lift_on_appeal(ban.suspension_id, 1, principal(9), 80_000).is_some());
+        assert!(require_active_user(principal(1)).is_ok());
+        assert_eq!(find_user(principal(1)).unwrap().role, UserRole::Admin);
+
+        // And an unban, which lifts the suspension too
+        let ban = ban_as(principal(9), principal(1), "third".to_string(), Some(u64::MAX), false, 90_000).unwrap();
+        assert_eq!(ban.expires_at, Some(u64::MAX));
+        let ended = unban_as(principal(9), principal(1), Some("resolved".to_string()), 95_000).unwrap();
+        assert_eq!(ended.ended_at, Some(95_000));
+        let lifted = SUSPENSIONS.with(|s| s.borrow().iter().find(|s| s.id == ban.suspension_id).cloned().unwrap());
+        assert_eq!((lifted.status, lifted.lifted_by), (SuspensionStatus::Lifted, Some(principal(9))));
+        assert_eq!(find_user(principal(1)).unwrap().role, UserRole::Admin);
+        assert!(matches!(unban_as(principal(9), principal(1), None, 96_000), Err(ApiError::NotFound { .. })));
+    }
+
+    #[test]
+    fn role_changes_are_checked_and_audited() {
+        USERS.with(|u| u.borrow_mut().extend(sample_state().users.into_iter().map(|user| (user.principal, user))));
+        USERS.with(|u| u.borrow_mut().insert(principal(2), User { principal: principal(2), username: "bob".to_string(), bio: None, avatar_url: None, role: UserRole::User }));
+
+        assert!(matches!(change_role(principal(2), UserRole::Banned, principal(1), 10), Err(ApiError::InvalidInput { .. })));
+        assert!(matches!(change_role(principal(3), UserRole::Admin, principal(1), 10), Err(ApiError::NotFound { .. })));
+        assert_eq!(change_role(principal(2), UserRole::Moderator, principal(1), 10), Ok(UserRole::User));
+        assert!(has_permission(principal(2), &Permission::ReviewReports) && !is_admin(principal(2)));
+        let entry = AUDIT_LOG.with(|log| log.borrow().last().cloned().unwrap());
+        assert_eq!((entry.admin, entry.action.as_str(), entry.target_id, entry.timestamp), (principal(1), "set_user_role", principal(2).to_text(), 10));
+        assert_eq!(entry.details.as_deref(), Some("Role changed from User to Moderator"));
+        // Setting the same role again changes nothing and logs nothing
+        change_role(principal(2), UserRole::Moderator, principal(1), 11).unwrap();
+        assert_eq!(AUDIT_LOG.with(|log| log.borrow().len()), 1);
+
+        // Install-time admins get a profile if they have none
+        backend_initialize_admin(principal(4), principal(0), 20);
+        assert!(is_admin(principal(4)));
+        assert_eq!(AUDIT_LOG.with(|log| log.borrow().last().unwrap().admin), principal(0));
+
+        // Banned users keep their role until unbanned, and both changes are logged
+        ban_as(principal(1), principal(2), "spam".to_string(), None, false, 30).unwrap();
+        assert!(matches!(change_role(principal(2), UserRole::Admin, principal(1), 31), Err(ApiError::Conflict { .. })));
+        unban_as(principal(4), principal(2), None, 32).unwrap();
+        assert_eq!(find_user(principal(2)).unwrap().role, UserRole::Moderator);
+        let changes: Vec<_> = AUDIT_LOG.with(|log| log.borrow().iter().skip(2).map(|e| (e.admin, e.details.clone().unwrap())).collect());
+        assert_eq!(changes, vec![
+            (principal(1), "Role changed from Moderator to Banned".to_string()),
+            (principal(4), "Role changed from Banned to Moderator".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn moderators_act_within_the_permission_matrix() {
+        USERS.with(|u| u.borrow_mut().extend(sample_state().users.into_iter().map(|user| (user.principal, user))));
+        USERS.with(|u| u.borrow_mut().insert(principal(2), User { principal: principal(2), username: "mod".to_string(), bio: None, avatar_url: None, role: UserRole::Moderator }));
+        let admin = principal(1).to_text();
+
+        assert_eq!(permissions_of(principal(1)), ALL_PERMISSIONS.to_vec());
+        assert_eq!(permissions_of(principal(2)), vec![Permission::ReviewReports, Permission::ReviewModerationQueue, Permission::SuspendTargets]);
+        assert!(permissions_of(principal(3)).is_empty());
+        assert!(require_permission(principal(2), Permission::ReviewReports).is_ok());
+        for permission in ADMIN_ONLY_PERMISSIONS {
+            assert!(matches!(require_permission(principal(2), permission), Err(ApiError::Unauthorized { .. })));
+        }
+
+        // Moderator suspensions are time-bound, capped and never hit admins
+        let cap = DEFAULT_MODERATOR_SUSPENSION_CAP_SECS;
+        assert!(check_suspension_scope(principal(2), &SuspensionTargetType::Track, "3", Some(cap)).is_ok());
+        assert!(matches!(check_suspension_scope(principal(2), &SuspensionTargetType::Track, "3", Some(cap + 1)), Err(ApiError::InvalidInput { .. })));
+        assert!(matches!(check_suspension_scope(principal(2), &SuspensionTargetType::Track, "3", None), Err(ApiError::InvalidInput { .. })));
+        assert!(matches!(check_suspension_scope(principal(2), &SuspensionTargetType::User, &admin, Some(60)), Err(ApiError::Unauthorized { .. })));
+        assert!(check_suspension_scope(principal(1), &SuspensionTargetType::User, &principal(2).to_text(), None).is_ok());
+
+        // The matrix changes at runtime, but admin-only permissions can't be handed out
+        assert!(matches!(update_role_permissions(UserRole::Moderator, vec![Permission::ViewAuditLog]), Err(ApiError::InvalidInput { .. })));
+        assert!(matches!(update_role_permissions(UserRole::Admin, vec![]), Err(ApiError::InvalidInput { .. })));
+        update_role_permissions(UserRole::Moderator, vec![Permission::ReviewAppeals, Permission::LiftSuspensions, Permission::ReviewAppeals]).unwrap();
+        assert_eq!(permissions_of(principal(2)), vec![Permission::LiftSuspensions, Permission::ReviewAppeals]);
+        assert!(!has_permission(principal(2), &Permission::ReviewReports));
+
+        // Lifting suspensions doesn't extend to bans
+        let ban = ban_as(principal(1), principal(3), "spam".to_string(), None, false, 10).unwrap();
+        assert!(matches!(require_ban_authority(principal(2), ban.suspension_id), Err(ApiError::Unauthorized { .. })));
+        assert!(require_ban_authority(principal(1), ban.suspension_id).is_ok());
+        assert!(require_ban_authority(principal(2), ban.suspension_id + 1).is_ok());
+
+        // A suspended moderator keeps the permission but can't use it
+        SUSPENSIONS.with(|s| s.borrow_mut().push(suspension(1, SuspensionTargetType::User, &principal(2).to_text(), None)));
+        assert!(matches!(require_permission(principal(2), Permission::ReviewAppeals), Err(ApiError::Suspended { .. })));
+    }
+
+    fn report(id: u64, reporter: Principal, status: ReportStatus, created_at: u64) -> Report {
+        Report {
+            id,
+            reporter,
+            target_type: if id.is_multiple_of(2) { ReportTargetType::Track } else { ReportTargetType::Comment },
+            target_id: id.to_string(),
+            reason: "spam".to_string(),
+            details: None,
+            status,
+            created_at,
+            reviewed_by: Some(principal(1)),
+            reviewed_at: None,
+            resolution_notes: Some("internal".to_string()),
+        }
+    }
+
+    #[test]
+    fn staff_listings_are_gated_filtered_and_paged() {
+        USERS.with(|u| u.borrow_mut().extend(sample_state().users.into_iter().map(|user| (user.principal, user))));
+        USERS.with(|u| u.borrow_mut().insert(principal(2), User { principal: principal(2), username: "mod".to_string(), bio: None, avatar_url: None, role: UserRole::Moderator }));
+        USERS.with(|u| u.borrow_mut().insert(principal(3), User { principal: principal(3), username: "fan".to_string(), bio: None, avatar_url: None, role: UserRole::User }));
+        REPORTS.with(|r| r.borrow_mut().extend((1..=5).map(|id| {
+            let status = if id == 3 { ReportStatus::Resolved } else { ReportStatus::Pending };
+            report(id, principal(3 + (id % 2) as u8), status, id * 100)
+        })));
+
+        // Moderators can list suspensions they may impose; plain users can list nothing
+        assert!(require_any_permission(principal(2), &SUSPENSION_PERMISSIONS).is_ok());
+        assert!(require_any_permission(principal(2), &ALL_PERMISSIONS).is_ok());
+        assert!(matches!(require_any_permission(principal(3), &ALL_PERMISSIONS), Err(ApiError::Unauthorized { .. })));
+
+        let pending = ReportFilter { status: Some(ReportStatus::Pending), ..Default::default() };
+        let ids = |reports: Vec<Report>| reports.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids(filter_reports(&pending)), vec![1, 2, 4, 5]);
+        let tracks_in_range = ReportFilter { target_type: Some(ReportTargetType::Track), from: Some(200), to: Some(400), ..Default::default() };
+        assert_eq!(ids(filter_reports(&tracks_in_range)), vec![2]);
+
+        let first = paginate(filter_reports(&pending), &PageRequest { offset: 0, limit: 3 });
+        assert_eq!((ids(first.items), first.total, first.next_offset), (vec![5, 4, 2], 4, Some(3)));
+        let last = paginate(filter_reports(&pending), &PageRequest { offset: 3, limit: 3 });
+        assert_eq!((ids(last.items), last.next_offset), (vec![1], None));
+        assert_eq!(paginate(filter_reports(&ReportFilter::default()), &PageRequest { offset: 0, limit: 0 }).items.len(), 1);
+        let beyond = paginate(filter_reports(&pending), &PageRequest { offset: u64::MAX, limit: 3 });
+        assert_eq!((beyond.items.len(), beyond.total, beyond.next_offset), (0, 4, None));
+
+        // Reporters see only their own reports, without the reviewer's notes
+        let mine = reports_by(principal(3));
+        assert_eq!(mine.iter().map(|r| r.id).collect::<Vec<_>>(), vec![4, 2]);
+        assert_eq!(mine[0].status, ReportStatus::Pending);
+
+        // Appeals can be filtered by what the appealed suspension targets
+        SUSPENSIONS.with(|s| s.borrow_mut().extend([
+            suspension(1, SuspensionTargetType::User, &principal(3).to_text(), None),
+            suspension(2, SuspensionTargetType::Track, "3", None),
+        ]));
+        SUSPENSION_APPEALS.with(|a| a.borrow_mut().extend(sample_state().suspension_appeals.into_iter().chain([SuspensionAppeal {
+            id: 2,
+            suspension_id: 2,
+            ..sample_state().suspension_appeals[0].clone()
+        }])));
+        let track_appeals = filter_suspension_appeals(&AppealFilter { target_type: Some(SuspensionTargetType::Track), ..Default::default() });
+        assert_eq!(track_appeals.iter().map(|a| a.id).collect::<Vec<_>>(), vec![2]);
+        let active_user = filter_suspensions(&SuspensionFilter { target_type: Some(SuspensionTargetType::User), status: Some(SuspensionStatus::Active), ..Default::default() });
+        assert_eq!(active_user.len(), 1);
+    }
+
+    #[test]
+    fn royalty_shares_lose_no_remainder() {
+        let splits = [Split { id: 1, pct: 60 }, Split { id: 2, pct: 40 }];
+        assert_eq!(royalty_shares(&splits, 1_000), (vec![(1, 600), (2, 400)], None));
+        assert_eq!(royalty_shares(&splits, 999), (vec![(1, 599), (2, 399)], Some((1, 1))));
+        let thirds = [Split { id: 1, pct: 33 }, Split { id: 2, pct: 34 }, Split { id: 3, pct: 33 }];
+        assert_eq!(royalty_shares(&thirds, 10), (vec![(1, 3), (2, 3), (3, 3)], Some((2, 1))));
+        assert_eq!(royalty_shares(&thirds, u64::MAX).0[1].1, (u64::MAX as u128 * 34 / 100) as u64);
+    }
+
+    #[test]
+    fn royalty_journal_derives_balances_and_reconciles() {
+        let splits = [Split { id: 1, pct: 50 }, Split { id: 2, pct: 50 }];
+        record_payment_in(Some(3), &splits, 0, 1_001, 7, 10);
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(1)), 501);
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(2)), 500);
+
+        assert_eq!(reserve_payout(RoyaltyAccount::Artist(1), 502, 11), Err(ApiError::InsufficientFunds { available: 501 }));
+        reserve_payout(RoyaltyAccount::Artist(1), 400, 11).unwrap();
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(1)), 101);
+        assert_eq!(reconcile().pending_payouts, 400);
+        // A failed transfer returns the reservation
+        release_payout(RoyaltyAccount::Artist(1), 400, 12);
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(1)), 501);
+
+        reserve_payout(RoyaltyAccount::Artist(2), 500, 13).unwrap();
+        settle_payout(500, 10, 8, 14);
+        assert_eq!(account_balance(&RoyaltyAccount::Artist(2)), 0);
+        assert_eq!(reconcile(), RoyaltyReconciliation {
+            total_received: 1_001,
+            total_credited: 1_001,
+            total_paid_out: 490,
+            total_fees: 10,
+            treasury_balance: 501,
+            artist_balances: 501,
+            pending_payouts: 0,
+            platform_balance: 0,
+            escrow_balance: 0,
+            balanced: true,
+        });
+        assert!(JOURNAL_ENTRIES.with(|e| e.borrow().iter().all(|e| e.debit == 0 || e.credit == 0)));
+    }
+
+    #[test]
+    fn migrations_cover_every_schema_version() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.from_version, i as u32 + 1);
+        }
+        assert_eq!(MIGRATIONS.len() as u32 + 1, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn track_visibility_follows_roles_and_invites() {
+        restore_state(sample_state());
+        index_artist(principal(4), 4);
+        let mut track = sample_track();
+        let (owner, invitee, stranger) = (principal(1), principal(4), principal(7));
+
+        assert!(can_view_track(owner, &track));
+        assert!(can_view_track(invitee, &track));
+        assert!(!can_view_track(stranger, &track));
+
+        track.visibility = TrackVisibility::Private;
+        assert!(can_view_track(owner, &track));
+        assert!(!can_view_track(invitee, &track));
+
+        track.visibility = TrackVisibility::Public;
+        assert!(can_view_track(stranger, &track));
+
+        // Revenue insights and analytics only cover tracks the viewer can see
+        TRACKS.with(|t| t.borrow_mut().push(Track { id: 5, ..track }));
+        let insights = revenue_insights(stranger, 0);
+        assert_eq!((insights.total_platform_revenue, insights.top_earning_tracks), (500, vec![(5, 500)]));
+        assert_eq!(revenue_insights(owner, 0).total_platform_revenue, 1_000);
+        let plays = sample_track().play_count;
+        let metrics = user_engagement_metrics(stranger, 1).unwrap();
+        assert_eq!((metrics.total_tracks_created, metrics.total_plays_received, metrics.total_revenue_earned), (1, plays, 500));
+        assert_eq!(user_engagement_metrics(owner, 1).unwrap().total_plays_received, 2 * plays);
+        let analytics = platform_analytics(stranger);
+        assert_eq!((analytics.total_tracks, analytics.total_plays, analytics.total_revenue), (1, plays, 500));
+        assert_eq!(platform_analytics(owner).total_revenue, 1_000);
+    }
+
+    #[test]
+    fn track_writes_follow_roles() {
+        restore_state(sample_state());
+        index_artist(principal(5), 2);
+        let (owner, collaborator, stranger) = (principal(1), principal(5), principal(7));
+        let retitle = |principal| with_track_for(principal, 3, TrackRole::Collaborator, |t| t.title = "Retitled".to_string());
+        let hide = |principal| with_track_for(principal, 3, TrackRole::Owner, |t| t.visibility = TrackVisibility::Private);
+
+        // Collaborators edit metadata; visibility, splits and the artist itself stay with owners
+        assert!(retitle(collaborator).is_ok());
+        assert!(matches!(hide(collaborator), Err(ApiError::Unauthorized { .. })));
+        assert!(matches!(retitle(stranger), Err(ApiError::Unauthorized { .. })));
+        assert!(hide(owner).is_ok());
+        assert_eq!(find_track(3).map(|t| (t.title, t.visibility)), Some(("Retitled".to_string(), TrackVisibility::Private)));
+        assert!(matches!(with_track_for(owner, 9, TrackRole::Viewer, |_| ()), Err(ApiError::NotFound { .. })));
+
+        let splits = || vec![Split { id: 1, pct: 50 }, Split { id: 2, pct: 50 }];
+        assert!(matches!(propose_splits_as(collaborator, 3, splits(), 10), Err(ApiError::Unauthorized { .. })));
+        assert!(matches!(propose_splits_as(stranger, 3, splits(), 10), Err(ApiError::Unauthorized { .. })));
+        assert!(matches!(propose_splits_as(owner, 3, vec![Split { id: 1, pct: 60 }], 10), Err(ApiError::InvalidInput { .. })));
+        assert_eq!(propose_splits_as(owner, 3, splits(), 10).unwrap().proposed_by, owner);
+
+        assert!(require_artist_owner(owner, 1).is_ok());
+        assert!(matches!(require_artist_owner(collaborator, 1), Err(ApiError::Unauthorized { .. })));
+        assert!(matches!(require_artist_owner(stranger, 1), Err(ApiError::Unauthorized { .. })));
+        assert!(require_artist_owner_or_admin(owner, 2).is_ok()); // principal 1 is an admin
+
+        // A suspended owner keeps the role but gets a Suspended error instead
+        SUSPENSIONS.with(|s| s.borrow_mut().push(suspension(1, SuspensionTargetType::User, &owner.to_text(), None)));
+        assert!(matches!(hide(owner), Err(ApiError::Suspended { .. })));
+    }
+
+    #[test]
+    fn identity_index_follows_artist_ownership() {
+        restore_state(sample_state());
+        assert_eq!(artist_ids_of(principal(1)), vec![1]);
+        assert!(artist_ids_of(principal(2)).is_empty());
+
+        // A principal can own several artists and holds the strongest role among them
+        ARTISTS.with(|a| a.borrow_mut().push(Artist { id: 2, user_principal: principal(2), ..sample_state().artists[0].clone() }));
+        index_artist(principal(2), 2);
+        index_artist(principal(2), 4);
+        index_artist(principal(2), 4);
+        assert_eq!(artist_ids_of(principal(2)), vec![2, 4]);
+        assert_eq!(track_role_of(principal(2), &sample_track()), Some(TrackRole::Collaborator));
+        assert!(owns_artist(principal(2), 4) && !owns_artist(principal(1), 4));
+
+        unindex_artist(principal(2), 4);
+        assert_eq!(artist_ids_of(principal(2)), vec![2]);
+        unindex_artist(principal(2), 2);
+        assert_eq!(track_role_of(principal(2), &sample_track()), None);
+        assert!(ARTISTS_BY_PRINCIPAL.with(|i| !i.borrow().contains_key(&principal(2))));
+
+        // The index is derived from the artists, so it survives upgrades
+        rebuild_identity_index();
+        assert_eq!(artist_ids_of(principal(2)), vec![2]);
+    }
+
+    #[test]
+    fn api_errors_round_trip_through_candid() {
+        let errors = vec![
+            ApiError::not_found("track"),
+            ApiError::unauthorized("admin only"),
+            ApiError::Suspended { reason: "spam".to_string() },
+            ApiError::RateLimited { retry_after_secs: 3 },
+            ApiError::invalid_input("title", "must not be empty"),
+            ApiError::conflict("already exists"),
+            ApiError::Ledger { reason: "rejected".to_string() },
+            ApiError::InsufficientFunds { available: 5 },
+        ];
+        for error in errors {
+            let result: Result<(), ApiError> = Err(error.clone());
+            let bytes = candid::encode_one(&result).unwrap();
+            assert_eq!(candid::decode_one::<Result<(), ApiError>>(&bytes).unwrap(), Err(error));
+        }
+        assert_eq!(ApiError::invalid_input("title", "must not be empty"), ApiError::InvalidInput { field: "title".to_string(), reason: "must not be empty".to_string() });
+    }
+
+    #[test]
+    fn purchases_unlock_priced_tracks() {
+        restore_state(sample_state());
+        let mut track = sample_track();
+        let (owner, collaborator, buyer, stranger) = (principal(1), principal(2), principal(2), principal(7));
+        index_artist(owner, 1);
+        index_artist(collaborator, 2);
+        ENTITLEMENTS.with(|e| e.borrow_mut().retain(|e| e.owner != collaborator));
+        for access in [TrackAccess::Stream, TrackAccess::Download] {
+            assert!(has_track_access(owner, &track, access));
+            assert!(has_track_access(collaborator, &track, access));
+            assert!(!has_track_access(stranger, &track, access));
+        }
+
+        // Streaming can stay free while downloads are sold
+        track.pricing.paid_streaming = false;
+        assert!(has_track_access(stranger, &track, TrackAccess::Stream));
+        assert!(matches!(require_track_access(stranger, &track, TrackAccess::Download), Err(ApiError::Unauthorized { .. })));
+        track.roles.retain(|(id, _)| *id != 2);
+        ENTITLEMENTS.with(|e| e.borrow_mut().push(Entitlement { track_id: 3, owner: buyer, amount_paid: 100, block_index: 1, purchased_at: 5 }));
+        assert!(has_track_access(buyer, &track, TrackAccess::Download));
+
+        // Masters of priced tracks are sold with the download, even when streaming is free
+        track.pricing = TrackPricing { mode: PricingMode::PayPerDownload { price: 100 }, paid_streaming: false };
+        assert_eq!(master_access(&track), TrackAccess::Download);
+        assert!(!has_track_access(stranger, &track, master_access(&track)));
+        assert!(has_track_access(buyer, &track, master_access(&track)));
+
+        // A second purchase can't start while the first is awaiting its transfer
+        let pending = PendingPurchase::start(buyer, 3).unwrap();
+        assert!(matches!(PendingPurchase::start(buyer, 3), Err(ApiError::Conflict { .. })));
+        assert!(PendingPurchase::start(stranger, 3).is_ok());
+        drop(pending);
+        assert!(PendingPurchase::start(buyer, 3).is_ok());
+
+        // Free tracks fall back to the downloadable flag, masters included
+        track.pricing = TrackPricing::default();
+        assert_eq!(master_access(&track), TrackAccess::Download);
+        assert!(!has_track_access(stranger, &track, TrackAccess::Download));
+        track.downloadable = true;
+        assert_eq!(master_access(&track), TrackAccess::Stream);
+        assert!(has_track_access(stranger, &track, TrackAccess::Download));
+    }
+
+    #[test]
+    fn purchase_amounts_follow_the_pricing_mode() {
+        let pricing = |mode| TrackPricing { mode, paid_streaming: false };
+        assert!(matches!(check_purchase_amount(&pricing(PricingMode::Free), 10), Err(ApiError::Conflict { .. })));
+        let fixed = pricing(PricingMode::PayPerDownload { price: 250 });
+        assert!(check_purchase_amount(&fixed, 250).is_ok());
+        for amount in [249, 251] {
+            assert!(matches!(check_purchase_amount(&fixed, amount), Err(ApiError::InvalidInput { .. })));
+        }
+        let pwyw = pricing(PricingMode::PayWhatYouWant { minimum: 100 });
+        assert!(check_purchase_amount(&pwyw, 100).is_ok());
+        assert!(check_purchase_amount(&pwyw, 1_000).is_ok());
+        assert!(check_purchase_amount(&pwyw, 99).is_err());
+        assert!(check_purchase_amount(&pricing(PricingMode::PayWhatYouWant { minimum: 0 }), 0).is_err());
+    }
+
+    /// Fails when `icp-music-platform-backend.did` and the Rust interface disagree in either
+    /// direction. Run with `UPDATE_DID=1` to rewrite the file from the Rust code.
+    #[test]
+    fn candid_interface_matches_checked_in_did() {
+        use candid_parser::utils::{service_compatible, CandidSource};
+
+        let generated = __export_service();
+        let did_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("icp-music-platform-backend.did");
+        if std::env::var_os("UPDATE_DID").is_some() {
+            std::fs::write(&did_path, &generated).unwrap();
+        }
+        service_compatible(CandidSource::Text(&generated), CandidSource::File(&did_path))
+            .expect("the canister no longer satisfies icp-music-platform-backend.did");
+        service_compatible(CandidSource::File(&did_path), CandidSource::Text(&generated))
+            .expect("icp-music-platform-backend.did is out of date; rerun with UPDATE_DID=1");
+    }
+}
@@ -0,0 +1,243 @@
+//! Runs the backend against an ICRC-1/ICRC-2 ledger in PocketIC: a listener pays for a track
+//! through `icrc2_transfer_from` (`pull_payment`) and the artist withdraws it through
+//! `icrc1_transfer` (`send_payout`).
+//!
+//! Needs the PocketIC server (`POCKET_IC_BIN`), the ledger suite's `ic-icrc1-ledger` wasm
+//! (`ICRC1_LEDGER_WASM`) and the backend built for `wasm32-unknown-unknown` in release mode
+//! (`BACKEND_WASM` overrides the default path). `scripts/integration-tests.sh` provides all three.
+
+use candid::{decode_one, encode_one, CandidType, Deserialize, Nat, Principal};
+use pocket_ic::{PocketIc, WasmResult};
+
+const FEE: u64 = 10_000;
+const PRICE: u64 = 500_000;
+const ROYALTIES_SUBACCOUNT: [u8; 32] = *b"royalties\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+struct Account {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+// Only the fields the ledger requires; its optional init fields decode as null
+#[derive(CandidType)]
+enum LedgerArgument {
+    Init(LedgerInitArgs),
+}
+
+#[derive(CandidType)]
+struct LedgerInitArgs {
+    minting_account: Account,
+    initial_balances: Vec<(Account, Nat)>,
+    transfer_fee: Nat,
+    token_name: String,
+    token_symbol: String,
+    metadata: Vec<(String, MetadataValue)>,
+    archive_options: ArchiveOptions,
+    feature_flags: Option<FeatureFlags>,
+}
+
+// The ledger takes Nat, Int, Text and Blob values; the test sets none
+#[derive(CandidType)]
+#[allow(dead_code)]
+enum MetadataValue {
+    Text(String),
+}
+
+#[derive(CandidType)]
+struct ArchiveOptions {
+    trigger_threshold: u64,
+    num_blocks_to_archive: u64,
+    controller_id: Principal,
+}
+
+#[derive(CandidType)]
+struct FeatureFlags {
+    icrc2: bool,
+}
+
+#[derive(CandidType)]
+struct ApproveArgs {
+    spender: Account,
+    amount: Nat,
+}
+
+#[derive(CandidType)]
+struct InitArgs {
+    admins: Vec<Principal>,
+}
+
+#[derive(CandidType)]
+struct Split {
+    id: u64,
+    pct: u8,
+}
+
+// Artists and tracks are only needed for their ids
+#[derive(CandidType, Deserialize)]
+struct Created {
+    id: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+#[allow(dead_code)]
+enum ApiError {
+    InvalidInput { field: String, reason: String },
+    NotFound { resource: String },
+    Suspended { reason: String },
+    Unauthorized { reason: String },
+    RateLimited { retry_after_secs: u64 },
+    Ledger { reason: String },
+    InsufficientFunds { available: u64 },
+    Conflict { reason: String },
+}
+
+fn user(n: u8) -> Principal {
+    Principal::from_slice(&[n; 29])
+}
+
+fn account(owner: Principal) -> Account {
+    Account { owner, subaccount: None }
+}
+
+fn wasm(var: &str, default: Option<&str>) -> Vec<u8> {
+    let path = std::env::var(var).ok().or(default.map(str::to_string)).unwrap_or_else(|| panic!("set {} to the wasm to install", var));
+    std::fs::read(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path, e))
+}
+
+fn update<T: CandidType + for<'de> Deserialize<'de>>(pic: &PocketIc, canister: Principal, sender: Principal, method: &str, args: Vec<u8>) -> T {
+    match pic.update_call(canister, sender, method, args) {
+        Ok(WasmResult::Reply(bytes)) => decode_one(&bytes).unwrap_or_else(|e| panic!("{} returned an unexpected type: {}", method, e)),
+        Ok(WasmResult::Reject(message)) => panic!("{} rejected: {}", method, message),
+        Err(err) => panic!("{} failed: {}", method, err),
+    }
+}
+
+fn query<T: CandidType + for<'de> Deserialize<'de>>(pic: &PocketIc, canister: Principal, method: &str, args: Vec<u8>) -> T {
+    match pic.query_call(canister, Principal::anonymous(), method, args) {
+        Ok(WasmResult::Reply(bytes)) => decode_one(&bytes).unwrap_or_else(|e| panic!("{} returned an unexpected type: {}", method, e)),
+        Ok(WasmResult::Reject(message)) => panic!("{} rejected: {}", method, message),
+        Err(err) => panic!("{} failed: {}", method, err),
+    }
+}
+
+fn balance_of(pic: &PocketIc, ledger: Principal, account: Account) -> u64 {
+    let balance: Nat = query(pic, ledger, "icrc1_balance_of", encode_one(account).unwrap());
+    u64::try_from(balance.0).unwrap()
+}
+
+struct Setup {
+    pic: PocketIc,
+    ledger: Principal,
+    backend: Principal,
+    artist_id: u64,
+    track_id: u64,
+}
+
+fn setup(admin: Principal, artist: Principal, listener: Principal) -> Setup {
+    let pic = PocketIc::new();
+
+    let ledger = pic.create_canister();
+    pic.add_cycles(ledger, 2_000_000_000_000);
+    let ledger_init = LedgerArgument::Init(LedgerInitArgs {
+        minting_account: account(user(99)),
+        initial_balances: vec![(account(listener), Nat::from(10 * PRICE))],
+        transfer_fee: Nat::from(FEE),
+        token_name: "Test Token".to_string(),
+        token_symbol: "TST".to_string(),
+        metadata: vec![],
+        archive_options: ArchiveOptions { trigger_threshold: 2_000, num_blocks_to_archive: 1_000, controller_id: user(99) },
+        feature_flags: Some(FeatureFlags { icrc2: true }),
+    });
+    pic.install_canister(ledger, wasm("ICRC1_LEDGER_WASM", None), encode_one(ledger_init).unwrap(), None);
+
+    let backend = pic.create_canister();
+    pic.add_cycles(backend, 2_000_000_000_000);
+    let backend_wasm = wasm("BACKEND_WASM", Some("../../target/wasm32-unknown-unknown/release/icp_music_platform_backend.wasm"));
+    pic.install_canister(backend, backend_wasm, encode_one(Some(InitArgs { admins: vec![admin] })).unwrap(), None);
+
+    let configured: Result<(), ApiError> = update(&pic, backend, admin, "set_ledger_canister", encode_one(ledger).unwrap());
+    configured.unwrap();
+    let registered: Result<Created, ApiError> = update(
+        &pic,
+        backend,
+        artist,
+        "register_artist",
+        candid::encode_args(("Artist".to_string(), "bio".to_string(), None::<String>, None::<String>, None::<Vec<String>>)).unwrap(),
+    );
+    let artist_id = registered.unwrap().id;
+    let created: Result<Created, ApiError> = update(
+        &pic,
+        backend,
+        artist,
+        "create_track",
+        candid::encode_args(("Track".to_string(), "A track".to_string(), vec![artist_id])).unwrap(),
+    );
+    let track_id = created.unwrap().id;
+    // A sole contributor's proposal is accepted straight away
+    let proposed: Result<candid::Reserved, ApiError> = update(
+        &pic,
+        backend,
+        artist,
+        "propose_track_splits",
+        candid::encode_args((track_id, vec![Split { id: artist_id, pct: 100 }])).unwrap(),
+    );
+    proposed.unwrap();
+
+    Setup { pic, ledger, backend, artist_id, track_id }
+}
+
+#[test]
+fn payments_are_pulled_and_paid_out_through_the_ledger() {
+    let (admin, artist, listener) = (user(1), user(2), user(3));
+    let Setup { pic, ledger, backend, artist_id, track_id } = setup(admin, artist, listener);
+    let royalties = Account { owner: backend, subaccount: Some(ROYALTIES_SUBACCOUNT.to_vec()) };
+    let pay = |pic: &PocketIc| -> Result<u64, ApiError> {
+        update(pic, backend, listener, "distribute_payment", candid::encode_args((track_id, PRICE)).unwrap())
+    };
+
+    // Without an approval the pull is refused and nothing moves
+    match pay(&pic) {
+        Err(ApiError::Ledger { reason }) => assert!(reason.contains("allowance"), "unexpected reason: {}", reason),
+        other => panic!("expected an allowance error, got {:?}", other),
+    }
+    // An approval that doesn't cover the fee is refused the same way
+    let approved: Result<Nat, candid::Reserved> = update(
+        &pic,
+        ledger,
+        listener,
+        "icrc2_approve",
+        encode_one(ApproveArgs { spender: account(backend), amount: Nat::from(PRICE) }).unwrap(),
+    );
+    approved.unwrap();
+    assert!(matches!(pay(&pic), Err(ApiError::Ledger { .. })));
+    assert_eq!(balance_of(&pic, ledger, account(listener)), 10 * PRICE - FEE);
+    assert_eq!(balance_of(&pic, ledger, royalties.clone()), 0);
+
+    // pull_payment: icrc2_transfer_from moves the price into the royalty subaccount
+    let approved: Result<Nat, candid::Reserved> = update(
+        &pic,
+        ledger,
+        listener,
+        "icrc2_approve",
+        encode_one(ApproveArgs { spender: account(backend), amount: Nat::from(PRICE + FEE) }).unwrap(),
+    );
+    approved.unwrap();
+    pay(&pic).unwrap();
+    assert_eq!(balance_of(&pic, ledger, account(listener)), 10 * PRICE - 3 * FEE - PRICE);
+    assert_eq!(balance_of(&pic, ledger, royalties.clone()), PRICE);
+    let balance: Result<u64, ApiError> = query(&pic, backend, "get_royalty_balance", encode_one(artist_id).unwrap());
+    assert_eq!(balance.unwrap(), PRICE);
+
+    // send_payout: icrc1_transfer pays the artist the amount minus the fee
+    let withdrawn: Result<u64, ApiError> = update(&pic, backend, artist, "withdraw_royalties", candid::encode_args((artist_id, PRICE)).unwrap());
+    withdrawn.unwrap();
+    assert_eq!(balance_of(&pic, ledger, account(artist)), PRICE - FEE);
+    assert_eq!(balance_of(&pic, ledger, royalties), 0);
+    let balance: Result<u64, ApiError> = query(&pic, backend, "get_royalty_balance", encode_one(artist_id).unwrap());
+    assert_eq!(balance.unwrap(), 0);
+
+    // A withdrawal the balance can't cover never reaches the ledger
+    let withdrawn: Result<u64, ApiError> = update(&pic, backend, artist, "withdraw_royalties", candid::encode_args((artist_id, PRICE)).unwrap());
+    assert!(withdrawn.is_err());
+}